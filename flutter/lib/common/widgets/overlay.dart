@@ -366,6 +366,14 @@ class QualityMonitor extends StatelessWidget {
                           "${qualityMonitorModel.data.targetBitrate ?? '-'}kb"),
                       _row(
                           "Codec", qualityMonitorModel.data.codecFormat ?? '-'),
+                      _row("Send Rate",
+                          "${qualityMonitorModel.data.sendRate ?? '-'}kb"),
+                      _row("Backlog",
+                          "${qualityMonitorModel.data.backlog ?? '-'}ms"),
+                      _row("Jitter",
+                          "${qualityMonitorModel.data.jitter ?? '-'}ms"),
+                      _row("Congestion",
+                          qualityMonitorModel.data.congestion ?? '-'),
                     ],
                   ),
                 )
@@ -708,3 +708,76 @@ void setPasswordDialog() async {
     );
   });
 }
+
+void setup2FaDialog() async {
+  if ((await bind.mainGetOption(key: '2fa')).isNotEmpty) {
+    gFFI.dialogManager.show((setState, close) {
+      submit() {
+        bind.mainSetOption(key: '2fa', value: '');
+        close();
+      }
+
+      return CustomAlertDialog(
+        title: Text(translate('2FA')),
+        content: Text(translate('Disable 2FA?')),
+        actions: [
+          dialogButton("Cancel", onPressed: close, isOutline: true),
+          dialogButton("OK", onPressed: submit),
+        ],
+        onSubmit: submit,
+        onCancel: close,
+      );
+    });
+    return;
+  }
+  final uri = await bind.mainGenerate2Fa();
+  if (uri.isEmpty) return;
+  final secret = Uri.parse(uri).queryParameters['secret'] ?? '';
+  final controller = TextEditingController();
+  var errMsg = '';
+  gFFI.dialogManager.show((setState, close) {
+    submit() async {
+      final code = controller.text.trim();
+      if (code.isEmpty) return;
+      if (await bind.mainVerify2Fa(code: code)) {
+        close();
+      } else {
+        setState(() => errMsg = translate('Wrong 2FA Code'));
+      }
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Enable 2FA')),
+      content: ConstrainedBox(
+        constraints: const BoxConstraints(minWidth: 500),
+        child: Column(
+          crossAxisAlignment: CrossAxisAlignment.start,
+          children: [
+            Text(translate('Add this key to your authenticator app')),
+            const SizedBox(height: 8.0),
+            SelectableText(secret,
+                style: const TextStyle(fontWeight: FontWeight.bold)),
+            const SizedBox(height: 8.0),
+            SelectableText(uri, style: const TextStyle(fontSize: 12)),
+            const SizedBox(height: 8.0),
+            TextField(
+              controller: controller,
+              autofocus: true,
+              decoration: InputDecoration(
+                  labelText: translate('Code of your authenticator app'),
+                  border: const OutlineInputBorder(),
+                  errorText: errMsg.isNotEmpty ? errMsg : null),
+              onChanged: (_) => setState(() => errMsg = ''),
+            ),
+          ],
+        ),
+      ),
+      actions: [
+        dialogButton("Cancel", onPressed: close, isOutline: true),
+        dialogButton("OK", onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
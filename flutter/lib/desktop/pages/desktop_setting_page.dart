@@ -278,6 +278,8 @@ class _GeneralState extends State<_General> {
       _OptionCheckBox(context, 'Confirm before closing multiple tabs',
           'enable-confirm-closing-tabs'),
       _OptionCheckBox(context, 'Adaptive Bitrate', 'enable-abr'),
+      _OptionCheckBox(context, 'Separate encoder for each connection',
+          'allow-separate-encoders'),
       if (Platform.isLinux)
         Tooltip(
           message: translate('software_render_tip'),
@@ -697,6 +699,7 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
             if (usePassword)
               hide_cm(!locked).marginOnly(left: _kContentHSubMargin - 6),
             if (usePassword) radios[2],
+            if (usePassword) _SubButton('2FA', setup2FaDialog, !locked),
           ]);
         })));
   }
@@ -396,6 +396,12 @@ class _RemotePageState extends State<RemotePage>
             QualityMonitor(_ffi.qualityMonitorModel), null, null),
       ),
     );
+    paints.add(Positioned(
+        left: 10,
+        right: 10,
+        bottom: 10,
+        child: ChangeNotifierProvider.value(
+            value: _ffi.imageModel, child: const DisplayTiles())));
     return Stack(
       children: paints,
     );
@@ -405,6 +411,28 @@ class _RemotePageState extends State<RemotePage>
   bool get wantKeepAlive => true;
 }
 
+/// The displays streamed besides the current one, tiled in a row.
+class DisplayTiles extends StatelessWidget {
+  const DisplayTiles({Key? key}) : super(key: key);
+
+  @override
+  Widget build(BuildContext context) {
+    final m = Provider.of<ImageModel>(context);
+    if (!m.captureAllDisplays || m.displayImages.isEmpty) return Offstage();
+    final displays = m.displayImages.keys.toList()..sort();
+    return SizedBox(
+        height: 180,
+        child: Row(
+            mainAxisAlignment: MainAxisAlignment.center,
+            children: displays
+                .map((d) => Padding(
+                    padding: const EdgeInsets.symmetric(horizontal: 4),
+                    child: RawImage(
+                        image: m.displayImages[d], fit: BoxFit.contain)))
+                .toList()));
+  }
+}
+
 class ImagePaint extends StatefulWidget {
   final String id;
   final RxBool zoomCursor;
@@ -1,6 +1,7 @@
 // original cm window in Sciter version.
 
 import 'dart:async';
+import 'dart:convert';
 import 'dart:io';
 
 import 'package:flutter/material.dart';
@@ -247,6 +248,11 @@ Widget buildConnectionCard(Client client) {
               client.type_() != ClientType.remote || client.disconnected
                   ? Offstage()
                   : _PrivilegeBoard(client: client),
+              client.type_() != ClientType.remote ||
+                      client.disconnected ||
+                      !client.authorized
+                  ? Offstage()
+                  : _ShareBoard(client: client),
               Expanded(
                   child: Align(
                 alignment: Alignment.bottomCenter,
@@ -497,6 +503,69 @@ class _PrivilegeBoardState extends State<_PrivilegeBoard> {
   }
 }
 
+class _ShareBoard extends StatefulWidget {
+  final Client client;
+
+  const _ShareBoard({Key? key, required this.client}) : super(key: key);
+
+  @override
+  State<StatefulWidget> createState() => _ShareBoardState();
+}
+
+/// Share a single window instead of the screen.
+class _ShareBoardState extends State<_ShareBoard> {
+  late final client = widget.client;
+  List<dynamic> windows = [];
+
+  @override
+  void initState() {
+    super.initState();
+    try {
+      windows = jsonDecode(bind.cmGetWindows());
+    } catch (e) {
+      debugPrint('Failed to get the windows: $e');
+    }
+  }
+
+  @override
+  Widget build(BuildContext context) {
+    if (windows.isEmpty) return Offstage();
+    final items = [
+      DropdownMenuItem<int>(value: 0, child: Text(translate('Screen'))),
+      ...windows.map((w) => DropdownMenuItem<int>(
+          value: w['id'] as int,
+          child: Text(w['title'] as String, overflow: TextOverflow.ellipsis)))
+    ];
+    return Container(
+      margin: EdgeInsets.only(bottom: 8.0),
+      child: Row(
+        children: [
+          Text(translate('Share'), style: TextStyle(fontSize: 16))
+              .marginOnly(left: 4.0, right: 8.0),
+          Expanded(
+            child: DropdownButton<int>(
+              isExpanded: true,
+              value: windows.any((w) => w['id'] == client.shareWindow)
+                  ? client.shareWindow
+                  : 0,
+              items: items,
+              onChanged: (value) {
+                if (value == null) return;
+                if (value == 0) {
+                  bind.cmShareDisplay(connId: client.id);
+                } else {
+                  bind.cmShareWindow(connId: client.id, window: value);
+                }
+                setState(() => client.shareWindow = value);
+              },
+            ),
+          ),
+        ],
+      ),
+    );
+  }
+}
+
 const double bigMargin = 15;
 
 class _CmControlPanel extends StatelessWidget {
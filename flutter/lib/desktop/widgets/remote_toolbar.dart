@@ -973,10 +973,15 @@ class _DisplayMenuState extends State<_DisplayMenu> {
           showRemoteCursor(),
           zoomCursor(),
           showQualityMonitor(),
+          fullChroma(),
+          showAllDisplays(),
           mute(),
+          forwardMicrophone(),
+          audioSource(),
           fileCopyAndPaste(),
           disableClipboard(),
           lockAfterSessionEnd(),
+          keyLogin(),
           privacyMode(),
           swapKey(),
         ]);
@@ -1374,9 +1379,11 @@ class _DisplayMenuState extends State<_DisplayMenu> {
       } catch (e) {
         debugPrint("Show Codec Preference err=$e");
       }
-      final visible = bind.mainHasHwcodec() &&
+      final hwcodec = bind.mainHasHwcodec() &&
           codecs.length == 2 &&
           (codecs[0] || codecs[1]);
+      final av1 = bind.sessionSupportedAv1(id: widget.id);
+      final visible = hwcodec || av1;
       if (!visible) return Offstage();
       final groupValue = data['codecPreference'] as String;
       onChanged(String? value) async {
@@ -1404,20 +1411,30 @@ class _DisplayMenuState extends State<_DisplayMenu> {
               onChanged: onChanged,
               ffi: widget.ffi,
             ),
-            _RadioMenuButton<String>(
-              child: Text(translate('H264')),
-              value: 'h264',
-              groupValue: groupValue,
-              onChanged: onChanged,
-              ffi: widget.ffi,
-            ),
-            _RadioMenuButton<String>(
-              child: Text(translate('H265')),
-              value: 'h265',
-              groupValue: groupValue,
-              onChanged: onChanged,
-              ffi: widget.ffi,
-            ),
+            if (hwcodec)
+              _RadioMenuButton<String>(
+                child: Text(translate('H264')),
+                value: 'h264',
+                groupValue: groupValue,
+                onChanged: onChanged,
+                ffi: widget.ffi,
+              ),
+            if (hwcodec)
+              _RadioMenuButton<String>(
+                child: Text(translate('H265')),
+                value: 'h265',
+                groupValue: groupValue,
+                onChanged: onChanged,
+                ffi: widget.ffi,
+              ),
+            if (av1)
+              _RadioMenuButton<String>(
+                child: Text(translate('AV1')),
+                value: 'av1',
+                groupValue: groupValue,
+                onChanged: onChanged,
+                ffi: widget.ffi,
+              ),
           ]);
     });
   }
@@ -1534,6 +1551,33 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         child: Text(translate('Show quality monitor')));
   }
 
+  fullChroma() {
+    if (!bind.sessionSupportedI444(id: widget.id)) return Offstage();
+    final option = 'i444';
+    final value = bind.sessionGetToggleOptionSync(id: widget.id, arg: option);
+    return _CheckboxMenuButton(
+        value: value,
+        onChanged: (value) async {
+          if (value == null) return;
+          await bind.sessionToggleOption(id: widget.id, value: option);
+        },
+        ffi: widget.ffi,
+        child: Text(translate('Full chroma')));
+  }
+
+  showAllDisplays() {
+    if (pi.displays.length < 2) return Offstage();
+    final imageModel = widget.ffi.imageModel;
+    return _CheckboxMenuButton(
+        value: imageModel.captureAllDisplays,
+        onChanged: (value) {
+          if (value == null) return;
+          imageModel.setCaptureAllDisplays(value);
+        },
+        ffi: widget.ffi,
+        child: Text(translate('Show all displays')));
+  }
+
   mute() {
     final visible = perms['audio'] != false;
     if (!visible) return Offstage();
@@ -1549,6 +1593,31 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         child: Text(translate('Mute')));
   }
 
+  audioSource() {
+    final visible = perms['audio'] != false && pi.features.virtualMicrophone;
+    if (!visible) return Offstage();
+    return _MenuItemButton(
+        child: Text(translate('Audio source')),
+        ffi: widget.ffi,
+        onPressed: () => bind.sessionGetAudioSources(id: widget.id));
+  }
+
+  forwardMicrophone() {
+    final visible =
+        perms['audio'] != false && pi.platform == kPeerPlatformLinux;
+    if (!visible) return Offstage();
+    final option = 'forward-microphone';
+    final value = bind.sessionGetToggleOptionSync(id: widget.id, arg: option);
+    return _CheckboxMenuButton(
+        value: value,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(id: widget.id, value: option);
+        },
+        ffi: widget.ffi,
+        child: Text(translate('Forward microphone')));
+  }
+
   fileCopyAndPaste() {
     final visible = Platform.isWindows &&
         pi.platform == kPeerPlatformWindows &&
@@ -1599,6 +1668,20 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         child: Text(translate('Lock after session end')));
   }
 
+  // signed at the next login
+  keyLogin() {
+    final option = 'key-login';
+    final value = bind.sessionGetToggleOptionSync(id: widget.id, arg: option);
+    return _CheckboxMenuButton(
+        value: value,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(id: widget.id, value: option);
+        },
+        ffi: widget.ffi,
+        child: Text(translate('Log in with my key')));
+  }
+
   privacyMode() {
     bool visible = perms['keyboard'] != false && pi.features.privacyMode;
     if (!visible) return Offstage();
@@ -983,6 +983,7 @@ void showOptions(
       debugPrint("Show Codec Preference err=$e");
     }
   }
+  final av1 = bind.sessionSupportedAv1(id: id);
 
   dialogManager.show((setState, close) {
     final more = <Widget>[];
@@ -1042,17 +1043,20 @@ void showOptions(
       const Divider(color: MyTheme.border)
     ];
 
-    if (hasHwcodec && codecs.length == 2 && (codecs[0] || codecs[1])) {
+    if (hasHwcodec && codecs.length == 2 && (codecs[0] || codecs[1]) || av1) {
       radios.addAll([
         getRadio(translate('Auto'), 'auto', codec, setCodec),
         getRadio('VP9', 'vp9', codec, setCodec),
       ]);
-      if (codecs[0]) {
+      if (codecs.length == 2 && codecs[0]) {
         radios.add(getRadio('H264', 'h264', codec, setCodec));
       }
-      if (codecs[1]) {
+      if (codecs.length == 2 && codecs[1]) {
         radios.add(getRadio('H265', 'h265', codec, setCodec));
       }
+      if (av1) {
+        radios.add(getRadio('AV1', 'av1', codec, setCodec));
+      }
       radios.add(const Divider(color: MyTheme.border));
     }
 
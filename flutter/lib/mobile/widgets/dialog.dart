@@ -173,6 +173,43 @@ void setTemporaryPasswordLengthDialog(
   }, backDismiss: true, clickMaskDismiss: true);
 }
 
+void showAudioSources(
+    String id, Map<String, dynamic> evt, OverlayDialogManager dialogManager) {
+  List<dynamic> sources = [];
+  try {
+    sources = json.decode(evt['sources']);
+  } catch (e) {
+    debugPrint('Failed to decode audio sources: $e');
+  }
+  String current = evt['current'].toString();
+  dialogManager.show((setState, close) {
+    select(String? newValue) {
+      if (newValue == null || current == newValue) return;
+      setState(() {
+        current = newValue;
+      });
+      bind.sessionSelectAudioSource(id: id, name: newValue);
+      Future.delayed(Duration(milliseconds: 200), close);
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate("Audio source")),
+      content: Column(mainAxisSize: MainAxisSize.min, children: [
+        getRadio('Default', '', current, select),
+        ...sources.map((e) => getRadio(
+            e['is_application'] == true
+                ? '${e['description']} (${translate('Application')})'
+                : e['description'].toString(),
+            e['name'].toString(),
+            current,
+            select)),
+      ]),
+      actions: [],
+      contentPadding: 14,
+    );
+  }, backDismiss: true, clickMaskDismiss: true);
+}
+
 void enterPasswordDialog(String id, OverlayDialogManager dialogManager) async {
   final controller = TextEditingController();
   var remember = await bind.sessionGetRemember(id: id) ?? false;
@@ -236,6 +273,60 @@ void enterPasswordDialog(String id, OverlayDialogManager dialogManager) async {
   });
 }
 
+void enter2FaDialog(
+    String id, OverlayDialogManager dialogManager, String title) async {
+  final controller = TextEditingController();
+  dialogManager.dismissAll();
+  dialogManager.show((setState, close) {
+    cancel() {
+      close();
+      closeConnection();
+    }
+
+    submit() {
+      var code = controller.text.trim();
+      if (code == '') return;
+      bind.sessionSend2Fa(id: id, code: code);
+      close();
+      dialogManager.showLoading(translate('Logging in...'),
+          onCancel: closeConnection);
+    }
+
+    return CustomAlertDialog(
+      title: Row(
+        mainAxisAlignment: MainAxisAlignment.center,
+        children: [
+          Icon(Icons.security_rounded, color: MyTheme.accent),
+          Text(translate(title)).paddingOnly(left: 10),
+        ],
+      ),
+      content: TextField(
+        controller: controller,
+        autofocus: true,
+        keyboardType: TextInputType.number,
+        decoration: InputDecoration(
+          labelText: translate('Code of your authenticator app'),
+        ),
+      ),
+      actions: [
+        dialogButton(
+          'Cancel',
+          icon: Icon(Icons.close_rounded),
+          onPressed: cancel,
+          isOutline: true,
+        ),
+        dialogButton(
+          'OK',
+          icon: Icon(Icons.done_rounded),
+          onPressed: submit,
+        ),
+      ],
+      onSubmit: submit,
+      onCancel: cancel,
+    );
+  });
+}
+
 void wrongPasswordDialog(
     String id, OverlayDialogManager dialogManager, type, title, text) {
   dialogManager.dismissAll();
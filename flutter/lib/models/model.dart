@@ -148,6 +148,8 @@ class FfiModel with ChangeNotifier {
       } else if (name == 'connection_ready') {
         setConnectionType(
             peerId, evt['secure'] == 'true', evt['direct'] == 'true');
+      } else if (name == 'display_rgba') {
+        parent.target?.imageModel.onDisplayRgba(int.parse(evt['display']));
       } else if (name == 'switch_display') {
         handleSwitchDisplay(evt, peerId);
       } else if (name == 'cursor_data') {
@@ -225,6 +227,8 @@ class FfiModel with ChangeNotifier {
       } else if (name == "on_voice_call_incoming") {
         // Voice call is requested by the peer.
         parent.target?.chatModel.onVoiceCallIncoming();
+      } else if (name == "audio_sources") {
+        showAudioSources(peerId, evt, parent.target!.dialogManager);
       } else if (name == "update_voice_call_state") {
         parent.target?.serverModel.updateVoiceCallState(evt);
       } else {
@@ -296,6 +300,8 @@ class FfiModel with ChangeNotifier {
       wrongPasswordDialog(id, dialogManager, type, title, text);
     } else if (type == 'input-password') {
       enterPasswordDialog(id, dialogManager);
+    } else if (type == 'input-2fa') {
+      enter2FaDialog(id, dialogManager, title);
     } else if (type == 'restarting') {
       showMsgBox(id, type, title, text, link, false, dialogManager,
           hasCancel: false);
@@ -447,6 +453,7 @@ class FfiModel with ChangeNotifier {
       }
       Map<String, dynamic> features = json.decode(evt['features']);
       _pi.features.privacyMode = features['privacy_mode'] == 1;
+      _pi.features.virtualMicrophone = features['virtual_microphone'] == 1;
       handleResolutions(peerId, evt["resolutions"]);
       parent.target?.elevationModel.onPeerInfo(_pi);
     }
@@ -548,8 +555,47 @@ class ImageModel with ChangeNotifier {
 
   ImageModel(this.parent);
 
+  /// The images of the displays streamed besides the current one.
+  final displayImages = <int, ui.Image>{};
+
+  /// Whether all the displays are streamed, tiled below the current one.
+  bool captureAllDisplays = false;
+
   addCallbackOnFirstImage(Function(String) cb) => callbacksOnFirstImage.add(cb);
 
+  setCaptureAllDisplays(bool value) {
+    final ffi = parent.target;
+    if (ffi == null) return;
+    final others = <int>[];
+    for (var i = 0; i < ffi.ffiModel.pi.displays.length; i++) {
+      if (i != ffi.ffiModel.pi.currentDisplay) others.add(i);
+    }
+    captureAllDisplays = value;
+    bind.sessionCaptureDisplays(
+        id: id, add: value ? others : [], sub: value ? [] : others);
+    if (!value) {
+      displayImages.clear();
+    }
+    notifyListeners();
+  }
+
+  onDisplayRgba(int display) {
+    final ffi = parent.target;
+    if (ffi == null || !captureAllDisplays) return;
+    final displays = ffi.ffiModel.pi.displays;
+    if (display >= displays.length) return;
+    final d = displays[display];
+    final rgba = bind.sessionGetDisplayRgba(id: id, display: display);
+    if (rgba.length != d.width * d.height * 4) return;
+    img.decodeImageFromPixels(rgba, d.width, d.height,
+            isWeb ? ui.PixelFormat.rgba8888 : ui.PixelFormat.bgra8888)
+        .then((image) {
+      if (parent.target?.id != ffi.id || !captureAllDisplays) return;
+      displayImages[display] = image;
+      notifyListeners();
+    });
+  }
+
   onRgba(Uint8List rgba) {
     if (_waitForImage[id]!) {
       _waitForImage[id] = false;
@@ -1386,6 +1432,10 @@ class QualityMonitorData {
   String? delay;
   String? targetBitrate;
   String? codecFormat;
+  String? sendRate;
+  String? backlog;
+  String? jitter;
+  String? congestion;
 }
 
 class QualityMonitorModel with ChangeNotifier {
@@ -1419,6 +1469,16 @@ class QualityMonitorModel with ChangeNotifier {
       if ((evt['codec_format'] as String).isNotEmpty) {
         _data.codecFormat = evt['codec_format'];
       }
+      if ((evt['send_rate'] as String).isNotEmpty) {
+        _data.sendRate = evt['send_rate'];
+      }
+      if ((evt['backlog'] as String).isNotEmpty) {
+        _data.backlog = evt['backlog'];
+      }
+      if ((evt['jitter'] as String).isNotEmpty) _data.jitter = evt['jitter'];
+      if ((evt['congestion'] as String).isNotEmpty) {
+        _data.congestion = evt['congestion'];
+      }
       notifyListeners();
     } catch (e) {
       //
@@ -1669,6 +1729,7 @@ class Resolution {
 
 class Features {
   bool privacyMode = false;
+  bool virtualMicrophone = false;
 }
 
 class PeerInfo {
@@ -639,6 +639,8 @@ class Client {
   bool fromSwitch = false;
   bool inVoiceCall = false;
   bool incomingVoiceCall = false;
+  // the window shared instead of the screen, 0 for the screen
+  int shareWindow = 0;
 
   RxBool hasUnreadChatMessage = false.obs;
 
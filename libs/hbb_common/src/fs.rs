@@ -4,6 +4,7 @@ use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde_derive::{Deserialize, Serialize};
+use sodiumoxide::crypto::hash::sha256;
 use tokio::{fs::File, io::*};
 
 use crate::{bail, get_version_number, message_proto::*, ResultType, Stream};
@@ -194,6 +195,9 @@ pub fn can_enable_overwrite_detection(version: i64) -> bool {
     version >= get_version_number("1.1.10")
 }
 
+/// Size of the raw data carried by one `FileTransferBlock`, the unit of `offset_blk`.
+pub const BLOCK_SIZE: usize = 128 * 1024;
+
 #[derive(Default)]
 pub struct TransferJob {
     pub id: i32,
@@ -215,6 +219,14 @@ pub struct TransferJob {
     file_skipped: bool,
     file_is_waiting: bool,
     default_overwrite_strategy: Option<bool>,
+    // index of the next block of the current file
+    blk_id: u32,
+    // offset and prefix hash confirmed by the receiver, verified before the next read
+    resume: Option<(u32, bytes::Bytes)>,
+    // block hashes of the file to be overwritten at the receiver side
+    block_hashes: Vec<bytes::Bytes>,
+    // the file to be overwritten, where unchanged blocks are copied from
+    dest_file: Option<File>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -326,6 +338,7 @@ impl TransferJob {
 
     #[inline]
     pub fn set_files(&mut self, files: Vec<FileEntry>) {
+        self.total_size = files.iter().map(|x| x.size).sum();
         self.files = files;
     }
 
@@ -354,7 +367,9 @@ impl TransferJob {
         self.file_num
     }
 
-    pub fn modify_time(&self) {
+    pub fn modify_time(&mut self) {
+        // must be closed before being replaced on Windows
+        self.dest_file.take();
         let file_num = self.file_num as usize;
         if file_num < self.files.len() {
             let entry = &self.files[file_num];
@@ -399,9 +414,27 @@ impl TransferJob {
                 std::fs::create_dir_all(p).ok();
             }
             let path = format!("{}.download", get_string(&path));
-            self.file = Some(File::create(&path).await?);
+            let offset = block.blk_id as u64 * BLOCK_SIZE as u64;
+            if offset > 0 {
+                // the sender has verified our prefix, keep it and append the rest
+                let mut file = tokio::fs::OpenOptions::new().write(true).open(&path).await?;
+                if file.metadata().await?.len() < offset {
+                    bail!("Partial file is shorter than the resume offset");
+                }
+                file.set_len(offset).await?;
+                file.seek(SeekFrom::End(0)).await?;
+                self.file = Some(file);
+                self.finished_size += offset;
+                log::info!("resume writing {} from block {}", path, block.blk_id);
+            } else {
+                self.file = Some(File::create(&path).await?);
+            }
         }
-        if block.compressed {
+        if block.unchanged {
+            let data = self.read_dest_block(block.blk_id).await?;
+            self.file.as_mut().unwrap().write_all(&data).await?;
+            self.finished_size += data.len() as u64;
+        } else if block.compressed {
             let tmp = decompress(&block.data);
             self.file.as_mut().unwrap().write_all(&tmp).await?;
             self.finished_size += tmp.len() as u64;
@@ -413,6 +446,19 @@ impl TransferJob {
         Ok(())
     }
 
+    async fn read_dest_block(&mut self, blk_id: u32) -> ResultType<Vec<u8>> {
+        if self.dest_file.is_none() {
+            let path = self.join(&self.files[self.file_num as usize].name);
+            self.dest_file = Some(File::open(&path).await?);
+        }
+        let file = self.dest_file.as_mut().unwrap();
+        file.seek(SeekFrom::Start(blk_id as u64 * BLOCK_SIZE as u64))
+            .await?;
+        let mut buf = Vec::with_capacity(BLOCK_SIZE);
+        file.take(BLOCK_SIZE as u64).read_to_end(&mut buf).await?;
+        Ok(buf)
+    }
+
     #[inline]
     pub fn join(&self, name: &str) -> PathBuf {
         if name.is_empty() {
@@ -435,6 +481,9 @@ impl TransferJob {
                     self.file = Some(file);
                     self.file_confirmed = false;
                     self.file_is_waiting = false;
+                    self.blk_id = 0;
+                    self.resume = None;
+                    self.block_hashes.clear();
                 }
                 Err(err) => {
                     self.file_num += 1;
@@ -451,7 +500,16 @@ impl TransferJob {
             }
             return Ok(None);
         }
-        const BUF_SIZE: usize = 128 * 1024;
+        if let Some((offset_blk, hash)) = self.resume.take() {
+            if let Err(err) = self.seek_to_resume(offset_blk, &hash).await {
+                self.file_num += 1;
+                self.file = None;
+                self.file_confirmed = false;
+                self.file_is_waiting = false;
+                return Err(err);
+            }
+        }
+        const BUF_SIZE: usize = BLOCK_SIZE;
         let mut buf: Vec<u8> = vec![0; BUF_SIZE];
         let mut compressed = false;
         let mut offset: usize = 0;
@@ -473,6 +531,7 @@ impl TransferJob {
             }
         }
         unsafe { buf.set_len(offset) };
+        let blk_id = self.blk_id;
         if offset == 0 {
             self.file_num += 1;
             self.file = None;
@@ -480,6 +539,16 @@ impl TransferJob {
             self.file_is_waiting = false;
         } else {
             self.finished_size += offset as u64;
+            if self.is_block_unchanged(blk_id, &buf) {
+                self.blk_id += 1;
+                return Ok(Some(FileTransferBlock {
+                    id: self.id,
+                    file_num: file_num as _,
+                    blk_id,
+                    unchanged: true,
+                    ..Default::default()
+                }));
+            }
             if !is_compressed_file(name) {
                 let tmp = compress(&buf, COMPRESS_LEVEL);
                 if tmp.len() < buf.len() {
@@ -488,16 +557,87 @@ impl TransferJob {
                 }
             }
             self.transferred += buf.len() as u64;
+            self.blk_id += 1;
         }
         Ok(Some(FileTransferBlock {
             id: self.id,
             file_num: file_num as _,
             data: buf.into(),
             compressed,
+            blk_id,
             ..Default::default()
         }))
     }
 
+    #[inline]
+    fn is_block_unchanged(&self, blk_id: u32, buf: &[u8]) -> bool {
+        match self.block_hashes.get(blk_id as usize) {
+            Some(hash) => sha256::hash(buf).as_ref() == &hash[..],
+            None => false,
+        }
+    }
+
+    /// Set the block hashes of the file to be overwritten, reported by the receiver.
+    #[inline]
+    pub fn set_block_hashes(&mut self, block_hashes: Vec<bytes::Bytes>) {
+        self.block_hashes = block_hashes;
+    }
+
+    /// Skip the first `offset_blk` blocks of the current file if the receiver already has them.
+    ///
+    /// The prefix of the local file is hashed and compared with `hash` sent by the receiver,
+    /// on mismatch the file is sent from the beginning, and the receiver truncates its copy
+    /// because the first block has `blk_id` 0.
+    async fn seek_to_resume(&mut self, offset_blk: u32, hash: &[u8]) -> ResultType<()> {
+        let offset = offset_blk as u64 * BLOCK_SIZE as u64;
+        let matched = match self.prefix_matches(offset, hash).await {
+            Ok(matched) => matched,
+            Err(err) => {
+                log::error!("Failed to check partial content: {}", err);
+                false
+            }
+        };
+        if matched {
+            log::info!(
+                "id: {}, file_num: {}, resume from block {}",
+                self.id,
+                self.file_num,
+                offset_blk
+            );
+            self.blk_id = offset_blk;
+            self.finished_size += offset;
+        } else {
+            log::info!(
+                "id: {}, file_num: {}, partial content mismatch, send from the beginning",
+                self.id,
+                self.file_num
+            );
+            self.file
+                .as_mut()
+                .unwrap()
+                .seek(SeekFrom::Start(0))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn prefix_matches(&mut self, len: u64, hash: &[u8]) -> ResultType<bool> {
+        if self.file.as_ref().unwrap().metadata().await?.len() < len {
+            return Ok(false);
+        }
+        let path = self.join(&self.files[self.file_num as usize].name);
+        let prefix = tokio::task::spawn_blocking(move || hash_file_prefix(path, len)).await??;
+        if prefix != hash {
+            return Ok(false);
+        }
+        self.file
+            .as_mut()
+            .unwrap()
+            .seek(SeekFrom::Start(len))
+            .await?;
+        Ok(true)
+    }
+
     async fn send_current_digest(&mut self, stream: &mut Stream) -> ResultType<()> {
         let mut msg = Message::new();
         let mut resp = FileResponse::new();
@@ -607,8 +747,14 @@ impl TransferJob {
                         self.set_file_confirmed(true);
                     }
                 }
-                Some(file_transfer_send_confirm_request::Union::OffsetBlk(_offset)) => {
+                Some(file_transfer_send_confirm_request::Union::OffsetBlk(offset)) => {
                     self.set_file_confirmed(true);
+                    if offset > 0 {
+                        self.resume = Some((offset, r.offset_hash.clone()));
+                    }
+                    if !r.block_hashes.is_empty() {
+                        self.block_hashes = r.block_hashes.clone();
+                    }
                 }
                 _ => {}
             }
@@ -675,6 +821,117 @@ pub fn new_send_confirm(r: FileTransferSendConfirmRequest) -> Message {
     msg_out
 }
 
+/// Confirm the transfer of a file which does not exist at the receiver side.
+///
+/// If `<file_path>.download` is left by an interrupted transfer, the whole blocks in it
+/// are offered to the sender together with their hash, so that only the rest is sent.
+pub async fn new_resume_confirm(
+    id: i32,
+    file_num: i32,
+    file_path: &str,
+    file_size: u64,
+) -> FileTransferSendConfirmRequest {
+    let mut req = FileTransferSendConfirmRequest {
+        id,
+        file_num,
+        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+        ..Default::default()
+    };
+    let download_path = format!("{}.download", file_path);
+    let len = std::fs::metadata(&download_path)
+        .map(|m| if m.is_file() { m.len() } else { 0 })
+        .unwrap_or(0);
+    let offset_blk = (std::cmp::min(len, file_size) / BLOCK_SIZE as u64) as u32;
+    if offset_blk == 0 {
+        return req;
+    }
+    let len = offset_blk as u64 * BLOCK_SIZE as u64;
+    let path = download_path.clone();
+    let hash = match tokio::task::spawn_blocking(move || hash_file_prefix(path, len)).await {
+        Ok(res) => res,
+        Err(err) => Err(err.into()),
+    };
+    match hash {
+        Ok(hash) => {
+            log::info!("offer to resume {} from block {}", file_path, offset_blk);
+            req.set_offset_blk(offset_blk);
+            req.offset_hash = hash.into();
+        }
+        Err(err) => {
+            log::error!("Failed to hash {}: {}", download_path, err);
+        }
+    }
+    req
+}
+
+// 256 KiB of hashes covering the first 1 GiB, the blocks after are sent as usual
+pub const MAX_BLOCK_HASHES: usize = 8 * 1024;
+
+/// Hash each block of an existing file which is going to be overwritten.
+///
+/// Sent to the sender, so that only the blocks which differ are transferred.
+/// Blocks are compared at the same offset, so this helps with in-place modifications,
+/// insertions fall back to sending the rest of the file.
+pub async fn get_block_hashes(file_path: &str) -> ResultType<Vec<bytes::Bytes>> {
+    let path = file_path.to_owned();
+    tokio::task::spawn_blocking(move || hash_blocks(&path, MAX_BLOCK_HASHES)).await?
+}
+
+fn hash_blocks(file_path: &str, max: usize) -> ResultType<Vec<bytes::Bytes>> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(file_path)?;
+    let mut hashes = Vec::new();
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    while hashes.len() < max {
+        let mut offset = 0;
+        while offset < BLOCK_SIZE {
+            let n = file.read(&mut buf[offset..])?;
+            if n == 0 {
+                break;
+            }
+            offset += n;
+        }
+        if offset == 0 {
+            break;
+        }
+        hashes.push(sha256::hash(&buf[..offset]).as_ref().to_vec().into());
+        if offset < BLOCK_SIZE {
+            break;
+        }
+    }
+    Ok(hashes)
+}
+
+/// Confirm overwriting an existing file, with the hashes of its blocks attached.
+pub async fn new_overwrite_confirm(
+    id: i32,
+    file_num: i32,
+    file_path: &str,
+) -> FileTransferSendConfirmRequest {
+    FileTransferSendConfirmRequest {
+        id,
+        file_num,
+        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+        block_hashes: get_block_hashes(file_path).await.unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+fn hash_file_prefix<P: AsRef<Path>>(path: P, len: u64) -> ResultType<Vec<u8>> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(path)?;
+    let mut state = sha256::State::new();
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = std::cmp::min(remaining, BLOCK_SIZE as u64) as usize;
+        file.read_exact(&mut buf[..n])?;
+        state.update(&buf[..n]);
+        remaining -= n as u64;
+    }
+    Ok(state.finalize().as_ref().to_vec())
+}
+
 #[inline]
 pub fn new_receive(id: i32, path: String, file_num: i32, files: Vec<FileEntry>) -> Message {
     let mut action = FileAction::new();
@@ -842,3 +1099,51 @@ pub fn is_write_need_confirmation(
         Ok(DigestCheckResult::NoSuchFile)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("hbb_fs_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        get_string(&dir.join(name))
+    }
+
+    #[tokio::test]
+    async fn test_resume_confirm() {
+        let path = temp_path("resume");
+        let data: Vec<u8> = (0..BLOCK_SIZE * 5 / 2).map(|i| i as u8).collect();
+        std::fs::write(format!("{}.download", path), &data).unwrap();
+        let req = new_resume_confirm(1, 0, &path, data.len() as u64 * 2).await;
+        assert_eq!(req.offset_blk(), 2);
+        let hash = sha256::hash(&data[..BLOCK_SIZE * 2]);
+        assert_eq!(&req.offset_hash[..], hash.as_ref());
+        let prefix = hash_file_prefix(format!("{}.download", path), BLOCK_SIZE as u64).unwrap();
+        assert_eq!(&prefix[..], sha256::hash(&data[..BLOCK_SIZE]).as_ref());
+        // never offer more than the sender has
+        let req = new_resume_confirm(1, 0, &path, BLOCK_SIZE as u64 - 1).await;
+        assert_eq!(req.offset_blk(), 0);
+        assert!(req.offset_hash.is_empty());
+        std::fs::remove_file(format!("{}.download", path)).ok();
+        let req = new_resume_confirm(1, 0, &path, data.len() as u64).await;
+        assert_eq!(req.offset_blk(), 0);
+    }
+
+    #[test]
+    fn test_block_hashes() {
+        let path = temp_path("blocks");
+        let data: Vec<u8> = (0..BLOCK_SIZE * 3 + 7).map(|i| (i / 3) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+        let hashes = hash_blocks(&path, MAX_BLOCK_HASHES).unwrap();
+        assert_eq!(hashes.len(), 4);
+        for (i, hash) in hashes.iter().enumerate() {
+            let end = std::cmp::min((i + 1) * BLOCK_SIZE, data.len());
+            assert_eq!(&hash[..], sha256::hash(&data[i * BLOCK_SIZE..end]).as_ref());
+        }
+        assert_eq!(hash_blocks(&path, 2).unwrap(), hashes[..2].to_vec());
+        std::fs::write(&path, b"").unwrap();
+        assert!(hash_blocks(&path, MAX_BLOCK_HASHES).unwrap().is_empty());
+        std::fs::remove_file(&path).ok();
+    }
+}
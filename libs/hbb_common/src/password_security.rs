@@ -1,5 +1,5 @@
 use crate::config::Config;
-use sodiumoxide::base64;
+use sodiumoxide::{base64, crypto::sign};
 use std::sync::{Arc, RwLock};
 
 lazy_static::lazy_static! {
@@ -82,6 +82,58 @@ pub fn hide_cm() -> bool {
         && !Config::get_option("allow-hide-cm").is_empty()
 }
 
+// one "<base64 public key> [comment]" per line, like authorized_keys of ssh
+pub fn authorized_keys() -> Vec<(String, String)> {
+    parse_authorized_keys(&Config::get_option("authorized-keys"))
+}
+
+pub fn parse_authorized_keys(v: &str) -> Vec<(String, String)> {
+    v.lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let mut it = line.splitn(2, char::is_whitespace);
+            let key = it.next()?.to_owned();
+            let comment = it.next().unwrap_or_default().trim().to_owned();
+            Some((key, comment))
+        })
+        .collect()
+}
+
+pub fn authorized_keys_option(keys: &[(String, String)]) -> String {
+    keys.iter()
+        .map(|(key, comment)| format!("{} {}", key, comment).trim().to_owned())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Check a public key in base64 to be added to the authorized keys.
+pub fn is_valid_public_key(key: &str) -> bool {
+    base64::decode(key, base64::Variant::Original)
+        .map_or(false, |pk| pk.len() == sign::PUBLICKEYBYTES)
+}
+
+pub fn is_authorized_key(pk: &[u8]) -> bool {
+    let pk = base64::encode(pk, base64::Variant::Original);
+    authorized_keys().iter().any(|(key, _)| key == &pk)
+}
+
+/// The public key of this device in base64, to be authorized by the peers.
+pub fn public_key() -> String {
+    base64::encode(Config::get_key_pair().1, base64::Variant::Original)
+}
+
+/// What the client signs with its key to log in, bound to the secure channel,
+/// which is the transcript of the key exchange or the signed IdPk of the peer,
+/// so that it can not be relayed to another peer.
+pub fn key_login_data(channel_binding: &[u8], salt: &str, challenge: &str) -> Vec<u8> {
+    let mut data = format!("rustdesk-key-login\n{}\n{}\n", salt, challenge).into_bytes();
+    data.extend_from_slice(channel_binding);
+    data
+}
+
 const VERSION_LEN: usize = 2;
 
 pub fn encrypt_str_or_original(s: &str, version: &str) -> String {
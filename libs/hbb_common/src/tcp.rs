@@ -3,13 +3,17 @@ use anyhow::Context as AnyhowCtx;
 use bytes::{BufMut, Bytes, BytesMut};
 use futures::{SinkExt, StreamExt};
 use protobuf::Message;
-use sodiumoxide::crypto::secretbox::{self, Key, Nonce};
+use sodiumoxide::crypto::{
+    hash::sha256,
+    secretbox::{self, Key, Nonce},
+};
 use std::{
     io::{self, Error, ErrorKind},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     ops::{Deref, DerefMut},
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 use tokio::{
     io::{AsyncRead, AsyncWrite, ReadBuf},
@@ -24,10 +28,149 @@ pub struct DynTcpStream(Box<dyn TcpStreamTrait + Send + Sync>);
 pub struct FramedStream(
     Framed<DynTcpStream, BytesCodec>,
     SocketAddr,
-    Option<(Key, u64, u64)>,
+    Option<Encrypt>,
     u64,
 );
 
+// a session key is ratcheted after this many bytes sent or this long
+const REKEY_BYTES: u64 = 1 << 30;
+const REKEY_INTERVAL: Duration = Duration::from_secs(600);
+// type and sequence number before the box of a frame with a session key
+const HEADER_LEN: usize = 9;
+const FRAME_DATA: u8 = 0;
+// sealed under the old key, the following frames use the ratcheted one
+const FRAME_REKEY: u8 = 1;
+
+struct Encrypt {
+    send_key: Key,
+    recv_key: Key,
+    send_seqnum: u64,
+    recv_seqnum: u64,
+    // set_session_key, otherwise the sequence numbers are implicit and the key never changes
+    session: bool,
+    sent_bytes: u64,
+    send_since: Instant,
+}
+
+impl Encrypt {
+    fn new(key: Key) -> Self {
+        Self {
+            send_key: key.clone(),
+            recv_key: key,
+            send_seqnum: 0,
+            recv_seqnum: 0,
+            session: false,
+            sent_bytes: 0,
+            send_since: Instant::now(),
+        }
+    }
+
+    fn new_session(key: Key, is_server: bool) -> Self {
+        let c2s = derive_key(&key, b"c2s");
+        let s2c = derive_key(&key, b"s2c");
+        let (send_key, recv_key) = if is_server { (s2c, c2s) } else { (c2s, s2c) };
+        Self {
+            send_key,
+            recv_key,
+            session: true,
+            ..Self::new(key)
+        }
+    }
+
+    fn seal(&mut self, typ: u8, msg: &[u8]) -> Vec<u8> {
+        self.send_seqnum += 1;
+        if !self.session {
+            return secretbox::seal(msg, &get_nonce(self.send_seqnum, 0), &self.send_key);
+        }
+        self.sent_bytes += msg.len() as u64;
+        let sealed = secretbox::seal(msg, &get_nonce(self.send_seqnum, typ), &self.send_key);
+        let mut frame = Vec::with_capacity(HEADER_LEN + sealed.len());
+        frame.push(typ);
+        frame.extend_from_slice(&self.send_seqnum.to_le_bytes());
+        frame.extend_from_slice(&sealed);
+        frame
+    }
+
+    // the frame to send before the next one if it's time to ratchet the key
+    fn rekey(&mut self) -> Option<Vec<u8>> {
+        if !self.session
+            || (self.sent_bytes < REKEY_BYTES && self.send_since.elapsed() < REKEY_INTERVAL)
+        {
+            return None;
+        }
+        let frame = self.seal(FRAME_REKEY, &[]);
+        self.send_key = derive_key(&self.send_key, b"rekey");
+        self.sent_bytes = 0;
+        self.send_since = Instant::now();
+        Some(frame)
+    }
+
+    // false for a control frame, which is not passed on
+    fn open(&mut self, bytes: &mut BytesMut) -> Result<bool, Error> {
+        self.recv_seqnum += 1;
+        if !self.session {
+            let res = secretbox::open(bytes, &get_nonce(self.recv_seqnum, 0), &self.recv_key)
+                .map_err(|_| Error::new(ErrorKind::Other, "decryption error"))?;
+            bytes.clear();
+            bytes.put_slice(&res);
+            return Ok(true);
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "truncated frame"));
+        }
+        let typ = bytes[0];
+        let mut seqnum = [0u8; 8];
+        seqnum.copy_from_slice(&bytes[1..HEADER_LEN]);
+        let seqnum = u64::from_le_bytes(seqnum);
+        if seqnum != self.recv_seqnum {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "frame {} out of sequence, {} expected, replayed or reordered",
+                    seqnum, self.recv_seqnum
+                ),
+            ));
+        }
+        let res = secretbox::open(
+            &bytes[HEADER_LEN..],
+            &get_nonce(seqnum, typ),
+            &self.recv_key,
+        )
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "decryption error"))?;
+        match typ {
+            FRAME_DATA => {
+                bytes.clear();
+                bytes.put_slice(&res);
+                Ok(true)
+            }
+            FRAME_REKEY => {
+                self.recv_key = derive_key(&self.recv_key, b"rekey");
+                Ok(false)
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown frame type {}", typ),
+            )),
+        }
+    }
+}
+
+fn derive_key(key: &Key, label: &[u8]) -> Key {
+    let mut data = label.to_vec();
+    data.extend_from_slice(&key.0);
+    let mut key = [0u8; secretbox::KEYBYTES];
+    key.copy_from_slice(&sha256::hash(&data).0[..secretbox::KEYBYTES]);
+    Key(key)
+}
+
+// the type is bound by the nonce, as the sequence number
+fn get_nonce(seqnum: u64, typ: u8) -> Nonce {
+    let mut nonce = Nonce([0u8; secretbox::NONCEBYTES]);
+    nonce.0[..std::mem::size_of_val(&seqnum)].copy_from_slice(&seqnum.to_le_bytes());
+    nonce.0[std::mem::size_of_val(&seqnum)] = typ;
+    nonce
+}
+
 impl Deref for FramedStream {
     type Target = Framed<DynTcpStream, BytesCodec>;
 
@@ -167,7 +310,12 @@ impl FramedStream {
         )
     }
 
+    /// For the bytes of a classic port forward, sent by `send_raw`.
+    /// A stream with a session key stays framed and encrypted, the others are not.
     pub fn set_raw(&mut self) {
+        if self.2.as_ref().map_or(false, |enc| enc.session) {
+            return;
+        }
         self.0.codec_mut().set_raw();
         self.2 = None;
     }
@@ -184,10 +332,11 @@ impl FramedStream {
     #[inline]
     pub async fn send_raw(&mut self, msg: Vec<u8>) -> ResultType<()> {
         let mut msg = msg;
-        if let Some(key) = self.2.as_mut() {
-            key.1 += 1;
-            let nonce = Self::get_nonce(key.1);
-            msg = secretbox::seal(&msg, &nonce, &key.0);
+        if let Some(frame) = self.2.as_mut().and_then(|enc| enc.rekey()) {
+            self.send_bytes(bytes::Bytes::from(frame)).await?;
+        }
+        if let Some(enc) = self.2.as_mut() {
+            msg = enc.seal(FRAME_DATA, &msg);
         }
         self.send_bytes(bytes::Bytes::from(msg)).await?;
         Ok(())
@@ -205,23 +354,19 @@ impl FramedStream {
 
     #[inline]
     pub async fn next(&mut self) -> Option<Result<BytesMut, Error>> {
-        let mut res = self.0.next().await;
-        if let Some(key) = self.2.as_mut() {
-            if let Some(Ok(bytes)) = res.as_mut() {
-                key.2 += 1;
-                let nonce = Self::get_nonce(key.2);
-                match secretbox::open(bytes, &nonce, &key.0) {
-                    Ok(res) => {
-                        bytes.clear();
-                        bytes.put_slice(&res);
-                    }
-                    Err(()) => {
-                        return Some(Err(Error::new(ErrorKind::Other, "decryption error")));
+        loop {
+            let mut res = self.0.next().await;
+            if let Some(enc) = self.2.as_mut() {
+                if let Some(Ok(bytes)) = res.as_mut() {
+                    match enc.open(bytes) {
+                        Ok(true) => {}
+                        Ok(false) => continue,
+                        Err(err) => return Some(Err(err)),
                     }
                 }
             }
+            return res;
         }
-        res
     }
 
     #[inline]
@@ -234,13 +379,13 @@ impl FramedStream {
     }
 
     pub fn set_key(&mut self, key: Key) {
-        self.2 = Some((key, 0, 0));
+        self.2 = Some(Encrypt::new(key));
     }
 
-    fn get_nonce(seqnum: u64) -> Nonce {
-        let mut nonce = Nonce([0u8; secretbox::NONCEBYTES]);
-        nonce.0[..std::mem::size_of_val(&seqnum)].copy_from_slice(&seqnum.to_le_bytes());
-        nonce
+    /// Like `set_key`, with the sequence numbers on the wire, a key for each direction,
+    /// and the keys ratcheted in-band every `REKEY_BYTES` or `REKEY_INTERVAL`.
+    pub fn set_session_key(&mut self, key: Key, is_server: bool) {
+        self.2 = Some(Encrypt::new_session(key, is_server));
     }
 }
 
@@ -323,3 +468,42 @@ impl AsyncWrite for DynTcpStream {
 }
 
 impl<R: AsyncRead + AsyncWrite + Unpin> TcpStreamTrait for R {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (Encrypt, Encrypt) {
+        let key = secretbox::gen_key();
+        (
+            Encrypt::new_session(key.clone(), false),
+            Encrypt::new_session(key, true),
+        )
+    }
+
+    #[test]
+    fn test_session_key() {
+        let (mut client, mut server) = pair();
+        let frame = client.seal(FRAME_DATA, b"hello");
+        let mut bytes = BytesMut::from(&frame[..]);
+        assert!(server.open(&mut bytes).unwrap());
+        assert_eq!(&bytes[..], b"hello");
+        let mut bytes = BytesMut::from(&frame[..]);
+        assert!(server.open(&mut bytes).is_err());
+
+        let (mut client, mut server) = pair();
+        client.seal(FRAME_DATA, b"lost");
+        let mut bytes = BytesMut::from(&client.seal(FRAME_DATA, b"hello")[..]);
+        assert!(server.open(&mut bytes).is_err());
+
+        let (mut client, mut server) = pair();
+        assert!(client.rekey().is_none());
+        client.sent_bytes = REKEY_BYTES;
+        let mut bytes = BytesMut::from(&client.rekey().unwrap()[..]);
+        assert!(!server.open(&mut bytes).unwrap());
+        let mut bytes = BytesMut::from(&client.seal(FRAME_DATA, b"hello")[..]);
+        assert!(server.open(&mut bytes).unwrap());
+        assert_eq!(&bytes[..], b"hello");
+        assert!(client.rekey().is_none());
+    }
+}
@@ -0,0 +1,417 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream, UdpSocket},
+    sync::mpsc,
+    task::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{log, message_proto::*, timeout, ResultType};
+
+/// Sent in `Hash` before login, so that the client knows the peer takes `TunnelData` sessions
+/// without telling it the software version of the peer.
+pub const TUNNEL_VERSION: u32 = 1;
+
+const READ_BUF_SIZE: usize = 64 * 1024;
+// messages from the peer waiting for one local socket
+const WRITE_QUEUE_SIZE: usize = 256;
+// events of all the local sockets waiting to be sent to the peer
+const EVENT_QUEUE_SIZE: usize = 256;
+// ms, a local socket not taking any data from the peer for this long is closed
+const WRITE_TIMEOUT: u64 = 30_000;
+// ms, the same as the target of a port forward
+const CONNECT_TIMEOUT: u64 = 3_000;
+/// A UDP flow without any datagram in either direction for this long is dropped.
+pub const UDP_FLOW_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Events of the local sockets, to be sent to the peer.
+pub enum TunnelEvent {
+    Opened(i32),
+    Data(i32, Bytes),
+    Closed(i32),
+}
+
+/// Local TCP sockets multiplexed over one `Stream` with `TunnelData` messages.
+///
+/// Reading and writing are done by two tasks per socket, the data read is returned by `next`,
+/// the data to write is queued by `write`. Both queues are bounded, so a local socket reading
+/// faster than the peer takes it waits, and a full write queue holds up the peer.
+pub struct TcpTunnels {
+    writers: HashMap<i32, (mpsc::Sender<Bytes>, JoinHandle<()>)>,
+    connecting: HashMap<i32, JoinHandle<()>>,
+    tx: mpsc::Sender<TunnelEvent>,
+    rx: mpsc::Receiver<TunnelEvent>,
+    connected_tx: mpsc::UnboundedSender<(i32, Option<TcpStream>)>,
+    connected_rx: mpsc::UnboundedReceiver<(i32, Option<TcpStream>)>,
+}
+
+impl TcpTunnels {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel(EVENT_QUEUE_SIZE);
+        let (connected_tx, connected_rx) = mpsc::unbounded_channel();
+        Self {
+            writers: Default::default(),
+            connecting: Default::default(),
+            tx,
+            rx,
+            connected_tx,
+            connected_rx,
+        }
+    }
+
+    /// Connect to `addr` in the background, `next` returns `Opened` or `Closed` of `id` when done.
+    pub fn connect(&mut self, id: i32, addr: String) {
+        let tx = self.connected_tx.clone();
+        let handle = tokio::spawn(async move {
+            let socket = match timeout(CONNECT_TIMEOUT, TcpStream::connect(&addr)).await {
+                Ok(Ok(socket)) => Some(socket),
+                _ => {
+                    log::debug!("Failed to connect to {}", addr);
+                    None
+                }
+            };
+            tx.send((id, socket)).ok();
+        });
+        if let Some(old) = self.connecting.insert(id, handle) {
+            old.abort();
+        }
+    }
+
+    pub fn add(&mut self, id: i32, socket: TcpStream) {
+        let (mut reader, writer) = socket.into_split();
+        let (writer_tx, writer_rx) = mpsc::channel(WRITE_QUEUE_SIZE);
+        tokio::spawn(write_loop(id, writer, writer_rx, self.tx.clone()));
+        let tx = self.tx.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                let mut buf = BytesMut::with_capacity(READ_BUF_SIZE);
+                match reader.read_buf(&mut buf).await {
+                    Ok(n) if n > 0 => {
+                        if tx.send(TunnelEvent::Data(id, buf.freeze())).await.is_err() {
+                            break;
+                        }
+                    }
+                    _ => {
+                        tx.send(TunnelEvent::Closed(id)).await.ok();
+                        break;
+                    }
+                }
+            }
+        });
+        if let Some((_, old)) = self.writers.insert(id, (writer_tx, handle)) {
+            old.abort();
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, id: i32) -> bool {
+        self.writers.contains_key(&id)
+    }
+
+    pub fn remove(&mut self, id: i32) {
+        if let Some((_, handle)) = self.writers.remove(&id) {
+            handle.abort();
+        }
+        if let Some(handle) = self.connecting.remove(&id) {
+            handle.abort();
+        }
+    }
+
+    /// Queue data from the peer to the local socket, waits while the queue is full so that the
+    /// peer is not read faster than the socket takes it. The socket is removed if it is gone or
+    /// takes nothing for `WRITE_TIMEOUT`.
+    pub async fn write(&mut self, id: i32, data: Bytes) -> bool {
+        if let Some((writer, _)) = self.writers.get(&id) {
+            match timeout(WRITE_TIMEOUT, writer.send(data)).await {
+                Ok(Ok(())) => return true,
+                Ok(Err(_)) => log::debug!("Tunnel #{} is closed", id),
+                Err(_) => log::debug!("Tunnel #{} does not keep up, closed", id),
+            }
+            self.remove(id);
+        }
+        false
+    }
+
+    /// Wait for the next event of the local sockets.
+    pub async fn next(&mut self) -> Option<TunnelEvent> {
+        loop {
+            tokio::select! {
+                Some((id, socket)) = self.connected_rx.recv() => {
+                    // removed while connecting
+                    if self.connecting.remove(&id).is_none() {
+                        continue;
+                    }
+                    return match socket {
+                        Some(socket) => {
+                            self.add(id, socket);
+                            Some(TunnelEvent::Opened(id))
+                        }
+                        None => Some(TunnelEvent::Closed(id)),
+                    };
+                }
+                event = self.rx.recv() => {
+                    if let Some(TunnelEvent::Closed(id)) = event {
+                        self.remove(id);
+                    }
+                    return event;
+                }
+            }
+        }
+    }
+}
+
+// Ends once the queue is closed and drained, or on error.
+async fn write_loop(
+    id: i32,
+    mut writer: OwnedWriteHalf,
+    mut rx: mpsc::Receiver<Bytes>,
+    tx: mpsc::Sender<TunnelEvent>,
+) {
+    while let Some(data) = rx.recv().await {
+        if writer.write_all(&data).await.is_err() {
+            tx.send(TunnelEvent::Closed(id)).await.ok();
+            break;
+        }
+    }
+}
+
+impl Default for TcpTunnels {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TcpTunnels {
+    fn drop(&mut self) {
+        for (_, (_, handle)) in self.writers.drain() {
+            handle.abort();
+        }
+        for (_, handle) in self.connecting.drain() {
+            handle.abort();
+        }
+    }
+}
+
+/// UDP flows to one target, each flow has its own connected socket,
+/// so that the replies can be told apart.
+pub struct UdpTunnels {
+    target: String,
+    flows: HashMap<i32, (Arc<UdpSocket>, Instant, JoinHandle<()>)>,
+    tx: mpsc::Sender<TunnelEvent>,
+    rx: mpsc::Receiver<TunnelEvent>,
+}
+
+impl UdpTunnels {
+    pub fn new(target: String) -> Self {
+        let (tx, rx) = mpsc::channel(EVENT_QUEUE_SIZE);
+        Self {
+            target,
+            flows: Default::default(),
+            tx,
+            rx,
+        }
+    }
+
+    /// Send a datagram of the flow `id` to the target, the flow is created on its first datagram.
+    pub async fn send(&mut self, id: i32, data: &[u8]) -> ResultType<()> {
+        if !self.flows.contains_key(&id) {
+            let socket = if self.target.starts_with('[') {
+                UdpSocket::bind("[::]:0").await?
+            } else {
+                UdpSocket::bind("0.0.0.0:0").await?
+            };
+            socket.connect(&self.target).await?;
+            let socket = Arc::new(socket);
+            let reader = socket.clone();
+            let tx = self.tx.clone();
+            let handle = tokio::spawn(async move {
+                let mut buf = vec![0u8; READ_BUF_SIZE];
+                loop {
+                    match reader.recv(&mut buf).await {
+                        Ok(n) => {
+                            let data = Bytes::copy_from_slice(&buf[..n]);
+                            if tx.send(TunnelEvent::Data(id, data)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(_) => {
+                            tx.send(TunnelEvent::Closed(id)).await.ok();
+                            break;
+                        }
+                    }
+                }
+            });
+            self.flows.insert(id, (socket, Instant::now(), handle));
+        }
+        if let Some((socket, last_active, _)) = self.flows.get_mut(&id) {
+            *last_active = Instant::now();
+            socket.send(data).await?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: i32) {
+        if let Some((_, _, handle)) = self.flows.remove(&id) {
+            handle.abort();
+        }
+    }
+
+    /// Drop the flows idle for [`UDP_FLOW_TIMEOUT`], returns their ids.
+    pub fn remove_idle(&mut self) -> Vec<i32> {
+        let ids: Vec<i32> = self
+            .flows
+            .iter()
+            .filter(|(_, (_, last_active, _))| last_active.elapsed() >= UDP_FLOW_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids.iter() {
+            self.remove(*id);
+        }
+        ids
+    }
+
+    /// Wait for the next datagram from the target.
+    pub async fn next(&mut self) -> Option<TunnelEvent> {
+        let event = self.rx.recv().await;
+        match event {
+            Some(TunnelEvent::Data(id, _)) => {
+                if let Some((_, last_active, _)) = self.flows.get_mut(&id) {
+                    *last_active = Instant::now();
+                }
+            }
+            Some(TunnelEvent::Closed(id)) => self.remove(id),
+            _ => {}
+        }
+        event
+    }
+}
+
+impl Drop for UdpTunnels {
+    fn drop(&mut self) {
+        for (_, (_, _, handle)) in self.flows.drain() {
+            handle.abort();
+        }
+    }
+}
+
+/// Accept from the listener if any, never ready without.
+pub async fn accept(listener: &Option<TcpListener>) -> std::io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[inline]
+pub fn new_tunnel_open(id: i32) -> Message {
+    let mut data = TunnelData::new();
+    data.id = id;
+    data.set_open(true);
+    new_tunnel_msg(data)
+}
+
+#[inline]
+pub fn new_tunnel_connect(id: i32, target: String) -> Message {
+    let mut data = TunnelData::new();
+    data.id = id;
+    data.set_open(true);
+    data.target = target;
+    new_tunnel_msg(data)
+}
+
+#[inline]
+pub fn new_tunnel_close(id: i32) -> Message {
+    let mut data = TunnelData::new();
+    data.id = id;
+    data.set_close(true);
+    new_tunnel_msg(data)
+}
+
+#[inline]
+pub fn new_tunnel_data(id: i32, bytes: Bytes) -> Message {
+    let mut data = TunnelData::new();
+    data.id = id;
+    data.set_data(bytes);
+    new_tunnel_msg(data)
+}
+
+impl From<TunnelEvent> for Message {
+    fn from(event: TunnelEvent) -> Self {
+        match event {
+            TunnelEvent::Opened(id) => new_tunnel_open(id),
+            TunnelEvent::Data(id, bytes) => new_tunnel_data(id, bytes),
+            TunnelEvent::Closed(id) => new_tunnel_close(id),
+        }
+    }
+}
+
+#[inline]
+fn new_tunnel_msg(data: TunnelData) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_tunnel_data(data);
+    msg_out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn listen() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        (listener, addr)
+    }
+
+    async fn next(tunnels: &mut TcpTunnels) -> Option<TunnelEvent> {
+        timeout(1_000, tunnels.next()).await.ok().flatten()
+    }
+
+    #[tokio::test]
+    async fn test_tcp_tunnel() {
+        let (listener, addr) = listen().await;
+        let mut tunnels = TcpTunnels::new();
+        tunnels.connect(1, addr);
+        let (mut local, _) = listener.accept().await.unwrap();
+        assert!(matches!(next(&mut tunnels).await, Some(TunnelEvent::Opened(1))));
+        assert!(tunnels.contains(1));
+
+        assert!(tunnels.write(1, Bytes::from_static(b"ping")).await);
+        let mut buf = [0u8; 4];
+        local.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        local.write_all(b"pong").await.unwrap();
+        match next(&mut tunnels).await {
+            Some(TunnelEvent::Data(1, data)) => assert_eq!(&data[..], b"pong"),
+            _ => panic!("no data"),
+        }
+
+        drop(local);
+        assert!(matches!(next(&mut tunnels).await, Some(TunnelEvent::Closed(1))));
+        assert!(!tunnels.contains(1));
+        assert!(!tunnels.write(1, Bytes::from_static(b"ping")).await);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_tunnel_connect_failure() {
+        let (listener, addr) = listen().await;
+        drop(listener);
+        let mut tunnels = TcpTunnels::new();
+        tunnels.connect(1, addr);
+        assert!(matches!(next(&mut tunnels).await, Some(TunnelEvent::Closed(1))));
+        assert!(!tunnels.contains(1));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_tunnel_remove_while_connecting() {
+        let (_listener, addr) = listen().await;
+        let mut tunnels = TcpTunnels::new();
+        tunnels.connect(1, addr);
+        tunnels.remove(1);
+        assert!(next(&mut tunnels).await.is_none());
+        assert!(!tunnels.contains(1));
+    }
+}
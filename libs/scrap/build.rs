@@ -116,13 +116,15 @@ fn generate_bindings(
     include_paths: &[PathBuf],
     ffi_rs: &Path,
     exact_file: &Path,
+    prefix: char,
 ) {
+    let upper = prefix.to_ascii_uppercase();
     let mut b = bindgen::builder()
         .header(ffi_header.to_str().unwrap())
-        .allowlist_type("^[vV].*")
-        .allowlist_var("^[vV].*")
-        .allowlist_function("^[vV].*")
-        .rustified_enum("^v.*")
+        .allowlist_type(format!("^[{}{}].*", prefix, upper))
+        .allowlist_var(format!("^[{}{}].*", prefix, upper))
+        .allowlist_function(format!("^[{}{}].*", prefix, upper))
+        .rustified_enum(format!("^{}.*", prefix))
         .trust_clang_mangling(false)
         .layout_tests(false) // breaks 32/64-bit compat
         .generate_comments(false); // vpx comments have prefix /*!\
@@ -150,7 +152,25 @@ fn gen_vpx() {
 
     let ffi_rs = out_dir.join("vpx_ffi.rs");
     let exact_file = src_dir.join("generated").join("vpx_ffi.rs");
-    generate_bindings(&ffi_header, &includes, &ffi_rs, &exact_file);
+    generate_bindings(&ffi_header, &includes, &ffi_rs, &exact_file, 'v');
+}
+
+fn gen_aom() {
+    let includes = find_package("aom");
+    let src_dir = env::var_os("CARGO_MANIFEST_DIR").unwrap();
+    let src_dir = Path::new(&src_dir);
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+
+    let ffi_header = src_dir.join("aom_ffi.h");
+    println!("rerun-if-changed={}", ffi_header.display());
+    for dir in &includes {
+        println!("rerun-if-changed={}", dir.display());
+    }
+
+    let ffi_rs = out_dir.join("aom_ffi.rs");
+    let exact_file = src_dir.join("generated").join("aom_ffi.rs");
+    generate_bindings(&ffi_header, &includes, &ffi_rs, &exact_file, 'a');
 }
 
 fn main() {
@@ -167,6 +187,9 @@ fn main() {
 
     find_package("libyuv");
     gen_vpx();
+    if env::var_os("CARGO_FEATURE_AV1").is_some() {
+        gen_aom();
+    }
 
     // there is problem with cfg(target_os) in build.rs, so use our workaround
     let target_os = std::env::var("CARGO_CFG_TARGET_OS").unwrap();
@@ -95,6 +95,7 @@ fn test_vp9(yuvs: &Vec<Vec<u8>>, width: usize, height: usize, bitrate_k: usize,
         bitrate: bitrate_k as _,
         codec: VpxVideoCodecId::VP9,
         num_threads: (num_cpus::get() / 2) as _,
+        i444: false,
     });
     let mut encoder = VpxEncoder::new(config).unwrap();
     let start = Instant::now();
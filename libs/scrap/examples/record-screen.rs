@@ -105,6 +105,7 @@ fn main() -> io::Result<()> {
         bitrate: args.flag_bv,
         codec: vpx_codec,
         num_threads: 0,
+        i444: false,
     }))
     .unwrap();
 
@@ -0,0 +1,498 @@
+// https://aomedia.googlesource.com/aom/+/refs/heads/main/examples/simple_encoder.c
+// https://aomedia.googlesource.com/aom/+/refs/heads/main/examples/simple_decoder.c
+
+use hbb_common::anyhow::{anyhow, Context};
+use hbb_common::message_proto::{EncodedVideoFrame, EncodedVideoFrames, Message, VideoFrame};
+use hbb_common::{get_time, ResultType};
+
+use crate::damage::DamageTracker;
+use crate::STRIDE_ALIGN;
+use crate::{codec::EncoderApi, ImageFormat};
+
+use super::aom::{aom_codec_err_t::*, aome_enc_control_id::*, *};
+use hbb_common::bytes::Bytes;
+use std::os::raw::{c_int, c_uint};
+use std::{ptr, slice};
+
+pub struct AomEncoder {
+    ctx: aom_codec_ctx_t,
+    width: usize,
+    height: usize,
+    damage: DamageTracker,
+    keyframe: bool,
+}
+
+pub struct AomDecoder {
+    ctx: aom_codec_ctx_t,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    FailedCall(String),
+    BadPtr(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+macro_rules! call_aom {
+    ($x:expr) => {{
+        let result = unsafe { $x }; // original expression
+        let result_int = unsafe { std::mem::transmute::<_, i32>(result) };
+        if result_int != 0 {
+            return Err(Error::FailedCall(format!(
+                "errcode={} {}:{}:{}:{}",
+                result_int,
+                module_path!(),
+                file!(),
+                line!(),
+                column!()
+            ))
+            .into());
+        }
+        result
+    }};
+}
+
+macro_rules! call_aom_ptr {
+    ($x:expr) => {{
+        let result = unsafe { $x }; // original expression
+        let result_int = unsafe { std::mem::transmute::<_, isize>(result) };
+        if result_int == 0 {
+            return Err(Error::BadPtr(format!(
+                "errcode={} {}:{}:{}:{}",
+                result_int,
+                module_path!(),
+                file!(),
+                line!(),
+                column!()
+            ))
+            .into());
+        }
+        result
+    }};
+}
+
+impl EncoderApi for AomEncoder {
+    fn new(cfg: crate::codec::EncoderCfg) -> ResultType<Self>
+    where
+        Self: Sized,
+    {
+        match cfg {
+            crate::codec::EncoderCfg::AOM(config) => {
+                let i = call_aom_ptr!(aom_codec_av1_cx());
+                let mut c = unsafe { std::mem::MaybeUninit::zeroed().assume_init() };
+                call_aom!(aom_codec_enc_config_default(
+                    i,
+                    &mut c,
+                    AOM_USAGE_REALTIME as _
+                ));
+
+                c.g_w = config.width;
+                c.g_h = config.height;
+                c.g_timebase.num = config.timebase[0];
+                c.g_timebase.den = config.timebase[1];
+                c.rc_target_bitrate = config.bitrate;
+                c.rc_undershoot_pct = 95;
+                c.rc_dropframe_thresh = 25;
+                c.g_threads = if config.num_threads == 0 {
+                    num_cpus::get() as _
+                } else {
+                    config.num_threads
+                };
+                c.g_lag_in_frames = 0;
+                c.rc_end_usage = aom_rc_mode::AOM_CBR;
+                c.kf_mode = aom_kf_mode::AOM_KF_DISABLED; // same as vpx, reduce bandwidth a lot
+
+                let mut ctx = Default::default();
+                call_aom!(aom_codec_enc_init_ver(
+                    &mut ctx,
+                    i,
+                    &c,
+                    0,
+                    AOM_ENCODER_ABI_VERSION as _
+                ));
+
+                // 10 is the fastest of the realtime presets, software AV1 is expensive
+                call_aom!(aom_codec_control(&mut ctx, AOME_SET_CPUUSED as _, 10 as c_int));
+                call_aom!(aom_codec_control(&mut ctx, AV1E_SET_ROW_MT as _, 1 as c_uint));
+                call_aom!(aom_codec_control(
+                    &mut ctx,
+                    AV1E_SET_TILE_COLUMNS as _,
+                    2 as c_uint
+                ));
+                // enable the screen content tools, palette and intra block copy
+                call_aom!(aom_codec_control(
+                    &mut ctx,
+                    AV1E_SET_TUNE_CONTENT as _,
+                    aom_tune_content::AOM_CONTENT_SCREEN as c_int
+                ));
+
+                Ok(Self {
+                    ctx,
+                    width: config.width as _,
+                    height: config.height as _,
+                    damage: DamageTracker::new(config.width as _, config.height as _),
+                    keyframe: false,
+                })
+            }
+            _ => Err(anyhow!("encoder type mismatch")),
+        }
+    }
+
+    fn encode_to_message(&mut self, frame: &[u8], ms: i64) -> ResultType<Message> {
+        let mut frames = Vec::new();
+        for ref frame in self
+            .encode(ms, frame, STRIDE_ALIGN)
+            .with_context(|| "Failed to encode")?
+        {
+            frames.push(AomEncoder::create_frame(frame));
+        }
+        for ref frame in self.flush().with_context(|| "Failed to flush")? {
+            frames.push(AomEncoder::create_frame(frame));
+        }
+
+        if frames.len() > 0 {
+            Ok(AomEncoder::create_msg(frames))
+        } else {
+            Err(anyhow!("no valid frame"))
+        }
+    }
+
+    fn use_yuv(&self) -> bool {
+        true
+    }
+
+    fn set_bitrate(&mut self, bitrate: u32) -> ResultType<()> {
+        let mut new_enc_cfg = unsafe { *self.ctx.config.enc.to_owned() };
+        new_enc_cfg.rc_target_bitrate = bitrate;
+        call_aom!(aom_codec_enc_config_set(&mut self.ctx, &new_enc_cfg));
+        return Ok(());
+    }
+
+    fn request_keyframe(&mut self) -> bool {
+        self.keyframe = true;
+        true
+    }
+}
+
+impl AomEncoder {
+    pub fn encode(&mut self, pts: i64, data: &[u8], stride_align: usize) -> Result<EncodeFrames> {
+        if 2 * data.len() < 3 * self.width * self.height {
+            return Err(Error::FailedCall("len not enough".to_string()));
+        }
+
+        let mut image = Default::default();
+        call_aom_ptr!(aom_img_wrap(
+            &mut image,
+            aom_img_fmt::AOM_IMG_FMT_I420,
+            self.width as _,
+            self.height as _,
+            stride_align as _,
+            data.as_ptr() as _,
+        ));
+        self.set_active_map(data, &image)?;
+        let flags = if std::mem::take(&mut self.keyframe) {
+            AOM_EFLAG_FORCE_KF
+        } else {
+            0
+        };
+
+        call_aom!(aom_codec_encode(
+            &mut self.ctx,
+            &image,
+            pts as _,
+            1, // Duration
+            flags as _,
+        ));
+
+        Ok(EncodeFrames {
+            ctx: &mut self.ctx,
+            iter: ptr::null(),
+        })
+    }
+
+    // Skip the blocks unchanged for a while.
+    fn set_active_map(&mut self, data: &[u8], image: &aom_image_t) -> Result<()> {
+        let base = data.as_ptr() as usize;
+        let mut planes = [(0, 0); 3];
+        for (i, plane) in planes.iter_mut().enumerate() {
+            *plane = (image.planes[i] as usize - base, image.stride[i] as usize);
+        }
+        let mut map = aom_active_map_t {
+            active_map: ptr::null_mut(),
+            rows: self.damage.rows() as _,
+            cols: self.damage.cols() as _,
+        };
+        let chroma_shift = image.x_chroma_shift as usize;
+        if let Some(active) = self.damage.update(data, planes, chroma_shift) {
+            map.active_map = active.as_ptr() as _;
+        }
+        call_aom!(aom_codec_control(&mut self.ctx, AOME_SET_ACTIVEMAP as _, &mut map));
+        Ok(())
+    }
+
+    /// Notify the encoder to return any pending packets
+    pub fn flush(&mut self) -> Result<EncodeFrames> {
+        call_aom!(aom_codec_encode(
+            &mut self.ctx,
+            ptr::null(),
+            -1, // PTS
+            1,  // Duration
+            0,  // Flags
+        ));
+
+        Ok(EncodeFrames {
+            ctx: &mut self.ctx,
+            iter: ptr::null(),
+        })
+    }
+
+    #[inline]
+    fn create_msg(av1s: Vec<EncodedVideoFrame>) -> Message {
+        let mut msg_out = Message::new();
+        let mut vf = VideoFrame::new();
+        vf.set_av1s(EncodedVideoFrames {
+            frames: av1s.into(),
+            ..Default::default()
+        });
+        vf.timestamp = get_time();
+        msg_out.set_video_frame(vf);
+        msg_out
+    }
+
+    #[inline]
+    fn create_frame(frame: &EncodeFrame) -> EncodedVideoFrame {
+        EncodedVideoFrame {
+            data: Bytes::from(frame.data.to_vec()),
+            key: frame.key,
+            pts: frame.pts,
+            ..Default::default()
+        }
+    }
+}
+
+impl Drop for AomEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            let result = aom_codec_destroy(&mut self.ctx);
+            if result != AOM_CODEC_OK {
+                panic!("failed to destroy aom codec");
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EncodeFrame<'a> {
+    /// Compressed data.
+    pub data: &'a [u8],
+    /// Whether the frame is a keyframe.
+    pub key: bool,
+    /// Presentation timestamp (in timebase units).
+    pub pts: i64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AomEncoderConfig {
+    /// The width (in pixels).
+    pub width: c_uint,
+    /// The height (in pixels).
+    pub height: c_uint,
+    /// The timebase numerator and denominator (in seconds).
+    pub timebase: [c_int; 2],
+    /// The target bitrate (in kilobits per second).
+    pub bitrate: c_uint,
+    pub num_threads: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AomDecoderConfig {
+    pub num_threads: u32,
+}
+
+pub struct EncodeFrames<'a> {
+    ctx: &'a mut aom_codec_ctx_t,
+    iter: aom_codec_iter_t,
+}
+
+impl<'a> Iterator for EncodeFrames<'a> {
+    type Item = EncodeFrame<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            unsafe {
+                let pkt = aom_codec_get_cx_data(self.ctx, &mut self.iter);
+                if pkt.is_null() {
+                    return None;
+                } else if (*pkt).kind == aom_codec_cx_pkt_kind::AOM_CODEC_CX_FRAME_PKT {
+                    let f = &(*pkt).data.frame;
+                    return Some(Self::Item {
+                        data: slice::from_raw_parts(f.buf as _, f.sz as _),
+                        key: (f.flags & AOM_FRAME_IS_KEY) != 0,
+                        pts: f.pts,
+                    });
+                } else {
+                    // Ignore the packet.
+                }
+            }
+        }
+    }
+}
+
+impl AomDecoder {
+    pub fn new(config: AomDecoderConfig) -> Result<Self> {
+        let i = call_aom_ptr!(aom_codec_av1_dx());
+        let mut ctx = Default::default();
+        let cfg = aom_codec_dec_cfg_t {
+            threads: if config.num_threads == 0 {
+                num_cpus::get() as _
+            } else {
+                config.num_threads
+            },
+            w: 0,
+            h: 0,
+            allow_lowbitdepth: 1,
+        };
+        call_aom!(aom_codec_dec_init_ver(
+            &mut ctx,
+            i,
+            &cfg,
+            0,
+            AOM_DECODER_ABI_VERSION as _,
+        ));
+        Ok(Self { ctx })
+    }
+
+    /// Feed some compressed data to the decoder
+    pub fn decode(&mut self, data: &[u8]) -> Result<DecodeFrames> {
+        call_aom!(aom_codec_decode(
+            &mut self.ctx,
+            data.as_ptr(),
+            data.len() as _,
+            ptr::null_mut(),
+        ));
+
+        Ok(DecodeFrames {
+            ctx: &mut self.ctx,
+            iter: ptr::null(),
+        })
+    }
+
+    /// Notify the decoder to return any pending frame
+    pub fn flush(&mut self) -> Result<DecodeFrames> {
+        call_aom!(aom_codec_decode(
+            &mut self.ctx,
+            ptr::null(),
+            0,
+            ptr::null_mut(),
+        ));
+        Ok(DecodeFrames {
+            ctx: &mut self.ctx,
+            iter: ptr::null(),
+        })
+    }
+}
+
+impl Drop for AomDecoder {
+    fn drop(&mut self) {
+        unsafe {
+            let result = aom_codec_destroy(&mut self.ctx);
+            if result != AOM_CODEC_OK {
+                panic!("failed to destroy aom codec");
+            }
+        }
+    }
+}
+
+pub struct DecodeFrames<'a> {
+    ctx: &'a mut aom_codec_ctx_t,
+    iter: aom_codec_iter_t,
+}
+
+impl<'a> Iterator for DecodeFrames<'a> {
+    type Item = AomImage;
+    fn next(&mut self) -> Option<Self::Item> {
+        let img = unsafe { aom_codec_get_frame(self.ctx, &mut self.iter) };
+        if img.is_null() {
+            return None;
+        } else {
+            return Some(AomImage(img));
+        }
+    }
+}
+
+/// A decoded frame, owned by the decoder and valid until the next call to `decode`.
+pub struct AomImage(*mut aom_image_t);
+
+impl Default for AomImage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AomImage {
+    #[inline]
+    pub fn new() -> Self {
+        Self(std::ptr::null_mut())
+    }
+
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        self.0.is_null()
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.inner().d_w as _
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.inner().d_h as _
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &aom_image_t {
+        unsafe { &*self.0 }
+    }
+
+    pub fn to(&self, fmt: ImageFormat, stride: usize, dst: &mut Vec<u8>) {
+        let h = self.height();
+        let w = self.width();
+        let bytes_per_pixel = match fmt {
+            ImageFormat::Raw => 3,
+            ImageFormat::ARGB | ImageFormat::ABGR => 4,
+        };
+        let bytes_per_row = (w * bytes_per_pixel + stride - 1) & !(stride - 1);
+        dst.resize(h * bytes_per_row, 0);
+        let img = self.inner();
+        unsafe {
+            let f = match fmt {
+                ImageFormat::Raw => super::I420ToRAW,
+                ImageFormat::ARGB => super::I420ToARGB,
+                ImageFormat::ABGR => super::I420ToABGR,
+            };
+            f(
+                img.planes[0],
+                img.stride[0],
+                img.planes[1],
+                img.stride[1],
+                img.planes[2],
+                img.stride[2],
+                dst.as_mut_ptr(),
+                bytes_per_row as _,
+                w as _,
+                h as _,
+            );
+        }
+    }
+}
+
+unsafe impl Send for aom_codec_ctx_t {}
@@ -1,10 +1,11 @@
-use std::ops::{Deref, DerefMut};
-#[cfg(feature = "hwcodec")]
 use std::{
     collections::HashMap,
+    ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
 };
 
+#[cfg(feature = "av1")]
+use crate::aomcodec::*;
 #[cfg(feature = "hwcodec")]
 use crate::hwcodec::*;
 #[cfg(feature = "mediacodec")]
@@ -15,22 +16,28 @@ use crate::{vpxcodec::*, ImageFormat};
 
 use hbb_common::{
     anyhow::anyhow,
-    log,
+    config::PeerConfig,
+    lazy_static, log,
     message_proto::{video_frame, EncodedVideoFrames, Message, VideoCodecState},
     ResultType,
 };
 #[cfg(any(feature = "hwcodec", feature = "mediacodec"))]
-use hbb_common::{
-    config::{Config2, PeerConfig},
-    lazy_static,
-    message_proto::video_codec_state::PreferCodec,
-};
+use hbb_common::config::Config2;
+#[cfg(any(feature = "hwcodec", feature = "mediacodec", feature = "av1"))]
+use hbb_common::message_proto::video_codec_state::PreferCodec;
 
-#[cfg(feature = "hwcodec")]
 lazy_static::lazy_static! {
     static ref PEER_DECODER_STATES: Arc<Mutex<HashMap<i32, VideoCodecState>>> = Default::default();
+    static ref USE_I444: Arc<Mutex<bool>> = Default::default();
+    static ref PEER_CHOICES: Arc<Mutex<HashMap<i32, EncoderChoice>>> = Default::default();
+}
+#[cfg(feature = "av1")]
+lazy_static::lazy_static! {
+    static ref USE_AV1: Arc<Mutex<bool>> = Default::default();
 }
 const SCORE_VPX: i32 = 90;
+// the software encoder is slower than vp9, so it is only used when preferred
+const SCORE_AV1: i32 = 88;
 
 #[derive(Debug, Clone)]
 pub struct HwEncoderConfig {
@@ -44,6 +51,8 @@ pub struct HwEncoderConfig {
 pub enum EncoderCfg {
     VPX(VpxEncoderConfig),
     HW(HwEncoderConfig),
+    #[cfg(feature = "av1")]
+    AOM(AomEncoderConfig),
 }
 
 pub trait EncoderApi {
@@ -56,6 +65,12 @@ pub trait EncoderApi {
     fn use_yuv(&self) -> bool;
 
     fn set_bitrate(&mut self, bitrate: u32) -> ResultType<()>;
+
+    /// Make the next frame a key frame, false if not supported,
+    /// the hardware encoders have a key frame every gop anyway.
+    fn request_keyframe(&mut self) -> bool {
+        false
+    }
 }
 
 pub struct DecoderCfg {
@@ -82,6 +97,8 @@ impl DerefMut for Encoder {
 
 pub struct Decoder {
     vpx: VpxDecoder,
+    #[cfg(feature = "av1")]
+    av1: Option<AomDecoder>,
     #[cfg(feature = "hwcodec")]
     hw: HwDecoders,
     #[cfg(feature = "hwcodec")]
@@ -90,6 +107,15 @@ pub struct Decoder {
     media_codec: MediaCodecDecoders,
 }
 
+/// The encoder negotiated with the decoders of some peers.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EncoderChoice {
+    /// The hardware encoder, none of it and av1 means vp9.
+    pub hw_name: Option<String>,
+    pub av1: bool,
+    pub i444: bool,
+}
+
 #[derive(Debug, Clone)]
 pub enum EncoderUpdate {
     State(VideoCodecState),
@@ -104,6 +130,10 @@ impl Encoder {
             EncoderCfg::VPX(_) => Ok(Encoder {
                 codec: Box::new(VpxEncoder::new(config)?),
             }),
+            #[cfg(feature = "av1")]
+            EncoderCfg::AOM(_) => Ok(Encoder {
+                codec: Box::new(AomEncoder::new(config)?),
+            }),
 
             #[cfg(feature = "hwcodec")]
             EncoderCfg::HW(_) => match HwEncoder::new(config) {
@@ -122,99 +152,178 @@ impl Encoder {
 
     // TODO
     pub fn update_video_encoder(id: i32, update: EncoderUpdate) {
-        #[cfg(feature = "hwcodec")]
-        {
-            let mut states = PEER_DECODER_STATES.lock().unwrap();
-            match update {
-                EncoderUpdate::State(state) => {
-                    states.insert(id, state);
-                }
-                EncoderUpdate::Remove => {
-                    states.remove(&id);
-                }
-                EncoderUpdate::DisableHwIfNotExist => {
-                    if !states.contains_key(&id) {
-                        states.insert(id, VideoCodecState::default());
-                    }
+        let mut states = PEER_DECODER_STATES.lock().unwrap();
+        match update {
+            EncoderUpdate::State(state) => {
+                states.insert(id, state);
+            }
+            EncoderUpdate::Remove => {
+                states.remove(&id);
+            }
+            EncoderUpdate::DisableHwIfNotExist => {
+                if !states.contains_key(&id) {
+                    states.insert(id, VideoCodecState::default());
                 }
             }
-            let name = HwEncoder::current_name();
-            if states.len() > 0 {
+        }
+        match states.get(&id) {
+            Some(state) => {
+                let choice = Self::negotiate_peer(id, state);
+                PEER_CHOICES.lock().unwrap().insert(id, choice);
+            }
+            None => {
+                PEER_CHOICES.lock().unwrap().remove(&id);
+            }
+        }
+        let choice = Self::negotiate(&states);
+        log::info!("connection count:{}, encoder:{:?}", states.len(), choice);
+        *USE_I444.lock().unwrap() = choice.i444;
+        #[cfg(feature = "hwcodec")]
+        {
+            *HwEncoder::current_name().lock().unwrap() = choice.hw_name;
+        }
+        #[cfg(feature = "av1")]
+        {
+            *USE_AV1.lock().unwrap() = choice.av1;
+        }
+    }
+
+    /// The encoder negotiated with all the peers, shared by their connections.
+    pub fn shared_choice() -> EncoderChoice {
+        EncoderChoice {
+            hw_name: Self::current_hw_encoder_name(),
+            av1: Self::use_av1(),
+            i444: Self::use_i444(),
+        }
+    }
+
+    /// The encoder negotiated with a single peer, for the encoder of its own connection.
+    pub fn peer_choice(id: i32) -> EncoderChoice {
+        PEER_CHOICES
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn negotiate_peer(id: i32, state: &VideoCodecState) -> EncoderChoice {
+        let states = HashMap::from([(id, state.clone())]);
+        #[allow(unused_mut)]
+        let mut choice = Self::negotiate(&states);
+        #[cfg(feature = "hwcodec")]
+        if !enable_hwcodec_option() {
+            choice.hw_name = None;
+        }
+        choice
+    }
+
+    fn negotiate(states: &HashMap<i32, VideoCodecState>) -> EncoderChoice {
+        // full chroma is encoded by vp9, only if all the peers ask for it
+        let i444 = states.len() > 0 && states.iter().all(|(_, s)| s.i444);
+        // the hardware encoder name, or av1, none of them means vp9
+        #[allow(unused_mut)]
+        let mut hw_name: Option<String> = None;
+        #[allow(unused_mut)]
+        let mut av1 = false;
+        #[cfg(any(feature = "hwcodec", feature = "av1"))]
+        if states.len() > 0 && !i444 {
+            #[cfg(feature = "hwcodec")]
+            let (best_h264, best_h265) = {
                 let best = HwEncoder::best();
-                let enabled_h264 = best.h264.is_some()
-                    && states.len() > 0
-                    && states.iter().all(|(_, s)| s.score_h264 > 0);
-                let enabled_h265 = best.h265.is_some()
-                    && states.len() > 0
-                    && states.iter().all(|(_, s)| s.score_h265 > 0);
-
-                // Preference first
-                let mut preference = PreferCodec::Auto;
-                let preferences: Vec<_> = states
-                    .iter()
-                    .filter(|(_, s)| {
-                        s.prefer == PreferCodec::VPX.into()
-                            || s.prefer == PreferCodec::H264.into() && enabled_h264
-                            || s.prefer == PreferCodec::H265.into() && enabled_h265
-                    })
-                    .map(|(_, s)| s.prefer)
-                    .collect();
-                if preferences.len() > 0 && preferences.iter().all(|&p| p == preferences[0]) {
-                    preference = preferences[0].enum_value_or(PreferCodec::Auto);
-                }
+                (
+                    best.h264.map(|c| (c.name, c.score)),
+                    best.h265.map(|c| (c.name, c.score)),
+                )
+            };
+            #[cfg(not(feature = "hwcodec"))]
+            let (best_h264, best_h265): (Option<(String, i32)>, Option<(String, i32)>) =
+                (None, None);
+            let enabled_h264 = best_h264.is_some() && states.iter().all(|(_, s)| s.score_h264 > 0);
+            let enabled_h265 = best_h265.is_some() && states.iter().all(|(_, s)| s.score_h265 > 0);
+            let enabled_av1 = cfg!(feature = "av1") && states.iter().all(|(_, s)| s.score_av1 > 0);
+
+            // Preference first
+            let mut preference = PreferCodec::Auto;
+            let preferences: Vec<_> = states
+                .iter()
+                .filter(|(_, s)| {
+                    s.prefer == PreferCodec::VPX.into()
+                        || s.prefer == PreferCodec::H264.into() && enabled_h264
+                        || s.prefer == PreferCodec::H265.into() && enabled_h265
+                        || s.prefer == PreferCodec::AV1.into() && enabled_av1
+                })
+                .map(|(_, s)| s.prefer)
+                .collect();
+            if preferences.len() > 0 && preferences.iter().all(|&p| p == preferences[0]) {
+                preference = preferences[0].enum_value_or(PreferCodec::Auto);
+            }
 
-                match preference {
-                    PreferCodec::VPX => *name.lock().unwrap() = None,
-                    PreferCodec::H264 => {
-                        *name.lock().unwrap() = best.h264.map_or(None, |c| Some(c.name))
+            match preference {
+                PreferCodec::VPX => {}
+                PreferCodec::H264 => hw_name = best_h264.map(|c| c.0),
+                PreferCodec::H265 => hw_name = best_h265.map(|c| c.0),
+                PreferCodec::AV1 => av1 = true,
+                PreferCodec::Auto => {
+                    // score encoder
+                    let mut score_vpx = SCORE_VPX;
+                    let mut score_h264 = best_h264.as_ref().map_or(0, |c| c.1);
+                    let mut score_h265 = best_h265.as_ref().map_or(0, |c| c.1);
+                    let mut score_av1 = if enabled_av1 { SCORE_AV1 } else { 0 };
+
+                    // score decoder
+                    score_vpx += states.iter().map(|s| s.1.score_vpx).sum::<i32>();
+                    if enabled_h264 {
+                        score_h264 += states.iter().map(|s| s.1.score_h264).sum::<i32>();
                     }
-                    PreferCodec::H265 => {
-                        *name.lock().unwrap() = best.h265.map_or(None, |c| Some(c.name))
+                    if enabled_h265 {
+                        score_h265 += states.iter().map(|s| s.1.score_h265).sum::<i32>();
                     }
-                    PreferCodec::Auto => {
-                        // score encoder
-                        let mut score_vpx = SCORE_VPX;
-                        let mut score_h264 = best.h264.as_ref().map_or(0, |c| c.score);
-                        let mut score_h265 = best.h265.as_ref().map_or(0, |c| c.score);
-
-                        // score decoder
-                        score_vpx += states.iter().map(|s| s.1.score_vpx).sum::<i32>();
-                        if enabled_h264 {
-                            score_h264 += states.iter().map(|s| s.1.score_h264).sum::<i32>();
-                        }
-                        if enabled_h265 {
-                            score_h265 += states.iter().map(|s| s.1.score_h265).sum::<i32>();
-                        }
-
-                        if enabled_h265 && score_h265 >= score_vpx && score_h265 >= score_h264 {
-                            *name.lock().unwrap() = best.h265.map_or(None, |c| Some(c.name));
-                        } else if enabled_h264
-                            && score_h264 >= score_vpx
-                            && score_h264 >= score_h265
-                        {
-                            *name.lock().unwrap() = best.h264.map_or(None, |c| Some(c.name));
-                        } else {
-                            *name.lock().unwrap() = None;
-                        }
+                    if enabled_av1 {
+                        score_av1 += states.iter().map(|s| s.1.score_av1).sum::<i32>();
                     }
-                }
 
-                log::info!(
-                    "connection count:{}, used preference:{:?}, encoder:{:?}",
-                    states.len(),
-                    preference,
-                    name.lock().unwrap()
-                )
-            } else {
-                *name.lock().unwrap() = None;
+                    if enabled_h265
+                        && score_h265 >= score_vpx
+                        && score_h265 >= score_h264
+                        && score_h265 >= score_av1
+                    {
+                        hw_name = best_h265.map(|c| c.0);
+                    } else if enabled_h264
+                        && score_h264 >= score_vpx
+                        && score_h264 >= score_h265
+                        && score_h264 >= score_av1
+                    {
+                        hw_name = best_h264.map(|c| c.0);
+                    } else if enabled_av1 && score_av1 > score_vpx {
+                        av1 = true;
+                    }
+                }
             }
+            log::debug!("used preference:{:?}", preference);
         }
-        #[cfg(not(feature = "hwcodec"))]
-        {
-            let _ = id;
-            let _ = update;
+        EncoderChoice {
+            hw_name,
+            av1: av1 && cfg!(feature = "av1"),
+            i444,
         }
     }
+
+    /// Whether the negotiated encoder is VP9 with full chroma.
+    #[inline]
+    pub fn use_i444() -> bool {
+        *USE_I444.lock().unwrap()
+    }
+
+    /// Whether the negotiated encoder is AV1, which has priority over the hardware encoders.
+    #[inline]
+    pub fn use_av1() -> bool {
+        #[cfg(feature = "av1")]
+        return *USE_AV1.lock().unwrap();
+        #[cfg(not(feature = "av1"))]
+        return false;
+    }
+
     #[inline]
     pub fn current_hw_encoder_name() -> Option<String> {
         #[cfg(feature = "hwcodec")]
@@ -241,10 +350,21 @@ impl Encoder {
         #[cfg(not(feature = "hwcodec"))]
         (false, false)
     }
+
+    #[inline]
+    pub fn supported_av1() -> bool {
+        cfg!(feature = "av1")
+    }
+
+    #[inline]
+    pub fn supported_i444() -> bool {
+        true
+    }
 }
 
 impl Decoder {
     pub fn video_codec_state(_id: &str) -> VideoCodecState {
+        let score_av1 = if cfg!(feature = "av1") { SCORE_AV1 } else { 0 };
         #[cfg(feature = "hwcodec")]
         if enable_hwcodec_option() {
             let best = HwDecoder::best();
@@ -252,7 +372,9 @@ impl Decoder {
                 score_vpx: SCORE_VPX,
                 score_h264: best.h264.map_or(0, |c| c.score),
                 score_h265: best.h265.map_or(0, |c| c.score),
+                score_av1,
                 prefer: Self::codec_preference(_id).into(),
+                i444: Self::i444(_id),
                 ..Default::default()
             };
         }
@@ -272,20 +394,40 @@ impl Decoder {
                 score_vpx: SCORE_VPX,
                 score_h264,
                 score_h265,
+                score_av1,
                 prefer: Self::codec_preference(_id).into(),
+                i444: Self::i444(_id),
                 ..Default::default()
             };
         }
         VideoCodecState {
             score_vpx: SCORE_VPX,
+            score_av1,
+            #[cfg(feature = "av1")]
+            prefer: Self::codec_preference(_id).into(),
+            i444: Self::i444(_id),
             ..Default::default()
         }
     }
 
+    // libvpx decodes vp9 profile 1 everywhere.
+    fn i444(id: &str) -> bool {
+        PeerConfig::load(id)
+            .options
+            .get("i444")
+            .map_or(false, |v| !v.is_empty())
+    }
+
     pub fn new(config: DecoderCfg) -> Decoder {
         let vpx = VpxDecoder::new(config.vpx).unwrap();
         Decoder {
             vpx,
+            #[cfg(feature = "av1")]
+            av1: AomDecoder::new(AomDecoderConfig {
+                num_threads: config.vpx.num_threads,
+            })
+            .map_err(|e| log::error!("failed to create av1 decoder: {}", e))
+            .ok(),
             #[cfg(feature = "hwcodec")]
             hw: if enable_hwcodec_option() {
                 HwDecoder::new_decoders()
@@ -313,6 +455,14 @@ impl Decoder {
             video_frame::Union::Vp9s(vp9s) => {
                 Decoder::handle_vp9s_video_frame(&mut self.vpx, vp9s, fmt, rgb)
             }
+            #[cfg(feature = "av1")]
+            video_frame::Union::Av1s(av1s) => {
+                if let Some(decoder) = &mut self.av1 {
+                    Decoder::handle_av1s_video_frame(decoder, av1s, fmt, rgb)
+                } else {
+                    Err(anyhow!("don't support av1!"))
+                }
+            }
             #[cfg(feature = "hwcodec")]
             video_frame::Union::H264s(h264s) => {
                 if let Some(decoder) = &mut self.hw.h264 {
@@ -374,6 +524,28 @@ impl Decoder {
         }
     }
 
+    #[cfg(feature = "av1")]
+    fn handle_av1s_video_frame(
+        decoder: &mut AomDecoder,
+        av1s: &EncodedVideoFrames,
+        fmt: (ImageFormat, usize),
+        rgb: &mut Vec<u8>,
+    ) -> ResultType<bool> {
+        let mut ret = false;
+        for av1 in av1s.frames.iter() {
+            // the image is owned by the decoder, convert it before decoding the next
+            let mut last_frame = AomImage::new();
+            for frame in decoder.decode(&av1.data)? {
+                last_frame = frame;
+            }
+            if !last_frame.is_null() {
+                last_frame.to(fmt.0, fmt.1, rgb);
+                ret = true;
+            }
+        }
+        Ok(ret)
+    }
+
     #[cfg(feature = "hwcodec")]
     fn handle_hw_video_frame(
         decoder: &mut HwDecoder,
@@ -408,7 +580,7 @@ impl Decoder {
         return Ok(false);
     }
 
-    #[cfg(any(feature = "hwcodec", feature = "mediacodec"))]
+    #[cfg(any(feature = "hwcodec", feature = "mediacodec", feature = "av1"))]
     fn codec_preference(id: &str) -> PreferCodec {
         let codec = PeerConfig::load(id)
             .options
@@ -420,6 +592,8 @@ impl Decoder {
             PreferCodec::H264
         } else if codec == "h265" {
             PreferCodec::H265
+        } else if codec == "av1" {
+            PreferCodec::AV1
         } else {
             PreferCodec::Auto
         }
@@ -116,6 +116,54 @@ extern "C" {
         height: c_int,
     ) -> c_int;
 
+    pub fn ARGBToI444(
+        src_bgra: *const u8,
+        src_stride_bgra: c_int,
+        dst_y: *mut u8,
+        dst_stride_y: c_int,
+        dst_u: *mut u8,
+        dst_stride_u: c_int,
+        dst_v: *mut u8,
+        dst_stride_v: c_int,
+        width: c_int,
+        height: c_int,
+    ) -> c_int;
+
+    pub fn I444ToARGB(
+        src_y: *const u8,
+        src_stride_y: c_int,
+        src_u: *const u8,
+        src_stride_u: c_int,
+        src_v: *const u8,
+        src_stride_v: c_int,
+        dst_rgba: *mut u8,
+        dst_stride_rgba: c_int,
+        width: c_int,
+        height: c_int,
+    ) -> c_int;
+
+    pub fn I444ToABGR(
+        src_y: *const u8,
+        src_stride_y: c_int,
+        src_u: *const u8,
+        src_stride_u: c_int,
+        src_v: *const u8,
+        src_stride_v: c_int,
+        dst_rgba: *mut u8,
+        dst_stride_rgba: c_int,
+        width: c_int,
+        height: c_int,
+    ) -> c_int;
+
+    pub fn ARGBToRAW(
+        src_bgra: *const u8,
+        src_stride_bgra: c_int,
+        dst_raw: *mut u8,
+        dst_stride_raw: c_int,
+        width: c_int,
+        height: c_int,
+    ) -> c_int;
+
     pub fn NV12ToARGB(
         src_y: *const u8,
         src_stride_y: c_int,
@@ -145,12 +193,22 @@ fn get_vpx_i420_stride(
     width: usize,
     height: usize,
     stride_align: usize,
+) -> (usize, usize, usize, usize, usize, usize) {
+    get_vpx_stride(vpx_img_fmt::VPX_IMG_FMT_I420, width, height, stride_align)
+}
+
+#[inline]
+fn get_vpx_stride(
+    fmt: vpx_img_fmt_t,
+    width: usize,
+    height: usize,
+    stride_align: usize,
 ) -> (usize, usize, usize, usize, usize, usize) {
     let mut img = Default::default();
     unsafe {
         vpx_img_wrap(
             &mut img,
-            vpx_img_fmt::VPX_IMG_FMT_I420,
+            fmt,
             width as _,
             height as _,
             stride_align as _,
@@ -236,6 +294,30 @@ pub fn bgra_to_i420(width: usize, height: usize, src: &[u8], dst: &mut Vec<u8>)
     }
 }
 
+/// Full chroma, for vp9 profile 1.
+pub fn bgra_to_i444(width: usize, height: usize, src: &[u8], dst: &mut Vec<u8>) {
+    let (_, h, dst_stride_y, dst_stride_uv, u, v) =
+        get_vpx_stride(vpx_img_fmt::VPX_IMG_FMT_I444, width, height, super::STRIDE_ALIGN);
+    dst.resize(h * dst_stride_y * 3, 0);
+    let dst_y = dst.as_mut_ptr();
+    let dst_u = dst[u..].as_mut_ptr();
+    let dst_v = dst[v..].as_mut_ptr();
+    unsafe {
+        ARGBToI444(
+            src.as_ptr(),
+            (src.len() / height) as _,
+            dst_y,
+            dst_stride_y as _,
+            dst_u,
+            dst_stride_uv as _,
+            dst_v,
+            dst_stride_uv as _,
+            width as _,
+            height as _,
+        );
+    }
+}
+
 pub fn rgba_to_i420(width: usize, height: usize, src: &[u8], dst: &mut Vec<u8>) {
     let (_, h, dst_stride_y, dst_stride_uv, u, v) =
         get_vpx_i420_stride(width, height, super::STRIDE_ALIGN);
@@ -0,0 +1,174 @@
+// Tile diff of the YUV frames fed to the encoders.
+// The static blocks are marked inactive in the active map of libvpx/libaom,
+// so the encoder skips them, which costs few bits and little cpu.
+
+/// Size of the blocks of the active map, the macroblocks of vp8/vp9/av1.
+pub const DAMAGE_BLOCK: usize = 16;
+// A block stays active for a while after its last change, to refine its quality.
+const REFINE_FRAMES: u8 = 15;
+
+/// Track the blocks of the frames changed recently.
+pub struct DamageTracker {
+    width: usize,
+    height: usize,
+    last: Vec<u8>,
+    ages: Vec<u8>,
+    map: Vec<u8>,
+}
+
+impl DamageTracker {
+    pub fn new(width: usize, height: usize) -> Self {
+        let n = Self::blocks(width) * Self::blocks(height);
+        Self {
+            width,
+            height,
+            last: Vec::new(),
+            ages: vec![0; n],
+            map: vec![1; n],
+        }
+    }
+
+    #[inline]
+    fn blocks(n: usize) -> usize {
+        (n + DAMAGE_BLOCK - 1) / DAMAGE_BLOCK
+    }
+
+    #[inline]
+    pub fn rows(&self) -> usize {
+        Self::blocks(self.height)
+    }
+
+    #[inline]
+    pub fn cols(&self) -> usize {
+        Self::blocks(self.width)
+    }
+
+    /// Compare a YUV frame with the last one.
+    /// `planes` are the offsets and strides of Y, U and V in `data`,
+    /// U and V are subsampled by `chroma_shift`.
+    ///
+    /// Returns the active map, 1 for the blocks to encode, or `None` if all blocks are active.
+    pub fn update(
+        &mut self,
+        data: &[u8],
+        planes: [(usize, usize); 3],
+        chroma_shift: usize,
+    ) -> Option<&[u8]> {
+        if self.last.len() != data.len() {
+            self.last = data.to_vec();
+            self.ages.iter_mut().for_each(|age| *age = 0);
+            self.map.iter_mut().for_each(|m| *m = 1);
+            return None;
+        }
+        let cols = self.cols();
+        for row in 0..self.rows() {
+            for col in 0..cols {
+                let changed = planes.iter().enumerate().any(|(i, &(offset, stride))| {
+                    let shift = if i == 0 { 0 } else { chroma_shift };
+                    let size = DAMAGE_BLOCK >> shift;
+                    let width = (self.width + shift) >> shift;
+                    let height = (self.height + shift) >> shift;
+                    let x = col * size;
+                    let w = size.min(width - x.min(width));
+                    let top = row * size;
+                    (top..(top + size).min(height)).any(|y| {
+                        let start = offset + y * stride + x;
+                        let range = start..start + w;
+                        data.get(range.clone())
+                            .map_or(true, |b| Some(b) != self.last.get(range))
+                    })
+                });
+                let age = &mut self.ages[row * cols + col];
+                if changed {
+                    *age = 0;
+                } else if *age < REFINE_FRAMES {
+                    *age += 1;
+                }
+            }
+        }
+        self.last.copy_from_slice(data);
+        for (m, age) in self.map.iter_mut().zip(self.ages.iter()) {
+            *m = (*age < REFINE_FRAMES) as u8;
+        }
+        if self.map.iter().all(|m| *m == 1) {
+            None
+        } else {
+            Some(&self.map)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: usize = 64;
+    const H: usize = 32;
+
+    // A yuv frame of W x H with the chroma planes subsampled by `shift`.
+    fn frame(shift: usize) -> (Vec<u8>, [(usize, usize); 3]) {
+        let (cw, ch) = (W >> shift, H >> shift);
+        let planes = [(0, W), (W * H, cw), (W * H + cw * ch, cw)];
+        (vec![0; W * H + 2 * cw * ch], planes)
+    }
+
+    // Feed the same frame until all the blocks are refined.
+    fn settle(t: &mut DamageTracker, data: &[u8], planes: [(usize, usize); 3], shift: usize) {
+        assert!(t.update(data, planes, shift).is_none());
+        for _ in 1..REFINE_FRAMES {
+            assert!(t.update(data, planes, shift).is_none());
+        }
+        let map = t.update(data, planes, shift).unwrap();
+        assert!(map.iter().all(|m| *m == 0));
+    }
+
+    fn active(map: &[u8], cols: usize) -> Vec<(usize, usize)> {
+        map.iter()
+            .enumerate()
+            .filter(|(_, m)| **m == 1)
+            .map(|(i, _)| (i / cols, i % cols))
+            .collect()
+    }
+
+    #[test]
+    fn test_block_diff() {
+        let (mut data, planes) = frame(1);
+        let mut t = DamageTracker::new(W, H);
+        assert_eq!((t.rows(), t.cols()), (2, 4));
+        settle(&mut t, &data, planes, 1);
+        // a pixel of the block at row 1, col 2
+        data[(DAMAGE_BLOCK + 3) * W + 2 * DAMAGE_BLOCK + 5] = 1;
+        assert_eq!(active(t.update(&data, planes, 1).unwrap(), 4), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn test_refine_frames() {
+        let (mut data, planes) = frame(1);
+        let mut t = DamageTracker::new(W, H);
+        settle(&mut t, &data, planes, 1);
+        data[0] = 1;
+        assert_eq!(active(t.update(&data, planes, 1).unwrap(), 4), vec![(0, 0)]);
+        // still active while refining
+        for _ in 0..REFINE_FRAMES - 1 {
+            assert_eq!(active(t.update(&data, planes, 1).unwrap(), 4), vec![(0, 0)]);
+        }
+        assert!(active(t.update(&data, planes, 1).unwrap(), 4).is_empty());
+    }
+
+    #[test]
+    fn test_chroma_shift() {
+        // i420, a chroma pixel covers 2x2 luma pixels
+        let (mut data, planes) = frame(1);
+        let mut t = DamageTracker::new(W, H);
+        settle(&mut t, &data, planes, 1);
+        data[planes[1].0 + 3 * planes[1].1 + 20] = 1;
+        assert_eq!(active(t.update(&data, planes, 1).unwrap(), 4), vec![(0, 2)]);
+
+        // i444, the chroma planes have the size of the luma plane
+        let (mut data, planes) = frame(0);
+        let mut t = DamageTracker::new(W, H);
+        settle(&mut t, &data, planes, 0);
+        data[planes[2].0 + 20 * planes[2].1 + 20] = 1;
+        assert_eq!(active(t.update(&data, planes, 0).unwrap(), 4), vec![(1, 1)]);
+    }
+}
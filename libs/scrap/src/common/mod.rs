@@ -29,8 +29,13 @@ cfg_if! {
     }
 }
 
+#[cfg(feature = "av1")]
+mod aom;
+#[cfg(feature = "av1")]
+pub mod aomcodec;
 pub mod codec;
 pub mod convert;
+pub mod damage;
 #[cfg(feature = "hwcodec")]
 pub mod hwcodec;
 #[cfg(feature = "mediacodec")]
@@ -41,6 +46,10 @@ pub const STRIDE_ALIGN: usize = 64; // commonly used in libvpx vpx_img_alloc cal
 pub const HW_STRIDE_ALIGN: usize = 0; // recommended by av_frame_get_buffer
 
 pub mod record;
+#[cfg(any(x11, dxgi))]
+mod region;
+#[cfg(any(x11, dxgi))]
+pub use self::region::*;
 mod vpx;
 
 #[derive(Copy, Clone)]
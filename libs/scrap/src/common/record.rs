@@ -1,31 +1,48 @@
 #[cfg(feature = "hwcodec")]
 use hbb_common::anyhow::anyhow;
 use hbb_common::{
-    bail, chrono,
+    allow_err, bail, chrono,
     config::Config,
     log,
-    message_proto::{message, video_frame, EncodedVideoFrame, Message},
+    message_proto::{
+        message, video_frame, AudioFrame, EncodedVideoFrame, Message, SessionEvent, VideoFrame,
+    },
+    protobuf::{CodedInputStream, Message as _},
     ResultType,
 };
 #[cfg(feature = "hwcodec")]
 use hwcodec::mux::{MuxContext, Muxer};
 use std::{
     fs::{File, OpenOptions},
-    io,
+    io::{self, BufWriter, Write},
     ops::{Deref, DerefMut},
-    path::PathBuf,
-    sync::mpsc::Sender,
-    time::Instant,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+    },
+    time::{Duration, Instant, SystemTime},
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
 
 const MIN_SECS: u64 = 1;
+const SPLIT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const CLEAN_INTERVAL: Duration = Duration::from_secs(60);
+// longer than any pause of the frames of a recording
+const IN_PROGRESS_AGE: Duration = Duration::from_secs(600);
+const LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const SESSION_LOG_EXT: &str = "events";
+const AUDIO_SIDECAR_EXT: &str = "opus.webm";
+// opus is always decoded at 48k, stereo also plays mono packets
+const OPUS_SAMPLE_RATE: i32 = 48000;
+const OPUS_CHANNELS: i32 = 2;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RecordCodecID {
     VP9,
     H264,
     H265,
+    AV1,
 }
 
 #[derive(Debug, Clone)]
@@ -56,25 +73,134 @@ impl RecorderContext {
         let file = if self.server { "s" } else { "c" }.to_string()
             + &self.id.clone()
             + &chrono::Local::now().format("_%Y%m%d%H%M%S").to_string()
-            + if self.codec_id == RecordCodecID::VP9 {
+            + if self.codec_id == RecordCodecID::VP9 || self.codec_id == RecordCodecID::AV1 {
                 ".webm"
             } else {
                 ".mp4"
             };
-        self.filename = PathBuf::from(&dir).join(file).to_string_lossy().to_string();
+        let mut path = PathBuf::from(&dir).join(&file);
+        // segments of a split recording may start within the same second
+        let mut n = 1;
+        while path.exists() {
+            let (stem, ext) = file.rsplit_once('.').unwrap_or((&file, ""));
+            path = PathBuf::from(&dir).join(format!("{}_{}.{}", stem, n, ext));
+            n += 1;
+        }
+        self.filename = path.to_string_lossy().to_string();
         log::info!("video will save to:{}", self.filename);
         Ok(())
     }
 }
 
+/// Rotation and retention of the recordings, from the options:
+/// `record-split-minutes`, `record-split-size-mb`, `record-max-total-size-mb`,
+/// `record-max-age-days` and `record-max-count`, 0 or empty means no limit.
+#[derive(Debug, Clone, Default)]
+pub struct RecordLimits {
+    pub split_duration: Option<Duration>,
+    pub split_size: Option<u64>,
+    pub max_total_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub max_count: Option<usize>,
+}
+
+impl RecordLimits {
+    pub fn from_config() -> Self {
+        let get = |k: &str| {
+            Config::get_option(k)
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|v| *v > 0)
+        };
+        const MB: u64 = 1024 * 1024;
+        Self {
+            split_duration: get("record-split-minutes").map(|v| Duration::from_secs(v * 60)),
+            split_size: get("record-split-size-mb").map(|v| v * MB),
+            max_total_size: get("record-max-total-size-mb").map(|v| v * MB),
+            max_age: get("record-max-age-days").map(|v| Duration::from_secs(v * 24 * 3600)),
+            max_count: get("record-max-count").map(|v| v as usize),
+        }
+    }
+
+    #[inline]
+    fn has_retention(&self) -> bool {
+        self.max_total_size.is_some() || self.max_age.is_some() || self.max_count.is_some()
+    }
+}
+
+/// Remove the oldest recordings in `dir` until the limits are met, `current` is always kept.
+///
+/// Only the recordings of the same side as `current` are removed, and not the ones
+/// written recently, which may be still in progress in another session.
+pub fn clean_record_dir(dir: &Path, limits: &RecordLimits, current: &str) -> ResultType<()> {
+    let current = PathBuf::from(current);
+    let side = recording_side(&current);
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path == current || side.is_none() || recording_side(&path) != side {
+            continue;
+        }
+        let meta = std::fs::metadata(&path)?;
+        let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        if modified.elapsed().map_or(true, |age| age < IN_PROGRESS_AGE) {
+            continue;
+        }
+        files.push((path, modified, meta.len()));
+    }
+    // oldest first
+    files.sort_by_key(|f| f.1);
+    let current_size = std::fs::metadata(&current).map(|m| m.len()).unwrap_or(0);
+    let mut total: u64 = current_size + files.iter().map(|f| f.2).sum::<u64>();
+    let mut count = files.len() + 1;
+    for (path, modified, len) in files {
+        let too_old = limits.max_age.map_or(false, |max| {
+            modified.elapsed().map_or(false, |age| age > max)
+        });
+        let too_many = limits.max_count.map_or(false, |max| count > max);
+        let too_big = limits.max_total_size.map_or(false, |max| total > max);
+        if !too_old && !too_many && !too_big {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(_) => {
+                log::info!("remove old recording:{}", path.display());
+                std::fs::remove_file(session_log_path(&path.to_string_lossy())).ok();
+                std::fs::remove_file(audio_sidecar_path(&path.to_string_lossy())).ok();
+                total -= len;
+                count -= 1;
+            }
+            Err(e) => log::error!("failed to remove {}: {}", path.display(), e),
+        }
+    }
+    Ok(())
+}
+
+/// `s` or `c` if the file is named by [`RecorderContext::set_filename`].
+fn recording_side(path: &Path) -> Option<char> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    // the audio sidecar goes with its video
+    if (ext == "webm" || ext == "mp4") && stem.contains('_') && !stem.ends_with(".opus") {
+        stem.chars().next().filter(|c| *c == 's' || *c == 'c')
+    } else {
+        None
+    }
+}
+
 unsafe impl Send for Recorder {}
 unsafe impl Sync for Recorder {}
 
+static CLEANING: AtomicBool = AtomicBool::new(false);
+
 pub trait RecorderApi {
     fn new(ctx: RecorderContext) -> ResultType<Self>
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    /// Write an opus packet at `pts` milliseconds of the video timeline.
+    fn write_audio(&mut self, data: &[u8], pts: i64) -> bool;
 }
 
 #[derive(Debug)]
@@ -88,6 +214,16 @@ pub enum RecordState {
 pub struct Recorder {
     pub inner: Box<dyn RecorderApi>,
     ctx: RecorderContext,
+    session_log: Option<BufWriter<File>>,
+    last_log_flush: Instant,
+    last_pts: Option<(i64, Instant)>,
+    // the wall time of pts 0, from the timestamps of the video frames
+    time_offset: Option<i64>,
+    limits: RecordLimits,
+    file_start: Instant,
+    last_split_check: Instant,
+    last_clean: Instant,
+    split_pending: bool,
 }
 
 impl Deref for Recorder {
@@ -107,43 +243,139 @@ impl DerefMut for Recorder {
 impl Recorder {
     pub fn new(mut ctx: RecorderContext) -> ResultType<Self> {
         ctx.set_filename()?;
-        let recorder = match ctx.codec_id {
-            RecordCodecID::VP9 => Recorder {
-                inner: Box::new(WebmRecorder::new(ctx.clone())?),
-                ctx,
-            },
+        let inner: Box<dyn RecorderApi> = match ctx.codec_id {
+            RecordCodecID::VP9 | RecordCodecID::AV1 => Box::new(WebmRecorder::new(ctx.clone())?),
             #[cfg(feature = "hwcodec")]
-            _ => Recorder {
-                inner: Box::new(HwRecorder::new(ctx.clone())?),
-                ctx,
-            },
+            _ => Box::new(HwRecorder::new(ctx.clone())?),
             #[cfg(not(feature = "hwcodec"))]
             _ => bail!("unsupported codec type"),
         };
+        let mut recorder = Recorder {
+            inner,
+            ctx,
+            session_log: None,
+            last_log_flush: Instant::now(),
+            last_pts: None,
+            time_offset: None,
+            limits: RecordLimits::from_config(),
+            file_start: Instant::now(),
+            last_split_check: Instant::now(),
+            last_clean: Instant::now(),
+            split_pending: false,
+        };
         recorder.send_state(RecordState::NewFile(recorder.ctx.filename.clone()));
+        recorder.clean();
         Ok(recorder)
     }
 
     fn change(&mut self, mut ctx: RecorderContext) -> ResultType<()> {
         ctx.set_filename()?;
         self.inner = match ctx.codec_id {
-            RecordCodecID::VP9 => Box::new(WebmRecorder::new(ctx.clone())?),
+            RecordCodecID::VP9 | RecordCodecID::AV1 => Box::new(WebmRecorder::new(ctx.clone())?),
             #[cfg(feature = "hwcodec")]
             _ => Box::new(HwRecorder::new(ctx.clone())?),
             #[cfg(not(feature = "hwcodec"))]
             _ => bail!("unsupported codec type"),
         };
         self.ctx = ctx;
+        // the events of the new file go to a new log
+        self.session_log = None;
+        self.last_pts = None;
+        self.time_offset = None;
+        self.file_start = Instant::now();
+        self.split_pending = false;
         self.send_state(RecordState::NewFile(self.ctx.filename.clone()));
+        self.clean();
         Ok(())
     }
 
+    /// Whether the current file has reached its duration or size limit, and is waiting
+    /// for a key frame to start the next segment, the encoder should be asked for one.
+    #[inline]
+    pub fn split_pending(&self) -> bool {
+        self.split_pending
+    }
+
+    fn check_split(&mut self) {
+        if self.split_pending || self.last_split_check.elapsed() < SPLIT_CHECK_INTERVAL {
+            return;
+        }
+        self.last_split_check = Instant::now();
+        let by_duration = self
+            .limits
+            .split_duration
+            .map_or(false, |d| self.file_start.elapsed() >= d);
+        let by_size = self.limits.split_size.map_or(false, |max| {
+            std::fs::metadata(&self.ctx.filename).map_or(false, |m| m.len() >= max)
+        });
+        if by_duration || by_size {
+            log::info!("recording {} reached its limit, split", self.ctx.filename);
+            self.split_pending = true;
+        }
+    }
+
+    /// Start the next segment if a split is pending and this frame can begin it.
+    fn try_split(&mut self, first: Option<&EncodedVideoFrame>) -> ResultType<()> {
+        self.check_split();
+        if self.split_pending && first.map_or(false, |f| f.key) {
+            // dropping the old inner recorder finalizes the segment
+            self.change(self.ctx.clone())?;
+        }
+        Ok(())
+    }
+
+    // in the background, not to hold up the frames
+    fn clean(&mut self) {
+        self.last_clean = Instant::now();
+        if !self.limits.has_retention() || CLEANING.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let limits = self.limits.clone();
+        let current = self.ctx.filename.clone();
+        std::thread::spawn(move || {
+            if let Some(dir) = PathBuf::from(&current).parent() {
+                if let Err(e) = clean_record_dir(dir, &limits, &current) {
+                    log::error!("failed to clean recordings: {}", e);
+                }
+            }
+            CLEANING.store(false, Ordering::SeqCst);
+        });
+    }
+
     pub fn write_message(&mut self, msg: &Message) {
-        if let Some(message::Union::VideoFrame(vf)) = &msg.union {
-            if let Some(frame) = &vf.union {
-                self.write_frame(frame).ok();
+        match &msg.union {
+            Some(message::Union::VideoFrame(vf)) => {
+                self.write_video_frame(vf).ok();
+            }
+            Some(message::Union::AudioFrame(af)) => {
+                self.write_audio_frame(af);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn write_video_frame(&mut self, vf: &VideoFrame) -> ResultType<()> {
+        if let Some(frame) = &vf.union {
+            self.write_frame(frame)?;
+            if vf.timestamp != 0 {
+                if let Some((pts, _)) = self.last_pts {
+                    self.time_offset = Some(vf.timestamp - pts);
+                }
             }
         }
+        Ok(())
+    }
+
+    /// Audio is placed on the video timeline by its timestamp,
+    /// or by its arrival if the frames have no timestamps.
+    pub fn write_audio_frame(&mut self, af: &AudioFrame) {
+        let pts = match (self.time_offset, self.last_pts) {
+            (Some(offset), _) if af.timestamp != 0 => af.timestamp - offset,
+            (_, Some((pts, time))) => pts + time.elapsed().as_millis() as i64,
+            // no video yet
+            _ => return,
+        };
+        self.inner.write_audio(&af.data, pts);
     }
 
     pub fn write_frame(&mut self, frame: &video_frame::Union) -> ResultType<()> {
@@ -155,7 +387,20 @@ impl Recorder {
                         ..self.ctx.clone()
                     })?;
                 }
+                self.try_split(vp9s.frames.first())?;
                 vp9s.frames.iter().map(|f| self.write_video(f)).count();
+                self.update_pts(vp9s.frames.last());
+            }
+            video_frame::Union::Av1s(av1s) => {
+                if self.ctx.codec_id != RecordCodecID::AV1 {
+                    self.change(RecorderContext {
+                        codec_id: RecordCodecID::AV1,
+                        ..self.ctx.clone()
+                    })?;
+                }
+                self.try_split(av1s.frames.first())?;
+                av1s.frames.iter().map(|f| self.write_video(f)).count();
+                self.update_pts(av1s.frames.last());
             }
             #[cfg(feature = "hwcodec")]
             video_frame::Union::H264s(h264s) => {
@@ -166,7 +411,9 @@ impl Recorder {
                     })?;
                 }
                 if self.ctx.codec_id == RecordCodecID::H264 {
+                    self.try_split(h264s.frames.first())?;
                     h264s.frames.iter().map(|f| self.write_video(f)).count();
+                    self.update_pts(h264s.frames.last());
                 }
             }
             #[cfg(feature = "hwcodec")]
@@ -178,22 +425,148 @@ impl Recorder {
                     })?;
                 }
                 if self.ctx.codec_id == RecordCodecID::H265 {
+                    self.try_split(h265s.frames.first())?;
                     h265s.frames.iter().map(|f| self.write_video(f)).count();
+                    self.update_pts(h265s.frames.last());
                 }
             }
             _ => bail!("unsupported frame type"),
         }
         self.send_state(RecordState::NewFrame);
+        if self.last_log_flush.elapsed() >= LOG_FLUSH_INTERVAL {
+            self.last_log_flush = Instant::now();
+            if let Some(log) = self.session_log.as_mut() {
+                allow_err!(log.flush());
+            }
+        }
+        if self.last_clean.elapsed() >= CLEAN_INTERVAL {
+            self.clean();
+        }
         Ok(())
     }
 
     fn send_state(&self, state: RecordState) {
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
+
+    #[inline]
+    fn update_pts(&mut self, frame: Option<&EncodedVideoFrame>) {
+        if let Some(frame) = frame {
+            self.last_pts = Some((frame.pts, Instant::now()));
+        }
+    }
+
+    /// Append a message of the session to the log next to the video file.
+    ///
+    /// The pts of the event is extrapolated from the last written frame,
+    /// events before the first frame get pts 0, so they are played with the first frame.
+    /// The log is buffered and flushed every second.
+    pub fn write_event(&mut self, conn_id: i32, outgoing: bool, msg: &Message) -> ResultType<()> {
+        let pts = match self.last_pts {
+            Some((pts, time)) => pts + time.elapsed().as_millis() as i64,
+            None => 0,
+        };
+        if self.session_log.is_none() {
+            let path = session_log_path(&self.ctx.filename);
+            self.session_log = Some(BufWriter::new(
+                OpenOptions::new().create(true).append(true).open(&path)?,
+            ));
+            log::info!("session events will save to:{}", path.display());
+        }
+        if let Some(log) = self.session_log.as_mut() {
+            write_session_event(log, pts, conn_id, outgoing, msg)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_session_event(
+    w: &mut impl Write,
+    pts: i64,
+    conn_id: i32,
+    outgoing: bool,
+    msg: &Message,
+) -> ResultType<()> {
+    let event = SessionEvent {
+        pts,
+        conn_id,
+        outgoing,
+        message: Some(msg.clone()).into(),
+        ..Default::default()
+    };
+    w.write_all(&event.write_length_delimited_to_bytes()?)?;
+    Ok(())
+}
+
+/// The session log of the recording `video`.
+pub fn session_log_path(video: &str) -> PathBuf {
+    PathBuf::from(video).with_extension(SESSION_LOG_EXT)
+}
+
+/// Step through the session log of a recording, in pts order.
+pub struct SessionLogPlayer {
+    events: Vec<SessionEvent>,
+    pos: usize,
+}
+
+impl SessionLogPlayer {
+    pub fn open<P: AsRef<Path>>(path: P) -> ResultType<Self> {
+        let buf = std::fs::read(path)?;
+        let mut is = CodedInputStream::from_bytes(&buf);
+        let mut events = Vec::new();
+        while !is.eof()? {
+            match is.read_message::<SessionEvent>() {
+                Ok(event) => events.push(event),
+                Err(e) => {
+                    // the tail may be cut if the recording was interrupted
+                    log::warn!("session log truncated: {}", e);
+                    break;
+                }
+            }
+        }
+        events.sort_by_key(|e| e.pts);
+        Ok(Self { events, pos: 0 })
+    }
+
+    /// Open the session log written next to the recording `video`.
+    pub fn open_for_video(video: &str) -> ResultType<Self> {
+        Self::open(session_log_path(video))
+    }
+
+    pub fn events(&self) -> &[SessionEvent] {
+        &self.events
+    }
+
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Move to the first event at or after `pts`.
+    pub fn seek(&mut self, pts: i64) {
+        self.pos = self.events.partition_point(|e| e.pts < pts);
+    }
+
+    pub fn next_event(&mut self) -> Option<&SessionEvent> {
+        let event = self.events.get(self.pos)?;
+        self.pos += 1;
+        Some(event)
+    }
+
+    /// The events from the current position up to and including `pts`,
+    /// call it with the pts of each displayed frame to follow the video.
+    pub fn step_to(&mut self, pts: i64) -> &[SessionEvent] {
+        let start = self.pos;
+        let end = start + self.events[start..].partition_point(|e| e.pts <= pts);
+        self.pos = end;
+        &self.events[start..end]
+    }
 }
 
 struct WebmRecorder {
     vt: VideoTrack,
+    at: AudioTrack,
+    last_audio_pts: Option<i64>,
     webm: Option<Segment<Writer<File>>>,
     ctx: RecorderContext,
     key: bool,
@@ -221,10 +594,17 @@ impl RecorderApi for WebmRecorder {
             ctx.width as _,
             ctx.height as _,
             None,
-            mux::VideoCodecId::VP9,
+            if ctx.codec_id == RecordCodecID::AV1 {
+                mux::VideoCodecId::AV1
+            } else {
+                mux::VideoCodecId::VP9
+            },
         );
+        let at = add_opus_track(&mut webm);
         Ok(WebmRecorder {
             vt,
+            at,
+            last_audio_pts: None,
             webm: Some(webm),
             ctx,
             key: false,
@@ -249,6 +629,15 @@ impl RecorderApi for WebmRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, data: &[u8], pts: i64) -> bool {
+        // the file starts with a video key frame, and the track must not go back in time
+        if !self.key || pts < 0 || self.last_audio_pts.map_or(false, |last| pts < last) {
+            return false;
+        }
+        self.last_audio_pts = Some(pts);
+        self.at.add_frame(data, pts as u64 * 1_000_000, true)
+    }
 }
 
 impl Drop for WebmRecorder {
@@ -263,6 +652,76 @@ impl Drop for WebmRecorder {
     }
 }
 
+fn add_opus_track(webm: &mut Segment<Writer<File>>) -> AudioTrack {
+    let mut at = webm.add_audio_track(
+        OPUS_SAMPLE_RATE,
+        OPUS_CHANNELS,
+        None,
+        mux::AudioCodecId::Opus,
+    );
+    at.set_codec_private(&opus_head());
+    at
+}
+
+// The CodecPrivate of an opus track, RFC 7845 5.1 with channel mapping family 0.
+fn opus_head() -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(OPUS_CHANNELS as _);
+    head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip, the packets are not trimmed
+    head.extend_from_slice(&(OPUS_SAMPLE_RATE as u32).to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family
+    head
+}
+
+/// The audio of an mp4 recording, the hardware muxer has no audio track.
+pub fn audio_sidecar_path(video: &str) -> PathBuf {
+    PathBuf::from(video).with_extension(AUDIO_SIDECAR_EXT)
+}
+
+#[cfg(feature = "hwcodec")]
+struct AudioSidecar {
+    webm: Option<Segment<Writer<File>>>,
+    at: AudioTrack,
+    path: PathBuf,
+    last_pts: Option<i64>,
+}
+
+#[cfg(feature = "hwcodec")]
+impl AudioSidecar {
+    fn new(video: &str) -> ResultType<Self> {
+        let path = audio_sidecar_path(video);
+        let mut webm = match mux::Segment::new(mux::Writer::new(File::create(&path)?)) {
+            Some(v) => v,
+            None => bail!("Failed to create webm mux"),
+        };
+        let at = add_opus_track(&mut webm);
+        Ok(Self {
+            webm: Some(webm),
+            at,
+            path,
+            last_pts: None,
+        })
+    }
+
+    fn write(&mut self, data: &[u8], pts: i64) -> bool {
+        if pts < 0 || self.last_pts.map_or(false, |last| pts < last) {
+            return false;
+        }
+        self.last_pts = Some(pts);
+        self.at.add_frame(data, pts as u64 * 1_000_000, true)
+    }
+
+    // removed if there is no audio, or no video
+    fn finalize(&mut self, keep: bool) {
+        self.webm.take().map(|webm| webm.finalize(None));
+        if !keep || self.last_pts.is_none() {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
 #[cfg(feature = "hwcodec")]
 struct HwRecorder {
     muxer: Muxer,
@@ -270,6 +729,9 @@ struct HwRecorder {
     written: bool,
     key: bool,
     start: Instant,
+    // the pts of the first video frame, the start of the audio
+    first_pts: Option<i64>,
+    audio: Option<AudioSidecar>,
 }
 
 #[cfg(feature = "hwcodec")]
@@ -283,12 +745,21 @@ impl RecorderApi for HwRecorder {
             framerate: crate::hwcodec::DEFAULT_TIME_BASE[1] as _,
         })
         .map_err(|_| anyhow!("Failed to create hardware muxer"))?;
+        let audio = match AudioSidecar::new(&ctx.filename) {
+            Ok(audio) => Some(audio),
+            Err(e) => {
+                log::error!("Failed to create the audio of the recording: {}", e);
+                None
+            }
+        };
         Ok(HwRecorder {
             muxer,
             ctx,
             written: false,
             key: false,
             start: Instant::now(),
+            first_pts: None,
+            audio,
         })
     }
 
@@ -300,12 +771,20 @@ impl RecorderApi for HwRecorder {
             let ok = self.muxer.write_video(&frame.data, frame.key).is_ok();
             if ok {
                 self.written = true;
+                self.first_pts.get_or_insert(frame.pts);
             }
             ok
         } else {
             false
         }
     }
+
+    fn write_audio(&mut self, data: &[u8], pts: i64) -> bool {
+        match (self.first_pts, self.audio.as_mut()) {
+            (Some(first), Some(audio)) => audio.write(data, pts - first),
+            _ => false,
+        }
+    }
 }
 
 #[cfg(feature = "hwcodec")]
@@ -317,6 +796,46 @@ impl Drop for HwRecorder {
             std::fs::remove_file(&self.ctx.filename).ok();
             state = RecordState::RemoveFile;
         }
+        if let Some(audio) = self.audio.as_mut() {
+            audio.finalize(matches!(state, RecordState::WriteTail));
+        }
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbb_common::message_proto::{Clipboard, KeyEvent};
+
+    #[test]
+    fn test_session_log_player() {
+        let path = std::env::temp_dir().join(format!("record_test_{}.events", std::process::id()));
+        let mut key = Message::new();
+        key.set_key_event(KeyEvent::new());
+        let mut clipboard = Message::new();
+        clipboard.set_clipboard(Clipboard::new());
+        let mut buf = Vec::new();
+        write_session_event(&mut buf, 200, 1, false, &key).unwrap();
+        write_session_event(&mut buf, 0, 1, true, &clipboard).unwrap();
+        write_session_event(&mut buf, 100, 2, false, &key).unwrap();
+        write_session_event(&mut buf, 300, 1, false, &key).unwrap();
+        // an interrupted recording
+        buf.pop();
+        std::fs::write(&path, &buf).unwrap();
+        let mut player = SessionLogPlayer::open(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let pts: Vec<i64> = player.events().iter().map(|e| e.pts).collect();
+        assert_eq!(pts, vec![0, 100, 200]);
+        let first = player.next_event().unwrap();
+        assert!(first.outgoing);
+        assert!(first.message.has_clipboard());
+        assert_eq!(player.step_to(150).len(), 1);
+        assert_eq!(player.position(), 2);
+        assert!(player.step_to(150).is_empty());
+        player.seek(100);
+        assert_eq!(player.next_event().map(|e| e.conn_id), Some(2));
+        assert_eq!(player.step_to(1000).len(), 1);
+        assert!(player.next_event().is_none());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{bgra_to_i420, Frame, TraitCapturer};
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A rectangle of a display, relative to the origin of the display.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureRegion {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Crop the frames of a display to a region.
+///
+/// The region may be moved while capturing, e.g. to follow a window,
+/// but it keeps its size, which is the size of the frames.
+pub struct RegionCapturer {
+    inner: Box<dyn TraitCapturer>,
+    display_height: usize,
+    region: Arc<Mutex<CaptureRegion>>,
+    use_yuv: bool,
+    bgra: Vec<u8>,
+    yuv: Vec<u8>,
+}
+
+impl RegionCapturer {
+    pub fn new(
+        mut inner: Box<dyn TraitCapturer>,
+        display_height: usize,
+        region: Arc<Mutex<CaptureRegion>>,
+        use_yuv: bool,
+    ) -> Self {
+        // crop the bgra, then convert the smaller image
+        inner.set_use_yuv(false);
+        Self {
+            inner,
+            display_height,
+            region,
+            use_yuv,
+            bgra: Vec::new(),
+            yuv: Vec::new(),
+        }
+    }
+}
+
+impl TraitCapturer for RegionCapturer {
+    fn set_use_yuv(&mut self, use_yuv: bool) {
+        self.use_yuv = use_yuv;
+    }
+
+    fn frame<'a>(&'a mut self, timeout: Duration) -> io::Result<Frame<'a>> {
+        let region = *self.region.lock().unwrap();
+        let frame = self.inner.frame(timeout)?;
+        if self.display_height == 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "empty display"));
+        }
+        let stride = frame.len() / self.display_height;
+        let row = region.width * 4;
+        if region.width == 0
+            || region.height == 0
+            || region.y + region.height > self.display_height
+            || region.x * 4 + row > stride
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("region {:?} out of the display", region),
+            ));
+        }
+        self.bgra.resize(row * region.height, 0);
+        for (i, dst) in self.bgra.chunks_exact_mut(row).enumerate() {
+            let start = (region.y + i) * stride + region.x * 4;
+            dst.copy_from_slice(&frame[start..start + row]);
+        }
+        if self.use_yuv {
+            bgra_to_i420(region.width, region.height, &self.bgra, &mut self.yuv);
+            Ok(Frame(&self.yuv))
+        } else {
+            Ok(Frame(&self.bgra))
+        }
+    }
+
+    #[cfg(windows)]
+    fn is_gdi(&self) -> bool {
+        self.inner.is_gdi()
+    }
+
+    #[cfg(windows)]
+    fn set_gdi(&mut self) -> bool {
+        self.inner.set_gdi()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: usize = 8;
+    const H: usize = 6;
+
+    // A bgra display of W x H, each pixel holds its own position.
+    struct Display(Vec<u8>);
+
+    impl TraitCapturer for Display {
+        fn set_use_yuv(&mut self, _use_yuv: bool) {}
+
+        fn frame<'a>(&'a mut self, _timeout: Duration) -> io::Result<Frame<'a>> {
+            Ok(Frame(&self.0))
+        }
+
+        #[cfg(windows)]
+        fn is_gdi(&self) -> bool {
+            false
+        }
+
+        #[cfg(windows)]
+        fn set_gdi(&mut self) -> bool {
+            false
+        }
+    }
+
+    fn capturer(region: CaptureRegion) -> RegionCapturer {
+        let data = (0..W * H)
+            .flat_map(|i| [(i % W) as u8, (i / W) as u8, 0, 255])
+            .collect();
+        RegionCapturer::new(Box::new(Display(data)), H, Arc::new(Mutex::new(region)), false)
+    }
+
+    #[test]
+    fn test_crop() {
+        let region = CaptureRegion {
+            x: 2,
+            y: 1,
+            width: 4,
+            height: 2,
+        };
+        let mut c = capturer(region);
+        let frame = c.frame(Duration::ZERO).unwrap();
+        assert_eq!(frame.len(), 4 * 2 * 4);
+        assert_eq!(&frame[..4], &[2, 1, 0, 255]);
+        assert_eq!(&frame[frame.len() - 4..], &[5, 2, 0, 255]);
+    }
+
+    #[test]
+    fn test_out_of_display() {
+        let regions = [
+            (W - 2, 0, 4, 2),
+            (0, H - 1, 2, 2),
+            (0, 0, W + 2, H),
+            (0, 0, 0, 2),
+            (0, 0, 2, 0),
+        ];
+        for (x, y, width, height) in regions {
+            let mut c = capturer(CaptureRegion {
+                x,
+                y,
+                width,
+                height,
+            });
+            assert!(c.frame(Duration::ZERO).is_err());
+        }
+        let mut c = capturer(CaptureRegion {
+            x: 0,
+            y: 0,
+            width: W,
+            height: H,
+        });
+        assert_eq!(c.frame(Duration::ZERO).unwrap().len(), W * H * 4);
+    }
+}
@@ -6,13 +6,18 @@ use hbb_common::anyhow::{anyhow, Context};
 use hbb_common::message_proto::{EncodedVideoFrame, EncodedVideoFrames, Message, VideoFrame};
 use hbb_common::{get_time, ResultType};
 
+use crate::damage::DamageTracker;
 use crate::STRIDE_ALIGN;
-use crate::{codec::EncoderApi, ImageFormat};
+use crate::{bgra_to_i444, codec::EncoderApi, ImageFormat};
 
 use super::vpx::{vp8e_enc_control_id::*, vpx_codec_err_t::*, *};
 use hbb_common::bytes::Bytes;
 use std::os::raw::{c_int, c_uint};
-use std::{ptr, slice};
+use std::{cell::RefCell, ptr, slice};
+
+thread_local! {
+    static ARGB_BUF: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+}
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum VpxVideoCodecId {
@@ -30,6 +35,10 @@ pub struct VpxEncoder {
     ctx: vpx_codec_ctx_t,
     width: usize,
     height: usize,
+    i444: bool,
+    yuv: Vec<u8>,
+    damage: DamageTracker,
+    keyframe: bool,
 }
 
 pub struct VpxDecoder {
@@ -108,6 +117,11 @@ impl EncoderApi for VpxEncoder {
                 }
                 let mut c = unsafe { std::mem::MaybeUninit::zeroed().assume_init() };
                 call_vpx!(vpx_codec_enc_config_default(i, &mut c, 0));
+                // profile 1 of vp9 supports 4:4:4
+                let i444 = config.i444 && config.codec == VpxVideoCodecId::VP9;
+                if i444 {
+                    c.g_profile = 1;
+                }
 
                 // https://www.webmproject.org/docs/encoder-parameters/
                 // default: c.rc_min_quantizer = 0, c.rc_max_quantizer = 63
@@ -187,12 +201,25 @@ impl EncoderApi for VpxEncoder {
                         VP9E_SET_TILE_COLUMNS as _,
                         4 as c_int
                     ));
+
+                    if i444 {
+                        // sharper text and lines
+                        call_vpx!(vpx_codec_control_(
+                            &mut ctx,
+                            VP9E_SET_TUNE_CONTENT as _,
+                            vp9e_tune_content::VP9E_CONTENT_SCREEN as c_int
+                        ));
+                    }
                 }
 
                 Ok(Self {
                     ctx,
                     width: config.width as _,
                     height: config.height as _,
+                    i444,
+                    yuv: Vec::new(),
+                    damage: DamageTracker::new(config.width as _, config.height as _),
+                    keyframe: false,
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -200,6 +227,14 @@ impl EncoderApi for VpxEncoder {
     }
 
     fn encode_to_message(&mut self, frame: &[u8], ms: i64) -> ResultType<Message> {
+        // the capturers convert to 4:2:0, convert the bgra here for 4:4:4
+        let mut yuv = std::mem::take(&mut self.yuv);
+        let frame = if self.i444 {
+            bgra_to_i444(self.width, self.height, frame, &mut yuv);
+            &yuv[..]
+        } else {
+            frame
+        };
         let mut frames = Vec::new();
         for ref frame in self
             .encode(ms, frame, STRIDE_ALIGN)
@@ -210,6 +245,7 @@ impl EncoderApi for VpxEncoder {
         for ref frame in self.flush().with_context(|| "Failed to flush")? {
             frames.push(VpxEncoder::create_frame(frame));
         }
+        self.yuv = yuv;
 
         // to-do: flush periodically, e.g. 1 second
         if frames.len() > 0 {
@@ -220,7 +256,7 @@ impl EncoderApi for VpxEncoder {
     }
 
     fn use_yuv(&self) -> bool {
-        true
+        !self.i444
     }
 
     fn set_bitrate(&mut self, bitrate: u32) -> ResultType<()> {
@@ -229,30 +265,46 @@ impl EncoderApi for VpxEncoder {
         call_vpx!(vpx_codec_enc_config_set(&mut self.ctx, &new_enc_cfg));
         return Ok(());
     }
+
+    fn request_keyframe(&mut self) -> bool {
+        self.keyframe = true;
+        true
+    }
 }
 
 impl VpxEncoder {
     pub fn encode(&mut self, pts: i64, data: &[u8], stride_align: usize) -> Result<EncodeFrames> {
-        if 2 * data.len() < 3 * self.width * self.height {
+        let (fmt, n) = if self.i444 {
+            (vpx_img_fmt::VPX_IMG_FMT_I444, 2)
+        } else {
+            (vpx_img_fmt::VPX_IMG_FMT_I420, 1)
+        };
+        if 2 * data.len() < 3 * n * self.width * self.height {
             return Err(Error::FailedCall("len not enough".to_string()));
         }
 
         let mut image = Default::default();
         call_vpx_ptr!(vpx_img_wrap(
             &mut image,
-            vpx_img_fmt::VPX_IMG_FMT_I420,
+            fmt,
             self.width as _,
             self.height as _,
             stride_align as _,
             data.as_ptr() as _,
         ));
+        self.set_active_map(data, &image)?;
+        let flags = if std::mem::take(&mut self.keyframe) {
+            VPX_EFLAG_FORCE_KF
+        } else {
+            0
+        };
 
         call_vpx!(vpx_codec_encode(
             &mut self.ctx,
             &image,
             pts as _,
             1, // Duration
-            0, // Flags
+            flags as _,
             VPX_DL_REALTIME as _,
         ));
 
@@ -262,6 +314,26 @@ impl VpxEncoder {
         })
     }
 
+    // Skip the blocks unchanged for a while.
+    fn set_active_map(&mut self, data: &[u8], image: &vpx_image_t) -> Result<()> {
+        let base = data.as_ptr() as usize;
+        let mut planes = [(0, 0); 3];
+        for (i, plane) in planes.iter_mut().enumerate() {
+            *plane = (image.planes[i] as usize - base, image.stride[i] as usize);
+        }
+        let mut map = vpx_active_map_t {
+            active_map: ptr::null_mut(),
+            rows: self.damage.rows() as _,
+            cols: self.damage.cols() as _,
+        };
+        let chroma_shift = image.x_chroma_shift as usize;
+        if let Some(active) = self.damage.update(data, planes, chroma_shift) {
+            map.active_map = active.as_ptr() as _;
+        }
+        call_vpx!(vpx_codec_control_(&mut self.ctx, VP8E_SET_ACTIVEMAP as _, &mut map));
+        Ok(())
+    }
+
     /// Notify the encoder to return any pending packets
     pub fn flush(&mut self) -> Result<EncodeFrames> {
         call_vpx!(vpx_codec_encode(
@@ -337,6 +409,8 @@ pub struct VpxEncoderConfig {
     /// The codec
     pub codec: VpxVideoCodecId,
     pub num_threads: u32,
+    /// Encode 4:4:4 with vp9 profile 1, the frames are bgra.
+    pub i444: bool,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -551,6 +625,10 @@ impl Image {
         let bytes_per_row = (w * bytes_per_pixel + stride - 1) & !(stride - 1);
         dst.resize(h * bytes_per_row, 0);
         let img = self.inner();
+        if img.fmt == vpx_img_fmt::VPX_IMG_FMT_I444 {
+            self.i444_to(fmt, bytes_per_row, dst);
+            return;
+        }
         unsafe {
             match fmt {
                 ImageFormat::Raw => {
@@ -599,6 +677,69 @@ impl Image {
         }
     }
 
+    // vp9 profile 1
+    fn i444_to(&self, fmt: ImageFormat, bytes_per_row: usize, dst: &mut Vec<u8>) {
+        let img = self.inner();
+        let (w, h) = (self.width(), self.height());
+        unsafe {
+            match fmt {
+                ImageFormat::Raw => ARGB_BUF.with(|argb| {
+                    // no I444ToRAW, convert through argb, the buffer is kept for the next frames
+                    let mut argb = argb.borrow_mut();
+                    argb.resize(w * h * 4, 0);
+                    super::I444ToARGB(
+                        img.planes[0],
+                        img.stride[0],
+                        img.planes[1],
+                        img.stride[1],
+                        img.planes[2],
+                        img.stride[2],
+                        argb.as_mut_ptr(),
+                        (w * 4) as _,
+                        w as _,
+                        h as _,
+                    );
+                    super::ARGBToRAW(
+                        argb.as_ptr(),
+                        (w * 4) as _,
+                        dst.as_mut_ptr(),
+                        bytes_per_row as _,
+                        w as _,
+                        h as _,
+                    );
+                }),
+                ImageFormat::ARGB => {
+                    super::I444ToARGB(
+                        img.planes[0],
+                        img.stride[0],
+                        img.planes[1],
+                        img.stride[1],
+                        img.planes[2],
+                        img.stride[2],
+                        dst.as_mut_ptr(),
+                        bytes_per_row as _,
+                        w as _,
+                        h as _,
+                    );
+                }
+                ImageFormat::ABGR => {
+                    super::I444ToABGR(
+                        img.planes[0],
+                        img.stride[0],
+                        img.planes[1],
+                        img.stride[1],
+                        img.planes[2],
+                        img.stride[2],
+                        dst.as_mut_ptr(),
+                        bytes_per_row as _,
+                        w as _,
+                        h as _,
+                    );
+                }
+            }
+        }
+    }
+
     #[inline]
     pub fn data(&self) -> (&[u8], &[u8], &[u8]) {
         unsafe {
@@ -606,7 +747,7 @@ impl Image {
             let h = (img.d_h as usize + 1) & !1;
             let n = img.stride[0] as usize * h;
             let y = slice::from_raw_parts(img.planes[0], n);
-            let n = img.stride[1] as usize * (h >> 1);
+            let n = img.stride[1] as usize * (h >> img.y_chroma_shift);
             let u = slice::from_raw_parts(img.planes[1], n);
             let v = slice::from_raw_parts(img.planes[2], n);
             (y, u, v)
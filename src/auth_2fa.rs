@@ -0,0 +1,172 @@
+// TOTP (RFC 6238) second factor after the password.
+// The secret is in the option "2fa", encrypted by encrypt_str_or_original.
+
+use hbb_common::{
+    anyhow::anyhow,
+    config::{Config, PASSWORD_ENC_VERSION},
+    lazy_static, log,
+    password_security::{decrypt_str_or_original, encrypt_str_or_original},
+    ResultType,
+};
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+pub const OPTION: &str = "2fa";
+
+lazy_static::lazy_static! {
+    // generated, not enabled until a code of it is confirmed
+    static ref PENDING: Mutex<Option<TOTP>> = Default::default();
+    // The time step of the last accepted code, which is not accepted again.
+    // Shared by all the peers on purpose: the id is told by the peer, so keyed by it a code
+    // seen on the network could be replayed under another id. Two logins within one step
+    // have to wait for the next code.
+    static ref LAST_STEP: Mutex<u64> = Default::default();
+}
+
+fn new_totp(secret: Vec<u8>) -> ResultType<TOTP> {
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(crate::get_app_name()),
+        Config::get_id(),
+    )
+    .map_err(|err| anyhow!("{:?}", err))
+}
+
+/// A new secret for the authenticator app, returns its otpauth uri.
+pub fn generate() -> ResultType<String> {
+    let secret = Secret::generate_secret()
+        .to_bytes()
+        .map_err(|err| anyhow!("{:?}", err))?;
+    let totp = new_totp(secret)?;
+    let uri = totp.get_url();
+    *PENDING.lock().unwrap() = Some(totp);
+    Ok(uri)
+}
+
+/// The value of the option enabling the generated secret, if the code matches.
+pub fn confirm(code: &str) -> Option<String> {
+    let mut pending = PENDING.lock().unwrap();
+    if !pending.as_ref().map_or(false, |totp| check(totp, code)) {
+        return None;
+    }
+    pending
+        .take()
+        .map(|totp| encrypt_str_or_original(&totp.get_secret_base32(), PASSWORD_ENC_VERSION))
+}
+
+pub fn is_enabled() -> bool {
+    !Config::get_option(OPTION).is_empty()
+}
+
+pub fn verify(code: &str) -> bool {
+    let (secret, decrypted, _) =
+        decrypt_str_or_original(&Config::get_option(OPTION), PASSWORD_ENC_VERSION);
+    if !decrypted {
+        log::error!("Failed to decrypt the secret of 2FA");
+        return false;
+    }
+    let totp = Secret::Encoded(secret)
+        .to_bytes()
+        .map_err(|err| anyhow!("{:?}", err))
+        .and_then(new_totp);
+    match totp {
+        Ok(totp) => check_once(&totp, code),
+        Err(err) => {
+            log::error!("Invalid secret of 2FA: {}", err);
+            false
+        }
+    }
+}
+
+fn check(totp: &TOTP, code: &str) -> bool {
+    totp.check_current(code.trim()).unwrap_or(false)
+}
+
+// Like `check`, but a code seen on the network can not be replayed within its time steps.
+fn check_once(totp: &TOTP, code: &str) -> bool {
+    let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => return false,
+    };
+    check_at(totp, code, now, &mut LAST_STEP.lock().unwrap())
+}
+
+// `now` in seconds, `last` is the step of the last accepted code.
+fn check_at(totp: &TOTP, code: &str, now: u64, last: &mut u64) -> bool {
+    let step = now / totp.step;
+    // the skew of one step
+    for s in [step.saturating_sub(1), step, step + 1] {
+        if s > *last && totp.generate(s * totp.step) == code.trim() {
+            *last = s;
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_010;
+
+    fn totp() -> TOTP {
+        TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            b"0123456789abcdef0123".to_vec(),
+            None,
+            "test".to_owned(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_check_at() {
+        let totp = totp();
+        let step = NOW / 30;
+        let code = totp.generate(NOW);
+        let mut last = 0;
+        assert!(check_at(&totp, &format!(" {} ", code), NOW, &mut last));
+        assert_eq!(last, step);
+        // replayed, also in the next step where it is still within the skew
+        assert!(!check_at(&totp, &code, NOW, &mut last));
+        assert!(!check_at(&totp, &code, NOW + 30, &mut last));
+        // older than the last accepted one
+        assert!(!check_at(&totp, &totp.generate(NOW - 30), NOW, &mut last));
+        assert!(check_at(&totp, &totp.generate(NOW + 30), NOW, &mut last));
+        assert_eq!(last, step + 1);
+
+        // the skew of one step, not two
+        let mut last = 0;
+        assert!(check_at(&totp, &totp.generate(NOW - 30), NOW, &mut last));
+        assert_eq!(last, step - 1);
+        assert!(!check_at(&totp, &totp.generate(NOW - 60), NOW, &mut last));
+        assert!(!check_at(&totp, &totp.generate(NOW + 60), NOW, &mut last));
+        assert!(!check_at(&totp, "abcdef", NOW, &mut last));
+    }
+
+    #[test]
+    fn test_confirm() {
+        let totp = totp();
+        *PENDING.lock().unwrap() = Some(totp.clone());
+        assert!(confirm("abcdef").is_none());
+        assert!(PENDING.lock().unwrap().is_some());
+        let enabled = confirm(&totp.generate_current().unwrap()).unwrap();
+        assert!(PENDING.lock().unwrap().is_none());
+        let (secret, decrypted, _) = decrypt_str_or_original(&enabled, PASSWORD_ENC_VERSION);
+        assert!(decrypted);
+        assert_eq!(secret, totp.get_secret_base32());
+        // the secret is enabled once
+        assert!(confirm(&totp.generate_current().unwrap()).is_none());
+    }
+}
@@ -1,17 +1,32 @@
 use crate::client::*;
+pub use crate::port_forward::ForwardTarget;
 use hbb_common::{
     config::PeerConfig,
     config::READ_TIMEOUT,
+    fs::{self, can_enable_overwrite_detection, get_string, DigestCheckResult},
     futures::{SinkExt, StreamExt},
     log,
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
-    tokio::{self, sync::mpsc},
+    tokio::{
+        self,
+        sync::mpsc,
+        time::{self, Duration, Instant},
+    },
     Stream,
 };
 use std::sync::{Arc, RwLock};
 
+/// Exit codes of the `push` and `pull` subcommands.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_TRANSFER_ERROR: i32 = 1;
+pub const EXIT_CONNECT_ERROR: i32 = 2;
+pub const EXIT_LOGIN_ERROR: i32 = 3;
+
+/// The password of the remote if not given on the command line.
+pub const PASSWORD_ENV: &str = "RUSTDESK_PASSWORD";
+
 #[derive(Clone)]
 pub struct Session {
     id: String,
@@ -21,10 +36,17 @@ pub struct Session {
 }
 
 impl Session {
-    pub fn new(id: &str, sender: mpsc::UnboundedSender<Data>) -> Self {
-        let mut password = "".to_owned();
-        if PeerConfig::load(id).password.is_empty() {
-            password = rpassword::prompt_password("Enter password: ").unwrap();
+    pub fn new(
+        id: &str,
+        sender: mpsc::UnboundedSender<Data>,
+        conn_type: ConnType,
+        password: Option<String>,
+    ) -> Self {
+        let mut password = password
+            .or_else(|| std::env::var(PASSWORD_ENV).ok())
+            .unwrap_or_default();
+        if password.is_empty() && PeerConfig::load(id).password.is_empty() {
+            password = prompt_password().unwrap_or_default();
         }
         let session = Self {
             id: id.to_owned(),
@@ -36,9 +58,57 @@ impl Session {
             .lc
             .write()
             .unwrap()
-            .initialize(id.to_owned(), ConnType::PORT_FORWARD, None);
+            .initialize(id.to_owned(), conn_type, None);
         session
     }
+
+    // close with the login error instead of waiting for a code never entered
+    fn send_2fa_or_close(&self, code: Option<String>) {
+        match code {
+            Some(code) if !code.trim().is_empty() => {
+                self.sender.send(Data::Message(auth_2fa_msg(code))).ok();
+            }
+            _ => {
+                eprintln!("No 2FA code");
+                self.sender.send(Data::Close).ok();
+            }
+        }
+    }
+
+    // close instead of retrying forever if there is nothing to log in with
+    fn login_or_close(&self, password: Option<String>) {
+        match password {
+            Some(password) if !password.is_empty() => {
+                self.sender.send(Data::Login((password, true))).ok();
+            }
+            _ => {
+                eprintln!("No password");
+                self.sender.send(Data::Close).ok();
+            }
+        }
+    }
+}
+
+// None without a terminal
+fn prompt_2fa() -> Option<String> {
+    match rpassword::prompt_password("Enter 2FA code: ") {
+        Ok(code) => Some(code),
+        Err(err) => {
+            log::error!("Failed to read the 2FA code: {}", err);
+            None
+        }
+    }
+}
+
+// None without a terminal
+fn prompt_password() -> Option<String> {
+    match rpassword::prompt_password("Enter password: ") {
+        Ok(password) => Some(password),
+        Err(err) => {
+            log::error!("Failed to read the password: {}", err);
+            None
+        }
+    }
 }
 
 #[async_trait]
@@ -49,13 +119,13 @@ impl Interface for Session {
 
     fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str) {
         if msgtype == "input-password" {
-            self.sender
-                .send(Data::Login((self.password.clone(), true)))
-                .ok();
+            self.login_or_close(Some(self.password.clone()));
         } else if msgtype == "re-input-password" {
             log::error!("{}: {}", title, text);
-            let pass = rpassword::prompt_password("Enter password: ").unwrap();
-            self.sender.send(Data::Login((pass, true))).ok();
+            self.login_or_close(prompt_password());
+        } else if msgtype == "input-2fa" {
+            eprintln!("{}", title);
+            self.send_2fa_or_close(prompt_2fa());
         } else if msgtype.contains("error") {
             log::error!("{}: {}: {}", msgtype, title, text);
         } else {
@@ -95,7 +165,7 @@ impl Interface for Session {
 #[tokio::main(flavor = "current_thread")]
 pub async fn connect_test(id: &str, key: String, token: String) {
     let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
+    let handler = Session::new(&id, sender, ConnType::PORT_FORWARD, None);
     match crate::client::Client::start(id, &key, &token, ConnType::PORT_FORWARD, handler).await {
         Err(err) => {
             log::error!("Failed to connect {}: {}", &id, err);
@@ -128,23 +198,440 @@ pub async fn connect_test(id: &str, key: String, token: String) {
     }
 }
 
+/// Parse `remote-id:local-port:remote-port[:remote-host]`,
+/// or `remote-id:local-port:socks5` for a dynamic (SOCKS5) forward.
+pub fn parse_port_forward(s: &str) -> hbb_common::ResultType<(String, i32, ForwardTarget)> {
+    let options: Vec<&str> = s.trim().splitn(4, ':').collect();
+    if options.len() < 3 {
+        hbb_common::bail!("Wrong port-forward options: {}", s);
+    }
+    let port = match options[1].parse::<i32>() {
+        Ok(v) => v,
+        Err(_) => hbb_common::bail!("Wrong local-port: {}", options[1]),
+    };
+    if options[2] == "socks5" {
+        return Ok((options[0].to_owned(), port, ForwardTarget::Dynamic));
+    }
+    let remote_port = match options[2].parse::<i32>() {
+        Ok(v) => v,
+        Err(_) => hbb_common::bail!("Wrong remote-port: {}", options[2]),
+    };
+    let remote_host = options.get(3).unwrap_or(&"localhost").to_string();
+    Ok((
+        options[0].to_owned(),
+        port,
+        ForwardTarget::Static(remote_host, remote_port),
+    ))
+}
+
+/// Parse `remote-id:remote-port:local-port[:local-host]` of a reverse forward.
+pub fn parse_reverse_forward(s: &str) -> hbb_common::ResultType<(String, i32, String, i32)> {
+    let options: Vec<&str> = s.trim().splitn(4, ':').collect();
+    if options.len() < 3 {
+        hbb_common::bail!("Wrong reverse-forward options: {}", s);
+    }
+    let remote_port = match options[1].parse::<i32>() {
+        Ok(v) if v > 0 => v,
+        _ => hbb_common::bail!("Wrong remote-port: {}", options[1]),
+    };
+    let local_port = match options[2].parse::<i32>() {
+        Ok(v) => v,
+        Err(_) => hbb_common::bail!("Wrong local-port: {}", options[2]),
+    };
+    let local_host = options.get(3).unwrap_or(&"localhost").to_string();
+    Ok((options[0].to_owned(), remote_port, local_host, local_port))
+}
+
+/// Read port-forward options from a file, one per line, `#` starts a comment line.
+pub fn load_port_forwards(path: &str) -> hbb_common::ResultType<Vec<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_owned())
+        .collect())
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_port_forwards(
+    id: String,
+    forwards: Vec<(i32, ForwardTarget)>,
+    bind: Option<String>,
+    key: String,
+    token: String,
+) {
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, sender, ConnType::PORT_FORWARD, None);
+    if let Err(err) = crate::port_forward::listen_multiple(
+        handler.id.clone(),
+        handler.password.clone(),
+        forwards,
+        bind.unwrap_or_else(crate::port_forward::bind_address),
+        handler.clone(),
+        receiver,
+        &key,
+        &token,
+        handler.lc.clone(),
+    )
+    .await
+    {
+        log::error!("Port forward error: {}", err);
+    }
+    log::info!("port forward exit");
+}
+
+/// Copy `local` to `remote` on the peer (`is_push`), or `remote` to `local` (pull),
+/// without any GUI. Existing files are overwritten, progress is printed on stderr.
+///
+/// Returns the process exit code, see `EXIT_*`.
 #[tokio::main(flavor = "current_thread")]
-pub async fn start_one_port_forward(
+pub async fn transfer_files(
+    id: String,
+    local: String,
+    remote: String,
+    is_push: bool,
+    password: Option<String>,
+    key: String,
+    token: String,
+) -> i32 {
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let mut handler = Session::new(&id, sender, ConnType::FILE_TRANSFER, password);
+    let (mut stream, _direct) =
+        match Client::start(&id, &key, &token, ConnType::FILE_TRANSFER, handler.clone()).await {
+            Ok(r) => r,
+            Err(err) => {
+                eprintln!("Failed to connect {}: {}", id, err);
+                return EXIT_CONNECT_ERROR;
+            }
+        };
+    let pi = match login(&mut handler, &mut receiver, &mut stream).await {
+        Ok(Some(pi)) => pi,
+        Ok(None) => return EXIT_LOGIN_ERROR,
+        Err(err) => {
+            eprintln!("Failed to login {}: {}", id, err);
+            return EXIT_CONNECT_ERROR;
+        }
+    };
+    let od = can_enable_overwrite_detection(handler.lc.read().unwrap().version);
+    const JOB_ID: i32 = 1;
+    let mut job = if is_push {
+        match fs::TransferJob::new_read(
+            JOB_ID,
+            remote.clone(),
+            local.clone(),
+            0,
+            false,
+            false,
+            od,
+        ) {
+            Ok(job) => job,
+            Err(err) => {
+                eprintln!("Failed to read {}: {}", local, err);
+                return EXIT_TRANSFER_ERROR;
+            }
+        }
+    } else {
+        fs::TransferJob::new_write(
+            JOB_ID,
+            remote.clone(),
+            local.clone(),
+            0,
+            false,
+            true,
+            Vec::new(),
+            od,
+        )
+    };
+    job.set_overwrite_strategy(Some(true));
+    let msg = if is_push {
+        #[cfg(not(windows))]
+        let files = job.files().clone();
+        #[cfg(windows)]
+        let mut files = job.files().clone();
+        #[cfg(windows)]
+        if pi.platform != "Windows" {
+            fs::transform_windows_path(&mut files);
+        }
+        fs::new_receive(JOB_ID, remote.clone(), 0, files)
+    } else {
+        fs::new_send(JOB_ID, remote.clone(), 0, false)
+    };
+    if let Err(err) = stream.send(&msg).await {
+        eprintln!("Connection error: {}", err);
+        return EXIT_CONNECT_ERROR;
+    }
+    let mut jobs = vec![job];
+    let mut timer = time::interval(MILLI1);
+    let mut progress_timer = time::interval(Duration::from_secs(1));
+    let mut last_progress = (Instant::now(), 0u64);
+    let code = loop {
+        tokio::select! {
+            res = hbb_common::timeout(READ_TIMEOUT, stream.next()) => match res {
+                Err(_) => {
+                    eprintln!("Timeout");
+                    break EXIT_CONNECT_ERROR;
+                }
+                Ok(Some(Ok(bytes))) => {
+                    if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
+                        if let Some(message::Union::Misc(misc)) = &msg_in.union {
+                            if let Some(misc::Union::CloseReason(c)) = &misc.union {
+                                eprintln!("Closed by the peer: {}", c);
+                                break EXIT_CONNECT_ERROR;
+                            }
+                        }
+                        match handle_transfer_msg(msg_in, &mut jobs, &mut stream, &pi).await {
+                            Some(Ok(())) => break EXIT_OK,
+                            Some(Err(err)) => {
+                                eprintln!("Transfer error: {}", err);
+                                break EXIT_TRANSFER_ERROR;
+                            }
+                            None => {}
+                        }
+                    }
+                }
+                Ok(Some(Err(err))) => {
+                    eprintln!("Connection closed: {}", err);
+                    break EXIT_CONNECT_ERROR;
+                }
+                _ => {
+                    eprintln!("Reset by the peer");
+                    break EXIT_CONNECT_ERROR;
+                }
+            },
+            // the read job is removed once all is sent, then wait for the peer to confirm
+            _ = timer.tick(), if is_push && !jobs.is_empty() => {
+                if let Err(err) = fs::handle_read_jobs(&mut jobs, &mut stream).await {
+                    eprintln!("Connection error: {}", err);
+                    break EXIT_CONNECT_ERROR;
+                }
+            }
+            _ = progress_timer.tick() => {
+                if let Some(job) = jobs.first() {
+                    let elapsed = last_progress.0.elapsed().as_secs_f64().max(0.001);
+                    let speed = (job.transferred() - last_progress.1) as f64 / elapsed;
+                    last_progress = (Instant::now(), job.transferred());
+                    eprint!(
+                        "\r{} / {} bytes, {:.2} kB/s",
+                        job.finished_size(),
+                        job.total_size(),
+                        speed / 1024.
+                    );
+                }
+            }
+        }
+    };
+    eprintln!();
+    // Keep the partial `.download` file when the connection drops so that a rerun resumes it,
+    // only a failed transfer (peer error, write error) discards it.
+    if code == EXIT_TRANSFER_ERROR && !is_push {
+        if let Some(job) = jobs.first() {
+            job.remove_download_file();
+        }
+    }
+    let mut misc = Misc::new();
+    misc.set_close_reason("".to_owned());
+    let mut msg = Message::new();
+    msg.set_misc(misc);
+    stream.send(&msg).await.ok();
+    code
+}
+
+async fn login(
+    handler: &mut Session,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    stream: &mut Stream,
+) -> hbb_common::ResultType<Option<PeerInfo>> {
+    loop {
+        tokio::select! {
+            res = hbb_common::timeout(READ_TIMEOUT, stream.next()) => match res {
+                Err(_) => hbb_common::bail!("Timeout"),
+                Ok(Some(Ok(bytes))) => {
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::Hash(hash)) => {
+                            let password = handler.password.clone();
+                            handler.handle_hash(&password, hash, stream).await;
+                        }
+                        Some(message::Union::LoginResponse(lr)) => match lr.union {
+                            Some(login_response::Union::Error(err)) => {
+                                if !handler.handle_login_error(&err) {
+                                    return Ok(None);
+                                }
+                            }
+                            Some(login_response::Union::PeerInfo(pi)) => {
+                                handler.handle_peer_info(pi.clone());
+                                return Ok(Some(pi));
+                            }
+                            _ => {}
+                        },
+                        Some(message::Union::TestDelay(t)) => {
+                            handler.handle_test_delay(t, stream).await;
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(Some(Err(err))) => hbb_common::bail!("Connection closed: {}", err),
+                _ => hbb_common::bail!("Reset by the peer"),
+            },
+            d = receiver.recv() => match d {
+                Some(Data::Login((password, remember))) => {
+                    handler.handle_login_from_ui(password, remember, stream).await;
+                }
+                Some(Data::Message(msg)) => {
+                    stream.send(&msg).await?;
+                }
+                Some(Data::Close) | None => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Returns `Some` when the job is finished, successfully or not.
+async fn handle_transfer_msg(
+    msg_in: Message,
+    jobs: &mut [fs::TransferJob],
+    stream: &mut Stream,
+    _pi: &PeerInfo,
+) -> Option<hbb_common::ResultType<()>> {
+    match msg_in.union {
+        Some(message::Union::FileResponse(fr)) => match fr.union {
+            Some(file_response::Union::Dir(fd)) => {
+                if let Some(job) = fs::get_job(fd.id, jobs) {
+                    #[cfg(windows)]
+                    let entries = fd.entries.to_vec();
+                    #[cfg(not(windows))]
+                    let mut entries = fd.entries.to_vec();
+                    #[cfg(not(windows))]
+                    if _pi.platform == "Windows" {
+                        fs::transform_windows_path(&mut entries);
+                    }
+                    job.set_files(entries);
+                }
+            }
+            Some(file_response::Union::Digest(digest)) => {
+                let job = fs::get_job(digest.id, jobs)?;
+                let req = if digest.is_upload {
+                    job.set_block_hashes(digest.block_hashes.clone());
+                    FileTransferSendConfirmRequest {
+                        id: digest.id,
+                        file_num: digest.file_num,
+                        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+                        ..Default::default()
+                    }
+                } else {
+                    let name = &job.files().get(digest.file_num as usize)?.name;
+                    let path = get_string(&job.join(name));
+                    match fs::is_write_need_confirmation(&path, &digest) {
+                        Ok(DigestCheckResult::IsSame) => FileTransferSendConfirmRequest {
+                            id: digest.id,
+                            file_num: digest.file_num,
+                            union: Some(file_transfer_send_confirm_request::Union::Skip(true)),
+                            ..Default::default()
+                        },
+                        Ok(DigestCheckResult::NeedConfirm(_)) => {
+                            fs::new_overwrite_confirm(digest.id, digest.file_num, &path).await
+                        }
+                        Ok(DigestCheckResult::NoSuchFile) => fs::new_resume_confirm(
+                            digest.id,
+                            digest.file_num,
+                            &path,
+                            digest.file_size,
+                        )
+                        .await,
+                        Err(err) => return Some(Err(err)),
+                    }
+                };
+                job.confirm(&req);
+                if let Err(err) = stream.send(&fs::new_send_confirm(req)).await {
+                    return Some(Err(err));
+                }
+            }
+            Some(file_response::Union::Block(block)) => {
+                if let Some(job) = fs::get_job(block.id, jobs) {
+                    if let Err(err) = job.write(block).await {
+                        return Some(Err(err));
+                    }
+                }
+            }
+            Some(file_response::Union::Done(d)) => {
+                if let Some(job) = fs::get_job(d.id, jobs) {
+                    job.modify_time();
+                }
+                return Some(Ok(()));
+            }
+            Some(file_response::Union::Error(e)) => {
+                return Some(Err(hbb_common::anyhow::anyhow!("{}", e.error)));
+            }
+            _ => {}
+        },
+        Some(message::Union::FileAction(action)) => {
+            if let Some(file_action::Union::SendConfirm(c)) = action.union {
+                if let Some(job) = fs::get_job(c.id, jobs) {
+                    job.confirm(&c);
+                }
+            }
+        }
+        _ => {}
+    }
+    None
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_reverse_port_forward(
+    id: String,
+    remote_port: i32,
+    local_host: String,
+    local_port: i32,
+    key: String,
+    token: String,
+) {
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, sender, ConnType::PORT_FORWARD, None);
+    if let Err(err) = crate::port_forward::listen_reverse(
+        handler.id.clone(),
+        handler.password.clone(),
+        remote_port,
+        local_host,
+        local_port,
+        handler.clone(),
+        receiver,
+        &key,
+        &token,
+        handler.lc.clone(),
+    )
+    .await
+    {
+        log::error!("Reverse port forward error: {}", err);
+    }
+    log::info!("reverse port forward exit");
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_udp_port_forward(
     id: String,
     port: i32,
     remote_host: String,
     remote_port: i32,
+    bind: Option<String>,
     key: String,
     token: String,
 ) {
     crate::common::test_rendezvous_server();
     crate::common::test_nat_type();
-    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
-    if let Err(err) = crate::port_forward::listen(
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, sender, ConnType::PORT_FORWARD, None);
+    if let Err(err) = crate::port_forward::listen_udp(
         handler.id.clone(),
         handler.password.clone(),
         port,
+        bind.unwrap_or_else(crate::port_forward::bind_address),
         handler.clone(),
         receiver,
         &key,
@@ -155,7 +642,7 @@ pub async fn start_one_port_forward(
     )
     .await
     {
-        log::error!("Failed to listen on {}: {}", port, err);
+        log::error!("Udp port forward error: {}", err);
     }
-    log::info!("port forward (:{}) exit", port);
+    log::info!("udp port forward exit");
 }
@@ -3,7 +3,10 @@ use std::{
     net::SocketAddr,
     ops::{Deref, Not},
     str::FromStr,
-    sync::{mpsc, Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, RwLock,
+    },
 };
 
 pub use async_trait::async_trait;
@@ -66,6 +69,23 @@ pub mod io_loop;
 
 pub const MILLI1: Duration = Duration::from_millis(1);
 pub const SEC30: Duration = Duration::from_secs(30);
+pub const LOGIN_MSG_2FA_REQUIRED: &str = "2FA Required";
+pub const LOGIN_MSG_2FA_WRONG: &str = "Wrong 2FA Code";
+
+/// The code is asked, then sent by [`auth_2fa_msg`] on the same connection.
+#[inline]
+pub fn is_2fa_login_error(err: &str) -> bool {
+    err == LOGIN_MSG_2FA_REQUIRED || err == LOGIN_MSG_2FA_WRONG
+}
+
+pub fn auth_2fa_msg(code: String) -> Message {
+    let mut msg_out = Message::new();
+    msg_out.set_auth_2fa(Auth2FA {
+        code,
+        ..Default::default()
+    });
+    msg_out
+}
 
 /// Client of the remote desktop.
 pub struct Client;
@@ -458,7 +478,7 @@ impl Client {
         });
         let mut sign_pk = None;
         if !signed_id_pk.is_empty() && rs_pk.is_some() {
-            if let Ok((id, pk)) = decode_id_pk(&signed_id_pk, &rs_pk.unwrap()) {
+            if let Ok((id, pk, _)) = decode_id_pk(&signed_id_pk, &rs_pk.unwrap()) {
                 if id == peer_id {
                     sign_pk = Some(sign::PublicKey(pk));
                 }
@@ -467,6 +487,8 @@ impl Client {
                 log::error!("Handshake failed: invalid public key from rendezvous server");
             }
         }
+        let lc = interface.get_login_config_handler();
+        lc.write().unwrap().channel_binding.clear();
         let sign_pk = match sign_pk {
             Some(v) => v,
             None => {
@@ -486,8 +508,25 @@ impl Client {
                 };
                 if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                     if let Some(message::Union::SignedId(si)) = msg_in.union {
-                        if let Ok((id, their_pk_b)) = decode_id_pk(&si.id, &sign_pk) {
-                            if id == peer_id {
+                        if let Ok((id, their_pk_b, kx_version)) = decode_id_pk(&si.id, &sign_pk) {
+                            if id == peer_id && kx_version >= crate::common::KX_VERSION {
+                                let their_pk_b = box_::PublicKey(their_pk_b);
+                                let (our_pk_b, out_sk_b) = box_::gen_keypair();
+                                let mut msg_out = Message::new();
+                                msg_out.set_public_key(PublicKey {
+                                    asymmetric_value: Vec::from(our_pk_b.0).into(),
+                                    kx_version: crate::common::KX_VERSION,
+                                    ..Default::default()
+                                });
+                                timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
+                                let transcript = crate::common::kx_transcript(&si.id, &our_pk_b.0);
+                                conn.set_session_key(
+                                    crate::common::kx_key(&their_pk_b, &out_sk_b, &transcript),
+                                    false,
+                                );
+                                crate::common::kx_confirm(conn, &transcript, false).await?;
+                                lc.write().unwrap().channel_binding = transcript;
+                            } else if id == peer_id {
                                 let their_pk_b = box_::PublicKey(their_pk_b);
                                 let (our_pk_b, out_sk_b) = box_::gen_keypair();
                                 let key = secretbox::gen_key();
@@ -501,6 +540,7 @@ impl Client {
                                 });
                                 timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
                                 conn.set_key(key);
+                                lc.write().unwrap().channel_binding = si.id.to_vec();
                             } else {
                                 log::error!("Handshake failed: sign failure");
                                 conn.send(&Message::new()).await?;
@@ -697,6 +737,10 @@ impl TextClipboardState {
 #[derive(Default)]
 pub struct AudioHandler {
     audio_decoder: Option<(AudioDecoder, Vec<f32>)>,
+    // the other streams of a multistream format
+    stream_decoders: Vec<AudioDecoder>,
+    coupled_streams: usize,
+    mapping: Vec<usize>,
     #[cfg(target_os = "android")]
     oboe: Option<OboePlayer>,
     #[cfg(target_os = "linux")]
@@ -720,6 +764,20 @@ impl AudioHandler {
         }
     }
 
+    /// The channels of the output device, more than 2 are received as opus multistream.
+    pub fn output_channels() -> u32 {
+        #[cfg(target_os = "linux")]
+        return crate::platform::linux::get_pa_sink_channels().unwrap_or(2);
+        #[cfg(target_os = "android")]
+        return 2;
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        return AUDIO_HOST
+            .default_output_device()
+            .and_then(|d| d.default_output_config().ok())
+            .map(|c| c.channels() as _)
+            .unwrap_or(2);
+    }
+
     /// Start the audio playback.
     #[cfg(target_os = "linux")]
     fn start_audio(&mut self, format0: AudioFormat) -> ResultType<()> {
@@ -735,6 +793,7 @@ impl AudioHandler {
         if !spec.is_valid() {
             bail!("Invalid audio format");
         }
+        let map = crate::platform::linux::pa_channel_map(spec.channels);
 
         self.simple = Some(Simple::new(
             None,                   // Use the default server
@@ -743,7 +802,7 @@ impl AudioHandler {
             None,                   // Use the default device
             "playback",             // Description of our stream
             &spec,                  // Our sample format
-            None,                   // Use default channel map
+            map.as_ref(),           // WAVEFORMATEXTENSIBLE order of more channels
             None,                   // Use default buffering attributes
         )?);
         self.sample_rate = (format0.sample_rate, format0.sample_rate);
@@ -788,17 +847,99 @@ impl AudioHandler {
 
     /// Handle audio format and create an audio decoder.
     pub fn handle_format(&mut self, f: AudioFormat) {
-        match AudioDecoder::new(f.sample_rate, if f.channels > 1 { Stereo } else { Mono }) {
-            Ok(d) => {
-                let buffer = vec![0.; f.sample_rate as usize * f.channels as usize];
-                self.audio_decoder = Some((d, buffer));
-                self.channels = f.channels as _;
-                allow_err!(self.start_audio(f));
+        if let Err(err) = Self::check_format(&f) {
+            log::error!("Invalid audio format: {}", err);
+            return;
+        }
+        let mut decoders = Vec::new();
+        for i in 0..f.streams.max(1) {
+            let stereo = if f.streams > 1 {
+                i < f.coupled_streams
+            } else {
+                f.channels > 1
+            };
+            match AudioDecoder::new(f.sample_rate, if stereo { Stereo } else { Mono }) {
+                Ok(d) => decoders.push(d),
+                Err(err) => {
+                    log::error!("Failed to create audio decoder: {}", err);
+                    return;
+                }
             }
-            Err(err) => {
-                log::error!("Failed to create audio decoder: {}", err);
+        }
+        let mut decoders = decoders.into_iter();
+        if let Some(d) = decoders.next() {
+            let buffer = vec![0.; f.sample_rate as usize * f.channels as usize];
+            self.audio_decoder = Some((d, buffer));
+            self.stream_decoders = decoders.collect();
+            self.coupled_streams = f.coupled_streams as _;
+            self.mapping = f.mapping.iter().map(|x| *x as usize).collect();
+            self.channels = f.channels as _;
+            allow_err!(self.start_audio(f));
+        }
+    }
+
+    // At most 8 channels, more than 2 with a stream for each pair or single channel.
+    fn check_format(f: &AudioFormat) -> ResultType<()> {
+        if f.channels == 0 || f.channels > 8 {
+            bail!("{} channels", f.channels);
+        }
+        if f.streams <= 1 && f.channels > 2 {
+            bail!("{} channels in a single stream", f.channels);
+        }
+        if f.streams > 1 {
+            if f.streams > f.channels || f.coupled_streams > f.streams {
+                bail!("{} streams, {} coupled", f.streams, f.coupled_streams);
+            }
+            if f.streams + f.coupled_streams != f.channels {
+                bail!("{} streams for {} channels", f.streams, f.channels);
+            }
+            let decoded = (f.streams + f.coupled_streams) as u8;
+            if f.mapping.len() != f.channels as usize || f.mapping.iter().any(|m| *m >= decoded) {
+                bail!("mapping {:?}", f.mapping);
             }
         }
+        Ok(())
+    }
+
+    // Returns the number of the interleaved samples decoded into the buffer.
+    fn decode(&mut self, frame: &AudioFrame) -> Option<usize> {
+        let (d, buffer) = self.audio_decoder.as_mut()?;
+        let channels = self.channels as usize;
+        if self.stream_decoders.is_empty() {
+            let n = d.decode_float(&frame.data, buffer, false).ok()?;
+            return Some(n * channels);
+        }
+        if frame.streams.len() != self.stream_decoders.len() {
+            return None;
+        }
+        // (samples, channels) of each stream
+        let mut decoded = Vec::new();
+        let datas = std::iter::once(&frame.data).chain(frame.streams.iter());
+        let decoders = std::iter::once(d).chain(self.stream_decoders.iter_mut());
+        for (i, (d, data)) in decoders.zip(datas).enumerate() {
+            let c = if i < self.coupled_streams { 2 } else { 1 };
+            // 125ms, more than the longest opus frame
+            let mut samples = vec![0.; buffer.len() / channels / 8 * c];
+            let n = d.decode_float(data, &mut samples, false).ok()?;
+            samples.truncate(n * c);
+            decoded.push((samples, c));
+        }
+        let n = decoded.iter().map(|(x, c)| x.len() / c).min().unwrap_or(0);
+        // the decoded channels, of the coupled streams then the mono ones
+        let sources: Vec<(usize, usize)> = decoded
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (_, c))| (0..*c).map(move |j| (i, j)))
+            .collect();
+        for (ch, m) in self.mapping.iter().enumerate().take(channels) {
+            if let Some((i, j)) = sources.get(*m) {
+                let (samples, c) = &decoded[*i];
+                for k in 0..n {
+                    buffer[k * channels + ch] = samples[k * c + j];
+                }
+            }
+        }
+        Some(n * channels)
     }
 
     /// Handle audio frame and play it.
@@ -833,46 +974,47 @@ impl AudioHandler {
         if self.oboe.is_none() {
             return;
         }
-        self.audio_decoder.as_mut().map(|(d, buffer)| {
-            if let Ok(n) = d.decode_float(&frame.data, buffer, false) {
+        let n = match self.decode(&frame) {
+            Some(n) => n,
+            None => return,
+        };
+        if let Some((_, buffer)) = self.audio_decoder.as_ref() {
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            {
                 let channels = self.channels;
-                let n = n * (channels as usize);
-                #[cfg(not(any(target_os = "android", target_os = "linux")))]
-                {
-                    let sample_rate0 = self.sample_rate.0;
-                    let sample_rate = self.sample_rate.1;
-                    let audio_buffer = self.audio_buffer.clone();
-                    // avoiding memory overflow if audio_buffer consumer side has problem
-                    if audio_buffer.lock().unwrap().len() as u32 > sample_rate * 120 {
-                        *audio_buffer.lock().unwrap() = Default::default();
-                    }
-                    if sample_rate != sample_rate0 {
-                        let buffer = crate::resample_channels(
-                            &buffer[0..n],
-                            sample_rate0,
-                            sample_rate,
-                            channels,
-                        );
-                        audio_buffer.lock().unwrap().extend(buffer);
-                    } else {
-                        audio_buffer
-                            .lock()
-                            .unwrap()
-                            .extend(buffer[0..n].iter().cloned());
-                    }
-                }
-                #[cfg(target_os = "android")]
-                {
-                    self.oboe.as_mut().map(|x| x.push(&buffer[0..n]));
+                let sample_rate0 = self.sample_rate.0;
+                let sample_rate = self.sample_rate.1;
+                let audio_buffer = self.audio_buffer.clone();
+                // avoiding memory overflow if audio_buffer consumer side has problem
+                if audio_buffer.lock().unwrap().len() as u32 > sample_rate * 120 {
+                    *audio_buffer.lock().unwrap() = Default::default();
                 }
-                #[cfg(target_os = "linux")]
-                {
-                    let data_u8 =
-                        unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, n * 4) };
-                    self.simple.as_mut().map(|x| x.write(data_u8));
+                if sample_rate != sample_rate0 {
+                    let buffer = crate::resample_multichannel(
+                        &buffer[0..n],
+                        sample_rate0,
+                        sample_rate,
+                        channels,
+                    );
+                    audio_buffer.lock().unwrap().extend(buffer);
+                } else {
+                    audio_buffer
+                        .lock()
+                        .unwrap()
+                        .extend(buffer[0..n].iter().cloned());
                 }
             }
-        });
+            #[cfg(target_os = "android")]
+            {
+                self.oboe.as_mut().map(|x| x.push(&buffer[0..n]));
+            }
+            #[cfg(target_os = "linux")]
+            {
+                let data_u8 =
+                    unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, n * 4) };
+                self.simple.as_mut().map(|x| x.write(data_u8));
+            }
+        }
     }
 
     /// Build audio output stream for current device.
@@ -958,7 +1100,7 @@ impl VideoHandler {
                         .lock()
                         .unwrap()
                         .as_mut()
-                        .map(|r| r.write_frame(frame));
+                        .map(|r| r.write_video_frame(&vf));
                 }
                 res
             }
@@ -966,6 +1108,17 @@ impl VideoHandler {
         }
     }
 
+    /// Mux the audio into the screen record.
+    pub fn record_audio(&mut self, af: &AudioFrame) {
+        if self.record {
+            self.recorder
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(|r| r.write_audio_frame(af));
+        }
+    }
+
     /// Reset the decoder.
     pub fn reset(&mut self) {
         self.decoder = Decoder::new(DecoderCfg {
@@ -1008,11 +1161,18 @@ pub struct LoginConfigHandler {
     pub remember: bool,
     config: PeerConfig,
     pub port_forward: (String, i32),
+    pub port_forward_udp: bool,
+    /// All the tcp connections to any target over one session.
+    pub port_forward_tcp_tunnel: bool,
+    /// Port to listen on the remote side for a reverse port forward, 0 if not reversed.
+    pub reverse_port_forward: i32,
     pub version: i64,
     pub conn_id: i32,
     features: Option<Features>,
     session_id: u64,
     pub supported_encoding: Option<(bool, bool)>,
+    pub supported_av1: bool,
+    pub supported_i444: bool,
     pub restarting_remote_device: bool,
     pub force_relay: bool,
     pub direct: Option<bool>,
@@ -1020,6 +1180,10 @@ pub struct LoginConfigHandler {
     switch_uuid: Option<String>,
     pub success_time: Option<hbb_common::tokio::time::Instant>,
     pub direct_error_counter: usize,
+    /// Not saved, the microphone is forwarded only when asked in this session.
+    pub forward_microphone: bool,
+    /// Of the secure connection, signed by the key login, empty if not secured.
+    pub channel_binding: Vec<u8>,
 }
 
 impl Deref for LoginConfigHandler {
@@ -1061,6 +1225,8 @@ impl LoginConfigHandler {
         self.config = config;
         self.session_id = rand::random();
         self.supported_encoding = None;
+        self.supported_av1 = false;
+        self.supported_i444 = false;
         self.restarting_remote_device = false;
         self.force_relay = !self.get_option("force-always-relay").is_empty() || force_relay;
         self.direct = None;
@@ -1068,6 +1234,8 @@ impl LoginConfigHandler {
         self.switch_uuid = switch_uuid;
         self.success_time = None;
         self.direct_error_counter = 0;
+        self.forward_microphone = false;
+        self.channel_binding.clear();
     }
 
     /// Check if the client should auto login.
@@ -1231,6 +1399,18 @@ impl LoginConfigHandler {
             option.block_input = BoolOption::Yes.into();
         } else if name == "unblock-input" {
             option.block_input = BoolOption::No.into();
+        } else if name == "forward-microphone" {
+            // the older peers would play the audio to their speakers
+            if !self.is_virtual_microphone_supported() {
+                return None;
+            }
+            self.forward_microphone = !self.forward_microphone;
+            option.forward_microphone = (if self.forward_microphone {
+                BoolOption::Yes
+            } else {
+                BoolOption::No
+            })
+            .into();
         } else if name == "show-quality-monitor" {
             config.show_quality_monitor.v = !config.show_quality_monitor.v;
         } else if name == "allow_swap_key" {
@@ -1249,7 +1429,7 @@ impl LoginConfigHandler {
             self.config.store(&self.id);
             return None;
         }
-        if !name.contains("block-input") {
+        if !name.contains("block-input") && name != "forward-microphone" {
             self.save_config(config);
         }
         let mut misc = Misc::new();
@@ -1321,6 +1501,11 @@ impl LoginConfigHandler {
             msg.disable_clipboard = BoolOption::Yes.into();
             n += 1;
         }
+        let audio_channels = AudioHandler::output_channels();
+        if audio_channels > 2 {
+            msg.audio_channels = audio_channels;
+            n += 1;
+        }
         let state = Decoder::video_codec_state(&self.id);
         msg.video_codec_state = hbb_common::protobuf::MessageField::some(state);
         n += 1;
@@ -1395,6 +1580,8 @@ impl LoginConfigHandler {
             self.config.show_quality_monitor.v
         } else if name == "allow_swap_key" {
             self.config.allow_swap_key.v
+        } else if name == "forward-microphone" {
+            self.forward_microphone
         } else {
             !self.get_option(name).is_empty()
         }
@@ -1408,6 +1595,14 @@ impl LoginConfigHandler {
         }
     }
 
+    pub fn is_virtual_microphone_supported(&self) -> bool {
+        if let Some(features) = &self.features {
+            features.virtual_microphone
+        } else {
+            false
+        }
+    }
+
     /// Create a [`Message`] for refreshing video.
     pub fn refresh() -> Message {
         let mut misc = Misc::new();
@@ -1557,6 +1752,8 @@ impl LoginConfigHandler {
         {
             self.supported_encoding = Some((pi.encoding.h264, pi.encoding.h265));
         }
+        self.supported_av1 = pi.encoding.av1;
+        self.supported_i444 = pi.encoding.i444;
     }
 
     pub fn get_remote_dir(&self) -> String {
@@ -1600,13 +1797,39 @@ impl LoginConfigHandler {
                 show_hidden: !self.get_option("remote_show_hidden").is_empty(),
                 ..Default::default()
             }),
+            ConnType::PORT_FORWARD if self.reverse_port_forward > 0 => {
+                lr.set_reverse_port_forward(ReversePortForward {
+                    port: self.reverse_port_forward,
+                    ..Default::default()
+                })
+            }
             ConnType::PORT_FORWARD => lr.set_port_forward(PortForward {
                 host: self.port_forward.0.clone(),
                 port: self.port_forward.1,
+                udp: self.port_forward_udp,
+                tcp_tunnel: self.port_forward_tcp_tunnel,
                 ..Default::default()
             }),
             _ => {}
         }
+        // opted in for each peer, the public key tells who we are
+        if !self.hash.challenge.is_empty()
+            && !self.channel_binding.is_empty()
+            && self.get_toggle_option("key-login")
+        {
+            let (sk, pk) = Config::get_key_pair();
+            if sk.len() == sign::SECRETKEYBYTES {
+                let mut sk_ = [0u8; sign::SECRETKEYBYTES];
+                sk_[..].copy_from_slice(&sk);
+                let data = hbb_common::password_security::key_login_data(
+                    &self.channel_binding,
+                    &self.hash.salt,
+                    &self.hash.challenge,
+                );
+                lr.public_key = pk.into();
+                lr.signed_challenge = sign::sign(&data, &sign::SecretKey(sk_)).into();
+            }
+        }
 
         let mut msg_out = Message::new();
         msg_out.set_login_request(lr);
@@ -1650,6 +1873,9 @@ impl LoginConfigHandler {
 /// Media data.
 pub enum MediaData {
     VideoFrame(VideoFrame),
+    // a frame of a display streamed besides the current one
+    DisplayVideoFrame(VideoFrame),
+    RemoveDisplay(usize),
     AudioFrame(AudioFrame),
     AudioFormat(AudioFormat),
     Reset,
@@ -1660,13 +1886,20 @@ pub type MediaSender = mpsc::Sender<MediaData>;
 
 /// Start video and audio thread.
 /// Return two [`MediaSender`], they should be given to the media producer.
+/// The display of the video callback is `None` for the current display.
 ///
 /// # Arguments
 ///
 /// * `video_callback` - The callback for video frame. Being called when a video frame is ready.
-pub fn start_video_audio_threads<F>(video_callback: F) -> (MediaSender, MediaSender)
+/// `decode_jitter` is set to the jitter of the decode time of the main display, in ms.
+/// The decoded and dropped frames are counted in the metrics of the session `metrics_key`.
+pub fn start_video_audio_threads<F>(
+    video_callback: F,
+    decode_jitter: Arc<AtomicUsize>,
+    metrics_key: String,
+) -> (MediaSender, MediaSender)
 where
-    F: 'static + FnMut(&mut Vec<u8>) + Send,
+    F: 'static + FnMut(Option<usize>, &mut Vec<u8>) + Send,
 {
     let (video_sender, video_receiver) = mpsc::channel::<MediaData>();
     let mut video_callback = video_callback;
@@ -1675,21 +1908,50 @@ where
     let latency_controller_cl = latency_controller.clone();
 
     std::thread::spawn(move || {
-        let mut video_handler = VideoHandler::new(latency_controller);
+        let mut video_handler = VideoHandler::new(latency_controller.clone());
+        let mut display_handlers: HashMap<usize, VideoHandler> = HashMap::new();
+        let mut jitter = Jitter::default();
+        let mut metrics = None;
         loop {
             if let Ok(data) = video_receiver.recv() {
                 match data {
                     MediaData::VideoFrame(vf) => {
-                        if let Ok(true) = video_handler.handle_frame(vf) {
-                            video_callback(&mut video_handler.rgb);
+                        let start = std::time::Instant::now();
+                        let res = video_handler.handle_frame(vf);
+                        let ms = start.elapsed().as_secs_f64() * 1000.;
+                        jitter.sample(ms);
+                        decode_jitter.store(jitter.get() as _, Ordering::Relaxed);
+                        if metrics.is_none() {
+                            metrics = crate::metrics::counters(&metrics_key);
+                        }
+                        if let Some(metrics) = metrics.as_ref() {
+                            metrics.decoded(ms, res.is_ok());
+                        }
+                        if let Ok(true) = res {
+                            video_callback(None, &mut video_handler.rgb);
                         }
                     }
+                    MediaData::DisplayVideoFrame(vf) => {
+                        let display = vf.display as usize;
+                        let handler = display_handlers
+                            .entry(display)
+                            .or_insert_with(|| VideoHandler::new(latency_controller.clone()));
+                        if let Ok(true) = handler.handle_frame(vf) {
+                            video_callback(Some(display), &mut handler.rgb);
+                        }
+                    }
+                    MediaData::RemoveDisplay(display) => {
+                        display_handlers.remove(&display);
+                    }
                     MediaData::Reset => {
                         video_handler.reset();
                     }
                     MediaData::RecordScreen(start, w, h, id) => {
                         video_handler.record_screen(start, w, h, id)
                     }
+                    MediaData::AudioFrame(af) => {
+                        video_handler.record_audio(&af);
+                    }
                     _ => {}
                 }
             } else {
@@ -1894,6 +2156,9 @@ pub fn handle_login_error(
         lc.write().unwrap().password = Default::default();
         interface.msgbox("re-input-password", err, "Do you want to enter again?", "");
         true
+    } else if is_2fa_login_error(err) {
+        interface.msgbox("input-2fa", err, "", "");
+        true
     } else if err == "No Password Access" {
         lc.write().unwrap().password = Default::default();
         interface.msgbox(
@@ -2071,10 +2336,12 @@ pub enum Data {
     AddJob((i32, String, String, i32, bool, bool)),
     ResumeJob((i32, bool)),
     RecordScreen(bool, i32, i32, String),
+    CaptureDisplays(Vec<i32>, Vec<i32>),
     ElevateDirect,
     ElevateWithLogon(String, String),
     NewVoiceCall,
     CloseVoiceCall,
+    ForwardMicrophone(bool),
 }
 
 /// Keycode for key events.
@@ -2250,12 +2517,13 @@ fn get_rs_pk(str_base64: &str) -> Option<sign::PublicKey> {
     }
 }
 
-fn decode_id_pk(signed: &[u8], key: &sign::PublicKey) -> ResultType<(String, [u8; 32])> {
+// id, pk and kx_version
+fn decode_id_pk(signed: &[u8], key: &sign::PublicKey) -> ResultType<(String, [u8; 32], u32)> {
     let res = IdPk::parse_from_bytes(
         &sign::verify(signed, key).map_err(|_| anyhow!("Signature mismatch"))?,
     )?;
     if let Some(pk) = get_pk(&res.pk) {
-        Ok((res.id, pk))
+        Ok((res.id, pk, res.kx_version))
     } else {
         bail!("Wrong public length");
     }
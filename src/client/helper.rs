@@ -5,7 +5,11 @@ use std::{
 
 use hbb_common::{
     log,
-    message_proto::{video_frame, VideoFrame, Message, VoiceCallRequest, VoiceCallResponse}, get_time,
+    message_proto::{
+        test_delay::Congestion, video_frame, Message, VideoFrame, VoiceCallRequest,
+        VoiceCallResponse,
+    },
+    get_time,
 };
 
 const MAX_LATENCY: i64 = 500;
@@ -82,6 +86,7 @@ pub enum CodecFormat {
     VP9,
     H264,
     H265,
+    AV1,
     Unknown,
 }
 
@@ -91,6 +96,7 @@ impl From<&VideoFrame> for CodecFormat {
             Some(video_frame::Union::Vp9s(_)) => CodecFormat::VP9,
             Some(video_frame::Union::H264s(_)) => CodecFormat::H264,
             Some(video_frame::Union::H265s(_)) => CodecFormat::H265,
+            Some(video_frame::Union::Av1s(_)) => CodecFormat::AV1,
             _ => CodecFormat::Unknown,
         }
     }
@@ -102,11 +108,21 @@ impl ToString for CodecFormat {
             CodecFormat::VP9 => "VP9".into(),
             CodecFormat::H264 => "H264".into(),
             CodecFormat::H265 => "H265".into(),
+            CodecFormat::AV1 => "AV1".into(),
             CodecFormat::Unknown => "Unknow".into(),
         }
     }
 }
 
+/// The state of the bitrate adaption shown in the quality monitor.
+pub fn congestion_name(congestion: Congestion) -> &'static str {
+    match congestion {
+        Congestion::Hold => "Hold",
+        Congestion::Increase => "Increase",
+        Congestion::Decrease => "Decrease",
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct QualityStatus {
     pub speed: Option<String>,
@@ -114,6 +130,48 @@ pub struct QualityStatus {
     pub delay: Option<i32>,
     pub target_bitrate: Option<i32>,
     pub codec_format: Option<CodecFormat>,
+    pub send_rate: Option<i32>,
+    pub backlog: Option<i32>,
+    pub jitter: Option<i32>,
+    pub congestion: Option<String>,
+}
+
+/// Smoothed jitter in ms, as the interarrival jitter of RFC 3550.
+#[derive(Debug)]
+pub struct Jitter {
+    start: Instant,
+    last: Option<f64>,
+    jitter: f64,
+}
+
+impl Default for Jitter {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            last: None,
+            jitter: 0.,
+        }
+    }
+}
+
+impl Jitter {
+    /// Update with a frame generated at `timestamp` on the remote device, arrived now.
+    pub fn arrive(&mut self, timestamp: i64) {
+        let transit = self.start.elapsed().as_secs_f64() * 1000. - timestamp as f64;
+        self.sample(transit);
+    }
+
+    /// Update with a value whose variation is measured, e.g. the decode time.
+    pub fn sample(&mut self, value: f64) {
+        if let Some(last) = self.last {
+            self.jitter += ((value - last).abs() - self.jitter) / 16.;
+        }
+        self.last = Some(value);
+    }
+
+    pub fn get(&self) -> u32 {
+        self.jitter.round() as u32
+    }
 }
 
 #[inline]
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::num::NonZeroI64;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
@@ -25,8 +25,8 @@ use hbb_common::{allow_err, get_time, message_proto::*, sleep};
 use hbb_common::{fs, log, Stream};
 
 use crate::client::{
-    new_voice_call_request, Client, CodecFormat, MediaData, MediaSender, QualityStatus, MILLI1,
-    SEC30,
+    new_voice_call_request, Client, CodecFormat, Jitter, MediaData, MediaSender, QualityStatus,
+    MILLI1, SEC30,
 };
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use crate::common::update_clipboard;
@@ -44,6 +44,9 @@ pub struct Remote<T: InvokeUiSession> {
     // Stop sending local audio to remote client.
     stop_voice_call_sender: Option<std::sync::mpsc::Sender<()>>,
     voice_call_request_timestamp: Option<NonZeroI64>,
+    // The local audio is sent by the same recorder for both.
+    in_voice_call: bool,
+    forward_microphone: bool,
     old_clipboard: Arc<Mutex<String>>,
     read_jobs: Vec<fs::TransferJob>,
     write_jobs: Vec<fs::TransferJob>,
@@ -55,8 +58,16 @@ pub struct Remote<T: InvokeUiSession> {
     client_conn_id: i32, // used for clipboard
     data_count: Arc<AtomicUsize>,
     frame_count: Arc<AtomicUsize>,
+    arrival_jitter: Jitter,
+    decode_jitter: Arc<AtomicUsize>,
+    metrics_key: String,
+    metrics: Option<Arc<crate::metrics::Counters>>,
     video_format: CodecFormat,
     elevation_requested: bool,
+    recording: bool,
+    current_display: usize,
+    // displays streamed besides the current one, each has its own decoder
+    capture_displays: HashSet<usize>,
 }
 
 impl<T: InvokeUiSession> Remote<T> {
@@ -67,7 +78,9 @@ impl<T: InvokeUiSession> Remote<T> {
         receiver: mpsc::UnboundedReceiver<Data>,
         sender: mpsc::UnboundedSender<Data>,
         frame_count: Arc<AtomicUsize>,
+        decode_jitter: Arc<AtomicUsize>,
     ) -> Self {
+        let metrics_key = handler.metrics_key();
         Self {
             handler,
             video_sender,
@@ -85,10 +98,19 @@ impl<T: InvokeUiSession> Remote<T> {
             client_conn_id: 0,
             data_count: Arc::new(AtomicUsize::new(0)),
             frame_count,
+            arrival_jitter: Default::default(),
+            decode_jitter,
+            metrics_key,
+            metrics: None,
             video_format: CodecFormat::Unknown,
             stop_voice_call_sender: None,
             voice_call_request_timestamp: None,
+            in_voice_call: false,
+            forward_microphone: false,
             elevation_requested: false,
+            recording: false,
+            current_display: 0,
+            capture_displays: Default::default(),
         }
     }
 
@@ -111,6 +133,9 @@ impl<T: InvokeUiSession> Remote<T> {
         .await
         {
             Ok((mut peer, direct)) => {
+                self.metrics = Some(crate::metrics::open(&self.metrics_key));
+                // The microphone is not forwarded again after a reconnection.
+                self.handler.lc.write().unwrap().forward_microphone = false;
                 self.handler.set_connection_type(peer.is_secured(), direct); // flutter -> connection_ready
                 self.handler.set_connection_info(direct, false);
 
@@ -216,6 +241,7 @@ impl<T: InvokeUiSession> Remote<T> {
                             let speed = self.data_count.swap(0, Ordering::Relaxed);
                             let speed = format!("{:.2}kB/s", speed as f32 / 1024 as f32);
                             let fps = self.frame_count.swap(0, Ordering::Relaxed) as _;
+                            crate::metrics::update(&self.metrics_key, |m| m.fps = fps as _);
                             self.handler.update_quality_status(QualityStatus {
                                 speed:Some(speed),
                                 fps:Some(fps),
@@ -225,6 +251,7 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                 }
                 log::debug!("Exit io_loop of id={}", self.handler.id);
+                crate::metrics::close(&self.metrics_key);
                 // Stop client audio server.
                 if let Some(s) = self.stop_voice_call_sender.take() {
                     s.send(()).ok();
@@ -360,6 +387,9 @@ impl<T: InvokeUiSession> Remote<T> {
                 self.check_clipboard_file_context();
             }
             Data::Message(msg) => {
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics.count_out(&msg, msg.compute_size() as _);
+                }
                 allow_err!(peer.send(&msg).await);
             }
             Data::SendFiles((id, path, to, file_num, include_hidden, is_remote)) => {
@@ -551,15 +581,23 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                         let mut msg = Message::new();
                         let mut file_action = FileAction::new();
-                        let req = FileTransferSendConfirmRequest {
-                            id,
-                            file_num,
-                            union: if need_override {
-                                Some(file_transfer_send_confirm_request::Union::OffsetBlk(0))
-                            } else {
-                                Some(file_transfer_send_confirm_request::Union::Skip(true))
+                        let req = match job.files().get(file_num as usize) {
+                            Some(file) if need_override => fs::new_overwrite_confirm(
+                                id,
+                                file_num,
+                                &get_string(&job.join(&file.name)),
+                            )
+                            .await,
+                            _ => FileTransferSendConfirmRequest {
+                                id,
+                                file_num,
+                                union: if need_override {
+                                    Some(file_transfer_send_confirm_request::Union::OffsetBlk(0))
+                                } else {
+                                    Some(file_transfer_send_confirm_request::Union::Skip(true))
+                                },
+                                ..Default::default()
                             },
-                            ..Default::default()
                         };
                         job.confirm(&req);
                         file_action.set_send_confirm(req);
@@ -676,10 +714,29 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
             }
             Data::RecordScreen(start, w, h, id) => {
+                self.recording = start;
                 let _ = self
                     .video_sender
                     .send(MediaData::RecordScreen(start, w, h, id));
             }
+            Data::CaptureDisplays(add, sub) => {
+                for i in add.iter() {
+                    self.capture_displays.insert(*i as _);
+                }
+                for i in sub.iter() {
+                    self.capture_displays.remove(&(*i as _));
+                    self.video_sender.send(MediaData::RemoveDisplay(*i as _)).ok();
+                }
+                let mut misc = Misc::new();
+                misc.set_capture_displays(CaptureDisplays {
+                    add,
+                    sub,
+                    ..Default::default()
+                });
+                let mut msg = Message::new();
+                msg.set_misc(misc);
+                allow_err!(peer.send(&msg).await);
+            }
             Data::ElevateDirect => {
                 let mut request = ElevationRequest::new();
                 request.set_direct(true);
@@ -714,8 +771,21 @@ impl<T: InvokeUiSession> Remote<T> {
                 allow_err!(peer.send(&msg).await);
                 self.handler.on_voice_call_waiting();
             }
+            Data::ForwardMicrophone(on) => {
+                self.forward_microphone = on;
+                if on {
+                    if self.stop_voice_call_sender.is_none() {
+                        self.stop_voice_call_sender = self.start_voice_call();
+                    }
+                } else if !self.in_voice_call {
+                    self.stop_voice_call();
+                }
+            }
             Data::CloseVoiceCall => {
-                self.stop_voice_call();
+                self.in_voice_call = false;
+                if !self.forward_microphone {
+                    self.stop_voice_call();
+                }
                 let msg = new_voice_call_request(false);
                 self.handler
                     .on_voice_call_closed("Closed manually by the peer");
@@ -809,6 +879,9 @@ impl<T: InvokeUiSession> Remote<T> {
 
     async fn handle_msg_from_peer(&mut self, data: &[u8], peer: &mut Stream) -> bool {
         if let Ok(msg_in) = Message::parse_from_bytes(&data) {
+            if let Some(metrics) = self.metrics.as_ref() {
+                metrics.count_in(&msg_in, data.len());
+            }
             match msg_in.union {
                 Some(message::Union::VideoFrame(vf)) => {
                     if !self.first_frame {
@@ -817,9 +890,21 @@ impl<T: InvokeUiSession> Remote<T> {
                         self.handler.adapt_size();
                         self.send_opts_after_login(peer).await;
                     }
+                    let display = vf.display as usize;
+                    if display != self.current_display && self.capture_displays.contains(&display)
+                    {
+                        self.video_sender.send(MediaData::DisplayVideoFrame(vf)).ok();
+                        return true;
+                    }
+                    if vf.timestamp > 0 {
+                        self.arrival_jitter.arrive(vf.timestamp);
+                    }
                     let incoming_format = CodecFormat::from(&vf);
                     if self.video_format != incoming_format {
                         self.video_format = incoming_format.clone();
+                        crate::metrics::update(&self.metrics_key, |m| {
+                            m.codec = incoming_format.to_string()
+                        });
                         self.handler.update_quality_status(QualityStatus {
                             codec_format: Some(incoming_format),
                             ..Default::default()
@@ -839,6 +924,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                     }
                     Some(login_response::Union::PeerInfo(pi)) => {
+                        self.current_display = pi.current_display as _;
                         self.handler.handle_peer_info(pi);
                         self.check_clipboard_file_context();
                         if !(self.handler.is_file_transfer() || self.handler.is_port_forward()) {
@@ -929,6 +1015,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         Some(file_response::Union::Digest(digest)) => {
                             if digest.is_upload {
                                 if let Some(job) = fs::get_job(digest.id, &mut self.read_jobs) {
+                                    job.set_block_hashes(digest.block_hashes.clone());
                                     if let Some(file) = job.files().get(digest.file_num as usize) {
                                         let read_path = get_string(&job.join(&file.name));
                                         let overwrite_strategy = job.default_overwrite_strategy();
@@ -979,15 +1066,20 @@ impl<T: InvokeUiSession> Remote<T> {
                                                 }
                                                 DigestCheckResult::NeedConfirm(digest) => {
                                                     if let Some(overwrite) = overwrite_strategy {
-                                                        let req = FileTransferSendConfirmRequest {
-                                                            id: digest.id,
-                                                            file_num: digest.file_num,
-                                                            union: Some(if overwrite {
-                                                                file_transfer_send_confirm_request::Union::OffsetBlk(0)
-                                                            } else {
-                                                                file_transfer_send_confirm_request::Union::Skip(true)
-                                                            }),
-                                                            ..Default::default()
+                                                        let req = if overwrite {
+                                                            fs::new_overwrite_confirm(
+                                                                digest.id,
+                                                                digest.file_num,
+                                                                &write_path,
+                                                            )
+                                                            .await
+                                                        } else {
+                                                            FileTransferSendConfirmRequest {
+                                                                id: digest.id,
+                                                                file_num: digest.file_num,
+                                                                union: Some(file_transfer_send_confirm_request::Union::Skip(true)),
+                                                                ..Default::default()
+                                                            }
                                                         };
                                                         job.confirm(&req);
                                                         let msg = new_send_confirm(req);
@@ -1003,12 +1095,13 @@ impl<T: InvokeUiSession> Remote<T> {
                                                     }
                                                 }
                                                 DigestCheckResult::NoSuchFile => {
-                                                    let req = FileTransferSendConfirmRequest {
-                                                        id: digest.id,
-                                                        file_num: digest.file_num,
-                                                        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
-                                                        ..Default::default()
-                                                    };
+                                                    let req = fs::new_resume_confirm(
+                                                        digest.id,
+                                                        digest.file_num,
+                                                        &write_path,
+                                                        digest.file_size,
+                                                    )
+                                                    .await;
                                                     job.confirm(&req);
                                                     let msg = new_send_confirm(req);
                                                     allow_err!(peer.send(&msg).await);
@@ -1093,6 +1186,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                     }
                     Some(misc::Union::SwitchDisplay(s)) => {
+                        self.current_display = s.display as _;
                         self.handler.ui_handler.switch_display(&s);
                         self.video_sender.send(MediaData::Reset).ok();
                         if s.width > 0 && s.height > 0 {
@@ -1201,13 +1295,25 @@ impl<T: InvokeUiSession> Remote<T> {
                         #[cfg(feature = "flutter")]
                         self.handler.switch_back(&self.handler.id);
                     }
+                    Some(misc::Union::AudioSources(sources)) => {
+                        self.handler.set_audio_sources(&sources);
+                    }
                     _ => {}
                 },
-                Some(message::Union::TestDelay(t)) => {
+                Some(message::Union::TestDelay(mut t)) => {
+                    if !t.from_client {
+                        t.arrival_jitter = self.arrival_jitter.get();
+                        t.decode_jitter = self.decode_jitter.load(Ordering::Relaxed) as _;
+                    }
                     self.handler.handle_test_delay(t, peer).await;
                 }
                 Some(message::Union::AudioFrame(frame)) => {
                     if !self.handler.lc.read().unwrap().disable_audio.v {
+                        if self.recording {
+                            self.video_sender
+                                .send(MediaData::AudioFrame(frame.clone()))
+                                .ok();
+                        }
                         self.audio_sender.send(MediaData::AudioFrame(frame)).ok();
                     }
                 }
@@ -1237,8 +1343,11 @@ impl<T: InvokeUiSession> Remote<T> {
                         // TODO: maybe we will do a voice call from the peer in the future.
                     } else {
                         log::debug!("The remote has requested to close the voice call");
-                        if let Some(sender) = self.stop_voice_call_sender.take() {
-                            allow_err!(sender.send(()));
+                        if self.in_voice_call {
+                            self.in_voice_call = false;
+                            if !self.forward_microphone {
+                                self.stop_voice_call();
+                            }
                             self.handler.on_voice_call_closed("");
                         }
                     }
@@ -1252,7 +1361,10 @@ impl<T: InvokeUiSession> Remote<T> {
                             if response.accepted {
                                 // The peer accepted the voice call.
                                 self.handler.on_voice_call_started();
-                                self.stop_voice_call_sender = self.start_voice_call();
+                                self.in_voice_call = true;
+                                if self.stop_voice_call_sender.is_none() {
+                                    self.stop_voice_call_sender = self.start_voice_call();
+                                }
                             } else {
                                 // The peer refused the voice call.
                                 self.handler.on_voice_call_closed("");
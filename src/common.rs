@@ -17,16 +17,19 @@ pub use arboard::Clipboard as ClipboardContext;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use hbb_common::compress::decompress;
 use hbb_common::{
-    allow_err,
+    allow_err, bail,
     compress::compress as compress_func,
-    config::{self, Config, COMPRESS_LEVEL, RENDEZVOUS_TIMEOUT},
+    config::{self, Config, COMPRESS_LEVEL, CONNECT_TIMEOUT, READ_TIMEOUT, RENDEZVOUS_TIMEOUT},
     get_version_number, log,
     message_proto::*,
     protobuf::Enum,
     protobuf::Message as _,
     rendezvous_proto::*,
-    sleep, socket_client, tokio, ResultType,
+    sleep, socket_client,
+    sodiumoxide::crypto::{box_, secretbox},
+    timeout, tokio, ResultType,
 };
+use sha2::{Digest, Sha256};
 // #[cfg(any(target_os = "android", target_os = "ios", feature = "cli"))]
 use hbb_common::{config::RENDEZVOUS_PORT, futures::future::join_all};
 
@@ -326,6 +329,86 @@ pub fn resample_channels(
     .unwrap_or_default()
 }
 
+/// Like [`resample_channels`], for more than 2 channels each channel is resampled on its own.
+pub fn resample_multichannel(
+    data: &[f32],
+    sample_rate0: u32,
+    sample_rate: u32,
+    channels: u16,
+) -> Vec<f32> {
+    if channels <= 2 {
+        return resample_channels(data, sample_rate0, sample_rate, channels);
+    }
+    let channels = channels as usize;
+    let resampled: Vec<Vec<f32>> = (0..channels)
+        .map(|c| {
+            let data: Vec<f32> = data.iter().skip(c).step_by(channels).cloned().collect();
+            resample_channels(&data, sample_rate0, sample_rate, 1)
+        })
+        .collect();
+    let n = resampled.iter().map(|x| x.len()).min().unwrap_or(0);
+    let mut out = Vec::with_capacity(n * channels);
+    for i in 0..n {
+        for x in resampled.iter() {
+            out.push(x[i]);
+        }
+    }
+    out
+}
+
+/// The channels coded, 1, 2, 6 (5.1) or 8 (7.1).
+pub fn supported_audio_channels(channels: u16) -> u16 {
+    if channels >= 8 {
+        8
+    } else if channels >= 6 {
+        6
+    } else if channels >= 2 {
+        2
+    } else {
+        1
+    }
+}
+
+/// The channels of each opus stream, the coupled streams first.
+/// More than 2 channels are in the order of WAVEFORMATEXTENSIBLE: FL FR FC LFE BL BR SL SR.
+pub fn audio_streams(channels: u16) -> Vec<Vec<usize>> {
+    match supported_audio_channels(channels) {
+        1 => vec![vec![0]],
+        2 => vec![vec![0, 1]],
+        6 => vec![vec![0, 1], vec![4, 5], vec![2], vec![3]],
+        _ => vec![vec![0, 1], vec![4, 5], vec![6, 7], vec![2], vec![3]],
+    }
+}
+
+/// Downmixes the interleaved `data` in the order of WAVEFORMATEXTENSIBLE to fewer channels.
+/// The surround of 5.1 and 7.1 is mixed into the front, other layouts are truncated.
+pub fn downmix_channels(data: &[f32], from: u16, to: u16) -> Vec<f32> {
+    let (from, to) = (from as usize, to as usize);
+    let mut out = Vec::with_capacity(data.len() / from * to);
+    for x in data.chunks_exact(from) {
+        match (from, to) {
+            (6, 2) | (8, 2) => {
+                let mut l = x[0] + 0.707 * (x[2] + x[4]);
+                let mut r = x[1] + 0.707 * (x[2] + x[5]);
+                if from == 8 {
+                    l += 0.707 * x[6];
+                    r += 0.707 * x[7];
+                }
+                out.push(l.clamp(-1., 1.));
+                out.push(r.clamp(-1., 1.));
+            }
+            (8, 6) => {
+                out.extend_from_slice(&x[..4]);
+                out.push((x[4] + x[6]).clamp(-1., 1.));
+                out.push((x[5] + x[7]).clamp(-1., 1.));
+            }
+            (_, 1) => out.push(x.iter().sum::<f32>() / from as f32),
+            _ => out.extend_from_slice(&x[..to]),
+        }
+    }
+    out
+}
+
 pub fn test_nat_type() {
     let mut i = 0;
     std::thread::spawn(move || loop {
@@ -799,3 +882,109 @@ pub fn decode64<T: AsRef<[u8]>>(input: T) -> Result<Vec<u8>, base64::DecodeError
     #[allow(deprecated)]
     base64::decode(input)
 }
+
+/// The key exchange of ephemeral X25519 keys, advertised in the signed IdPk.
+/// 0 is the fallback, the controlling side seals a key to the public key in IdPk.
+/// The key of 2 is used by `FramedStream::set_session_key`, rekeyed in-band,
+/// and kept by the classic port forwards. 1 had no rekeying and is not accepted.
+pub const KX_VERSION: u32 = 2;
+
+/// The hash of the key exchange: the signed IdPk and the public key of the controlling side.
+pub fn kx_transcript(signed_id: &[u8], pk: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"rustdesk-kx2");
+    hasher.update(signed_id);
+    hasher.update(pk);
+    hasher.finalize()[..].into()
+}
+
+pub fn kx_key(
+    their_pk: &box_::PublicKey,
+    our_sk: &box_::SecretKey,
+    transcript: &[u8],
+) -> secretbox::Key {
+    let shared = box_::precompute(their_pk, our_sk);
+    let mut hasher = Sha256::new();
+    hasher.update(b"key");
+    hasher.update(&shared.0);
+    hasher.update(transcript);
+    let mut key = [0u8; secretbox::KEYBYTES];
+    key[..].copy_from_slice(&hasher.finalize()[..secretbox::KEYBYTES]);
+    secretbox::Key(key)
+}
+
+fn kx_confirmation(transcript: &[u8], server: bool) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(if server { &b"server"[..] } else { &b"client"[..] });
+    hasher.update(transcript);
+    hasher.finalize()[..].into()
+}
+
+// The confirmation of the other side, not a reflected one of ours.
+fn kx_check_confirmation(transcript: &[u8], server: bool, confirmation: &[u8]) -> bool {
+    confirmation == &kx_confirmation(transcript, !server)[..]
+}
+
+/// Both sides prove the new key and the same transcript, the controlled side first.
+pub async fn kx_confirm(
+    stream: &mut hbb_common::Stream,
+    transcript: &[u8],
+    server: bool,
+) -> ResultType<()> {
+    if server {
+        kx_send_confirmation(stream, transcript, server).await?;
+    }
+    match timeout(READ_TIMEOUT, stream.next()).await? {
+        Some(res) => {
+            let msg_in = Message::parse_from_bytes(&res?)?;
+            match msg_in.union {
+                Some(message::Union::PublicKey(pk))
+                    if kx_check_confirmation(transcript, server, &pk.confirmation) => {}
+                _ => bail!("Handshake failed: key confirmation failure"),
+            }
+        }
+        None => bail!("Reset by the peer"),
+    }
+    if !server {
+        kx_send_confirmation(stream, transcript, server).await?;
+    }
+    Ok(())
+}
+
+async fn kx_send_confirmation(
+    stream: &mut hbb_common::Stream,
+    transcript: &[u8],
+    server: bool,
+) -> ResultType<()> {
+    let mut msg_out = Message::new();
+    msg_out.set_public_key(PublicKey {
+        kx_version: KX_VERSION,
+        confirmation: kx_confirmation(transcript, server).into(),
+        ..Default::default()
+    });
+    timeout(CONNECT_TIMEOUT, stream.send(&msg_out)).await??;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kx() {
+        let (server_pk, server_sk) = box_::gen_keypair();
+        let (client_pk, client_sk) = box_::gen_keypair();
+        let transcript = kx_transcript(b"signed id", &client_pk.0);
+        let server_key = kx_key(&client_pk, &server_sk, &transcript);
+        let client_key = kx_key(&server_pk, &client_sk, &transcript);
+        assert_eq!(server_key.0, client_key.0);
+        let other = kx_transcript(b"other id", &client_pk.0);
+        assert_ne!(kx_key(&server_pk, &client_sk, &other).0, server_key.0);
+
+        let confirmation = kx_confirmation(&transcript, true);
+        assert!(kx_check_confirmation(&transcript, false, &confirmation));
+        assert!(!kx_check_confirmation(&other, false, &confirmation));
+        assert!(!kx_check_confirmation(&transcript, true, &confirmation));
+        assert!(!kx_check_confirmation(&transcript, false, &confirmation[1..]));
+    }
+}
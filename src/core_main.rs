@@ -214,6 +214,44 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Permission denied!");
             }
             return None;
+        } else if args[0] == "--get-public-key" {
+            println!("{}", hbb_common::password_security::public_key());
+            return None;
+        } else if args[0] == "--authorized-keys" {
+            if crate::platform::is_root() {
+                crate::ipc::get_options();
+                for (key, comment) in hbb_common::password_security::authorized_keys() {
+                    println!("{} {}", key, comment);
+                }
+            } else {
+                println!("Permission denied!");
+            }
+            return None;
+        } else if args[0] == "--add-authorized-key" {
+            if args.len() >= 2 {
+                let key = args[1].clone();
+                let comment = args[2..].join(" ");
+                update_authorized_keys(|keys| {
+                    keys.retain(|(k, _)| k != &key);
+                    keys.push((key, comment));
+                });
+            }
+            return None;
+        } else if args[0] == "--remove-authorized-key" {
+            if args.len() == 2 {
+                update_authorized_keys(|keys| keys.retain(|(k, _)| k != &args[1]));
+            }
+            return None;
+        } else if args[0] == "--import-authorized-keys" {
+            if args.len() == 2 {
+                match std::fs::read_to_string(&args[1]) {
+                    Ok(v) => update_authorized_keys(|keys| {
+                        *keys = hbb_common::password_security::parse_authorized_keys(&v);
+                    }),
+                    Err(err) => println!("Failed to read {}: {}", args[1], err),
+                }
+            }
+            return None;
         } else if args[0] == "--check-hwcodec-config" {
             #[cfg(feature = "hwcodec")]
             scrap::hwcodec::check_config();
@@ -257,6 +295,22 @@ fn import_config(path: &str) {
     }
 }
 
+fn update_authorized_keys<F: FnOnce(&mut Vec<(String, String)>)>(f: F) {
+    use hbb_common::password_security::*;
+    if !crate::platform::is_root() {
+        println!("Administrative privileges required!");
+        return;
+    }
+    crate::ipc::get_options();
+    let mut keys = authorized_keys();
+    f(&mut keys);
+    if let Some((key, _)) = keys.iter().find(|(key, _)| !is_valid_public_key(key)) {
+        println!("Invalid public key: {}", key);
+        return;
+    }
+    crate::ipc::set_option("authorized-keys", &authorized_keys_option(&keys));
+}
+
 /// invoke a new connection
 ///
 /// [Note]
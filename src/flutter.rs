@@ -145,6 +145,8 @@ pub struct FlutterHandler {
     notify_rendered: Arc<RwLock<bool>>,
     renderer: Arc<RwLock<VideoRenderer>>,
     peer_info: Arc<RwLock<PeerInfo>>,
+    // the latest frames of the displays streamed besides the current one
+    pub display_rgbas: Arc<RwLock<HashMap<usize, Vec<u8>>>>,
 }
 
 #[cfg(not(feature = "flutter_texture_render"))]
@@ -156,6 +158,8 @@ pub struct FlutterHandler {
     pub rgba: Arc<RwLock<Vec<u8>>>,
     pub rgba_valid: Arc<AtomicBool>,
     peer_info: Arc<RwLock<PeerInfo>>,
+    // the latest frames of the displays streamed besides the current one
+    pub display_rgbas: Arc<RwLock<HashMap<usize, Vec<u8>>>>,
 }
 
 #[cfg(feature = "flutter_texture_render")]
@@ -350,6 +354,10 @@ impl InvokeUiSession for FlutterHandler {
                     "codec_format",
                     &status.codec_format.map_or(NULL, |it| it.to_string()),
                 ),
+                ("send_rate", &status.send_rate.map_or(NULL, |it| it.to_string())),
+                ("backlog", &status.backlog.map_or(NULL, |it| it.to_string())),
+                ("jitter", &status.jitter.map_or(NULL, |it| it.to_string())),
+                ("congestion", &status.congestion.map_or(NULL, |it| it)),
             ],
         );
     }
@@ -477,11 +485,20 @@ impl InvokeUiSession for FlutterHandler {
         }
     }
 
+    fn on_display_rgba(&self, display: usize, data: &mut Vec<u8>) {
+        let mut rgbas = self.display_rgbas.write().unwrap();
+        std::mem::swap::<Vec<u8>>(data, rgbas.entry(display).or_default());
+        drop(rgbas);
+        self.push_event("display_rgba", vec![("display", &display.to_string())]);
+    }
+
     fn set_peer_info(&self, pi: &PeerInfo) {
         let displays = Self::make_displays_msg(&pi.displays);
         let mut features: HashMap<&str, i32> = Default::default();
         for ref f in pi.features.iter() {
             features.insert("privacy_mode", if f.privacy_mode { 1 } else { 0 });
+            let mic = if f.virtual_microphone { 1 } else { 0 };
+            features.insert("virtual_microphone", mic);
         }
         // compatible with 1.1.9
         if get_version_number(&pi.version) < get_version_number("1.2.0") {
@@ -603,6 +620,30 @@ impl InvokeUiSession for FlutterHandler {
         self.push_event("on_voice_call_incoming", [].into());
     }
 
+    fn set_audio_sources(&self, sources: &AudioSources) {
+        let list: Vec<HashMap<&str, serde_json::Value>> = sources
+            .sources
+            .iter()
+            .map(|s| {
+                HashMap::from([
+                    ("name", s.name.clone().into()),
+                    ("description", s.description.clone().into()),
+                    ("is_application", s.is_application.into()),
+                ])
+            })
+            .collect();
+        self.push_event(
+            "audio_sources",
+            vec![
+                (
+                    "sources",
+                    &serde_json::ser::to_string(&list).unwrap_or("".to_owned()),
+                ),
+                ("current", &sources.current),
+            ],
+        );
+    }
+
     #[inline]
     fn get_rgba(&self) -> *const u8 {
         #[cfg(not(feature = "flutter_texture_render"))]
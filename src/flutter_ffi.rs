@@ -140,6 +140,12 @@ pub fn session_login(id: String, password: String, remember: bool) {
     }
 }
 
+pub fn session_send2fa(id: String, code: String) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&id) {
+        session.send2fa(code);
+    }
+}
+
 pub fn session_close(id: String) {
     if let Some(mut session) = SESSIONS.write().unwrap().remove(&id) {
         session.close_event_stream();
@@ -323,6 +329,22 @@ pub fn session_switch_display(id: String, value: i32) {
     }
 }
 
+pub fn session_capture_displays(id: String, add: Vec<i32>, sub: Vec<i32>) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&id) {
+        session.capture_displays(add, sub);
+    }
+}
+
+/// A copy of the latest frame of a display streamed besides the current one.
+pub fn session_get_display_rgba(id: String, display: i32) -> SyncReturn<Vec<u8>> {
+    if let Some(session) = SESSIONS.read().unwrap().get(&id) {
+        if let Some(rgba) = session.display_rgbas.read().unwrap().get(&(display as usize)) {
+            return SyncReturn(rgba.clone());
+        }
+    }
+    SyncReturn(vec![])
+}
+
 pub fn session_handle_flutter_key_event(
     id: String,
     name: String,
@@ -536,6 +558,18 @@ pub fn session_change_resolution(id: String, width: i32, height: i32) {
     }
 }
 
+pub fn session_get_audio_sources(id: String) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&id) {
+        session.get_audio_sources();
+    }
+}
+
+pub fn session_select_audio_source(id: String, name: String) {
+    if let Some(session) = SESSIONS.read().unwrap().get(&id) {
+        session.select_audio_source(name);
+    }
+}
+
 pub fn session_set_size(_id: String, _width: i32, _height: i32) {
     #[cfg(feature = "flutter_texture_render")]
     if let Some(session) = SESSIONS.write().unwrap().get_mut(&_id) {
@@ -1070,6 +1104,36 @@ pub fn session_supported_hwcodec(id: String) -> String {
     }
 }
 
+pub fn session_supported_av1(id: String) -> SyncReturn<bool> {
+    if let Some(session) = SESSIONS.read().unwrap().get(&id) {
+        SyncReturn(session.supported_av1())
+    } else {
+        SyncReturn(false)
+    }
+}
+
+pub fn session_supported_i444(id: String) -> SyncReturn<bool> {
+    if let Some(session) = SESSIONS.read().unwrap().get(&id) {
+        SyncReturn(session.supported_i444())
+    } else {
+        SyncReturn(false)
+    }
+}
+
+/// The metrics of the session in json, empty if no such session.
+pub fn session_get_metrics(id: String) -> SyncReturn<String> {
+    if let Some(session) = SESSIONS.read().unwrap().get(&id) {
+        SyncReturn(session.get_metrics())
+    } else {
+        SyncReturn("".to_owned())
+    }
+}
+
+/// The metrics of all the sessions and connections of this process in json.
+pub fn main_get_metrics() -> String {
+    serde_json::to_string(&crate::metrics::get_all()).unwrap_or_default()
+}
+
 pub fn session_change_prefer_codec(id: String) {
     if let Some(session) = SESSIONS.read().unwrap().get(&id) {
         session.change_prefer_codec();
@@ -1118,6 +1182,14 @@ pub fn main_set_permanent_password(password: String) {
     set_permanent_password(password);
 }
 
+pub fn main_generate2fa() -> String {
+    generate2fa()
+}
+
+pub fn main_verify2fa(code: String) -> bool {
+    verify2fa(code)
+}
+
 pub fn main_check_super_user_permission() -> bool {
     check_super_user_permission()
 }
@@ -1171,6 +1243,25 @@ pub fn cm_switch_permission(conn_id: i32, name: String, enabled: bool) {
     crate::ui_cm_interface::switch_permission(conn_id, name, enabled)
 }
 
+pub fn cm_get_windows() -> SyncReturn<String> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return SyncReturn(crate::ui_cm_interface::get_windows());
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    return SyncReturn("".to_owned());
+}
+
+pub fn cm_share_window(conn_id: i32, window: u64) {
+    crate::ui_cm_interface::share_window(conn_id, window)
+}
+
+pub fn cm_share_rect(conn_id: i32, x: i32, y: i32, width: i32, height: i32) {
+    crate::ui_cm_interface::share_rect(conn_id, x, y, width, height)
+}
+
+pub fn cm_share_display(conn_id: i32) {
+    crate::ui_cm_interface::share_display(conn_id)
+}
+
 pub fn cm_can_elevate() -> SyncReturn<bool> {
     SyncReturn(crate::ui_cm_interface::can_elevate())
 }
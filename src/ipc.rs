@@ -34,6 +34,19 @@ pub enum PrivacyModeState {
     OffUnknown,
 }
 
+/// The part of the screen shared instead of the whole display.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "t", content = "c")]
+pub enum ShareTarget {
+    Window(u64),
+    Rect {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "t", content = "c")]
 pub enum FS {
@@ -70,6 +83,8 @@ pub enum FS {
         file_num: i32,
         data: Bytes,
         compressed: bool,
+        blk_id: u32,
+        unchanged: bool,
     },
     WriteDone {
         id: i32,
@@ -215,6 +230,13 @@ pub enum Data {
     StartVoiceCall,
     VoiceCallResponse(bool),
     CloseVoiceCall(String),
+    // the audio of the peer for the virtual microphone, (sample rate, channels)
+    MicrophoneFormat((u32, u32)),
+    MicrophoneFrame(Vec<u8>),
+    CloseMicrophone,
+    // (name, description, is application), requested by the connection with None
+    AudioSources(Option<Vec<(String, String, bool)>>),
+    ShareTarget(Option<ShareTarget>),
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -475,7 +497,22 @@ pub async fn start_pa() {
                             {
                                 device = x;
                             }
-                            if !device.is_empty() {
+                            let mut channels: u8 = 2;
+                            if let Some(Ok(Some(Data::Config((_, Some(x)))))) =
+                                stream.next_timeout2(1000).await
+                            {
+                                channels = x.parse().unwrap_or(2);
+                            }
+                            let mut app_capture = None;
+                            if let Some(app) =
+                                device.strip_prefix(crate::platform::linux::PA_APP_PREFIX)
+                            {
+                                app_capture = crate::platform::linux::PaAppCapture::new(app);
+                                device = app_capture
+                                    .as_ref()
+                                    .map(|c| c.source())
+                                    .unwrap_or_default();
+                            } else if !device.is_empty() {
                                 device = crate::platform::linux::get_pa_source_name(&device);
                             }
                             if device.is_empty() {
@@ -486,12 +523,14 @@ pub async fn start_pa() {
                             }
                             let spec = pulse::sample::Spec {
                                 format: pulse::sample::Format::F32le,
-                                channels: 2,
+                                channels,
                                 rate: crate::platform::PA_SAMPLE_RATE,
                             };
-                            log::info!("pa monitor: {:?}", device);
+                            let map = crate::platform::linux::pa_channel_map(channels);
+                            log::info!("pa monitor: {:?}, channels: {}", device, channels);
                             // systemctl --user status pulseaudio.service
-                            let mut buf: Vec<u8> = vec![0; AUDIO_DATA_SIZE_U8];
+                            let mut buf: Vec<u8> =
+                                vec![0; AUDIO_DATA_SIZE_U8 / 2 * channels as usize];
                             match psimple::Simple::new(
                                 None,                             // Use the default server
                                 &crate::get_app_name(),           // Our application’s name
@@ -499,7 +538,7 @@ pub async fn start_pa() {
                                 Some(&device),                    // Use the default device
                                 "record",                         // Description of our stream
                                 &spec,                            // Our sample format
-                                None,                             // Use default channel map
+                                map.as_ref(), // WAVEFORMATEXTENSIBLE order of more channels
                                 None, // Use default buffering attributes
                             ) {
                                 Ok(s) => loop {
@@ -2,6 +2,7 @@
 /// cbindgen:ignore
 pub mod platform;
 mod keyboard;
+mod auth_2fa;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub use platform::{get_cursor, get_cursor_data, get_cursor_pos, start_os_service};
 #[cfg(not(any(target_os = "ios")))]
@@ -40,6 +41,7 @@ mod hbbs;
 mod lang;
 #[cfg(windows)]
 mod license;
+pub mod metrics;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod port_forward;
 
@@ -38,58 +38,141 @@ fn main() {
     if !common::global_init() {
         return;
     }
-    use clap::App;
+    use clap::{App, SubCommand};
     use hbb_common::log;
     let args = format!(
-        "-p, --port-forward=[PORT-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host]'
+        "-p, --port-forward=[PORT-FORWARD-OPTIONS]... 'Format: remote-id:local-port:remote-port[:remote-host], or remote-id:local-port:socks5 for a SOCKS5 proxy, can be repeated'
+        -f, --port-forward-file=[FILE] 'Read port-forward options from FILE, one per line'
+        -u, --udp-forward=[UDP-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host]'
+        -R, --reverse-forward=[REVERSE-FORWARD-OPTIONS] 'Format: remote-id:remote-port:local-port[:local-host], the remote listens on its loopback remote-port'
+        -b, --bind-address=[ADDRESS] 'Local address of the port forwards, 127.0.0.1 by default'
         -c, --connect=[REMOTE_ID] 'test only'
         -k, --key=[KEY] ''
        -s, --server=[] 'Start server'",
     );
+    let transfer_args = "<ID> 'Remote id'
+        <LOCAL> 'Local file or directory'
+        <REMOTE> 'Remote file or directory'
+        --password=[PASSWORD] 'Password of the remote, RUSTDESK_PASSWORD is read if not set'";
     let matches = App::new("rustdesk")
         .version(crate::VERSION)
         .author("CarrieZ Studio<info@rustdesk.com>")
         .about("RustDesk command line tool")
         .args_from_usage(&args)
+        .subcommand(
+            SubCommand::with_name("push")
+                .about("Copy LOCAL to REMOTE on the remote machine")
+                .args_from_usage(transfer_args),
+        )
+        .subcommand(
+            SubCommand::with_name("pull")
+                .about("Copy REMOTE on the remote machine to LOCAL")
+                .args_from_usage(transfer_args),
+        )
         .get_matches();
+    use cli::ForwardTarget;
     use hbb_common::{config::LocalConfig, env_logger::*};
     init_from_env(Env::default().filter_or(DEFAULT_FILTER_ENV, "info"));
-    if let Some(p) = matches.value_of("port-forward") {
-        let options: Vec<String> = p.split(":").map(|x| x.to_owned()).collect();
-        if options.len() < 3 {
-            log::error!("Wrong port-forward options");
-            return;
+    let transfer = match matches.subcommand() {
+        ("push", Some(sub)) => Some((true, sub)),
+        ("pull", Some(sub)) => Some((false, sub)),
+        _ => None,
+    };
+    if let Some((is_push, sub)) = transfer {
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        let code = cli::transfer_files(
+            sub.value_of("ID").unwrap_or("").to_owned(),
+            sub.value_of("LOCAL").unwrap_or("").to_owned(),
+            sub.value_of("REMOTE").unwrap_or("").to_owned(),
+            is_push,
+            sub.value_of("password").map(|x| x.to_owned()),
+            key,
+            token,
+        );
+        common::global_clean();
+        std::process::exit(code);
+    } else if matches.is_present("port-forward") || matches.is_present("port-forward-file") {
+        let mut options: Vec<String> = matches
+            .values_of("port-forward")
+            .map(|v| v.map(|x| x.to_owned()).collect())
+            .unwrap_or_default();
+        if let Some(path) = matches.value_of("port-forward-file") {
+            match cli::load_port_forwards(path) {
+                Ok(mut v) => options.append(&mut v),
+                Err(err) => {
+                    log::error!("Failed to read {}: {}", path, err);
+                    return;
+                }
+            }
         }
-        let mut port = 0;
-        if let Ok(v) = options[1].parse::<i32>() {
-            port = v;
-        } else {
-            log::error!("Wrong local-port");
-            return;
+        let mut id = String::new();
+        let mut forwards = Vec::new();
+        for option in options.iter() {
+            match cli::parse_port_forward(option) {
+                Ok((remote_id, port, target)) => {
+                    if !id.is_empty() && id != remote_id {
+                        log::error!("All port forwards must go to the same remote id");
+                        return;
+                    }
+                    id = remote_id;
+                    forwards.push((port, target));
+                }
+                Err(err) => {
+                    log::error!("{}", err);
+                    return;
+                }
+            }
         }
-        let mut remote_port = 0;
-        if let Ok(v) = options[2].parse::<i32>() {
-            remote_port = v;
-        } else {
-            log::error!("Wrong remote-port");
+        if forwards.is_empty() {
+            log::error!("Wrong port-forward options");
             return;
         }
-        let mut remote_host = "localhost".to_owned();
-        if options.len() > 3 {
-            remote_host = options[3].clone();
-        }
-        common::test_rendezvous_server();
-        common::test_nat_type();
         let key = matches.value_of("key").unwrap_or("").to_owned();
         let token = LocalConfig::get_option("access_token");
-        cli::start_one_port_forward(
-            options[0].clone(),
-            port,
-            remote_host,
-            remote_port,
-            key,
-            token,
-        );
+        let bind = matches.value_of("bind-address").map(|x| x.to_owned());
+        cli::start_port_forwards(id, forwards, bind, key, token);
+    } else if let Some(option) = matches.value_of("udp-forward") {
+        match cli::parse_port_forward(option) {
+            Ok((id, port, ForwardTarget::Static(remote_host, remote_port))) => {
+                let key = matches.value_of("key").unwrap_or("").to_owned();
+                let token = LocalConfig::get_option("access_token");
+                let bind = matches.value_of("bind-address").map(|x| x.to_owned());
+                cli::start_udp_port_forward(
+                    id,
+                    port,
+                    remote_host,
+                    remote_port,
+                    bind,
+                    key,
+                    token,
+                );
+            }
+            Ok(_) => {
+                log::error!("SOCKS5 is not supported for udp forward");
+            }
+            Err(err) => {
+                log::error!("{}", err);
+            }
+        }
+    } else if let Some(option) = matches.value_of("reverse-forward") {
+        match cli::parse_reverse_forward(option) {
+            Ok((id, remote_port, local_host, local_port)) => {
+                let key = matches.value_of("key").unwrap_or("").to_owned();
+                let token = LocalConfig::get_option("access_token");
+                cli::start_reverse_port_forward(
+                    id,
+                    remote_port,
+                    local_host,
+                    local_port,
+                    key,
+                    token,
+                );
+            }
+            Err(err) => {
+                log::error!("{}", err);
+            }
+        }
     } else if let Some(p) = matches.value_of("connect") {
         common::test_rendezvous_server();
         common::test_nat_type();
@@ -0,0 +1,433 @@
+// Quality metrics of the sessions, for monitoring.
+// The controlling side records its sessions by peer, the controlled side its connections.
+// A snapshot is returned by `get`, and all of them are written every few seconds
+// next to the logs if the option "metrics-sink" is "prometheus" or "jsonl".
+// Like the logs, the jsonl files are per day and the last few days are kept.
+
+use hbb_common::{
+    config::Config,
+    get_time, lazy_static, log,
+    message_proto::{message, Message},
+};
+use serde_derive::Serialize;
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::Write as _,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Once,
+    },
+    time::{Duration, Instant},
+};
+
+const SINK_INTERVAL: Duration = Duration::from_secs(5);
+// keep a closed session for a while, so the sink and a reconnection can see it
+const KEEP_CLOSED: Duration = Duration::from_secs(60);
+// the same as the logs
+const KEEP_JSONL_FILES: usize = 6;
+
+lazy_static::lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<String, Entry>> = Default::default();
+}
+
+struct Entry {
+    metrics: SessionMetrics,
+    counters: Arc<Counters>,
+    closed: Option<Instant>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SessionMetrics {
+    pub session: String,
+    // ms
+    pub time: i64,
+    pub fps: u32,
+    // ms
+    pub delay: u32,
+    // kbps
+    pub target_bitrate: u32,
+    pub send_rate: u32,
+    pub codec: String,
+    // average of the last frames, ms
+    pub decode_time: f64,
+    pub decoded_frames: u64,
+    pub dropped_frames: u64,
+    pub reconnects: u32,
+    // by message type
+    pub bytes_in: HashMap<&'static str, u64>,
+    pub bytes_out: HashMap<&'static str, u64>,
+}
+
+const MESSAGE_TYPES: [&str; 26] = [
+    "signed_id",
+    "public_key",
+    "test_delay",
+    "video_frame",
+    "login_request",
+    "login_response",
+    "hash",
+    "mouse_event",
+    "audio_frame",
+    "cursor_data",
+    "cursor_position",
+    "cursor_id",
+    "key_event",
+    "clipboard",
+    "file_action",
+    "file_response",
+    "misc",
+    "cliprdr",
+    "message_box",
+    "switch_sides_response",
+    "voice_call_request",
+    "voice_call_response",
+    "peer_info",
+    "tunnel_data",
+    "auth_2fa",
+    "unknown",
+];
+
+/// The counters of a session updated for each message or frame, without a lock.
+/// They are kept over the reconnections of the session.
+#[derive(Default)]
+pub struct Counters {
+    bytes_in: [AtomicU64; MESSAGE_TYPES.len()],
+    bytes_out: [AtomicU64; MESSAGE_TYPES.len()],
+    decoded_frames: AtomicU64,
+    dropped_frames: AtomicU64,
+    // the bits of f64, only written by the decoding thread
+    decode_time: AtomicU64,
+}
+
+impl Counters {
+    #[inline]
+    pub fn count_in(&self, msg: &Message, len: usize) {
+        self.bytes_in[message_type(msg)].fetch_add(len as _, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn count_out(&self, msg: &Message, len: usize) {
+        self.bytes_out[message_type(msg)].fetch_add(len as _, Ordering::Relaxed);
+    }
+
+    /// A decoded frame, or dropped if it failed.
+    pub fn decoded(&self, ms: f64, ok: bool) {
+        if ok {
+            self.decoded_frames.fetch_add(1, Ordering::Relaxed);
+            let avg = f64::from_bits(self.decode_time.load(Ordering::Relaxed));
+            let avg = avg + (ms - avg) / 16.;
+            self.decode_time.store(avg.to_bits(), Ordering::Relaxed);
+        } else {
+            self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn fill(&self, m: &mut SessionMetrics) {
+        let bytes = |counters: &[AtomicU64; MESSAGE_TYPES.len()]| {
+            MESSAGE_TYPES
+                .iter()
+                .zip(counters.iter())
+                .map(|(t, n)| (*t, n.load(Ordering::Relaxed)))
+                .filter(|(_, n)| *n > 0)
+                .collect()
+        };
+        m.bytes_in = bytes(&self.bytes_in);
+        m.bytes_out = bytes(&self.bytes_out);
+        m.decoded_frames = self.decoded_frames.load(Ordering::Relaxed);
+        m.dropped_frames = self.dropped_frames.load(Ordering::Relaxed);
+        m.decode_time = f64::from_bits(self.decode_time.load(Ordering::Relaxed));
+    }
+}
+
+/// The key of a session of the controlling side, `kind` tells the sessions to the same peer apart.
+#[inline]
+pub fn client_key(id: &str, kind: &str) -> String {
+    format!("client/{}/{}", id, kind)
+}
+
+/// The key of a connection of the controlled side.
+#[inline]
+pub fn server_key(conn_id: i32) -> String {
+    format!("server/{}", conn_id)
+}
+
+/// A (re)connection of the session `key`, returns its counters.
+pub fn open(key: &str) -> Arc<Counters> {
+    let mut sessions = SESSIONS.lock().unwrap();
+    let counters = match sessions.get_mut(key) {
+        Some(entry) => {
+            entry.metrics.reconnects += 1;
+            entry.closed = None;
+            entry.counters.clone()
+        }
+        None => {
+            let counters: Arc<Counters> = Default::default();
+            sessions.insert(
+                key.to_owned(),
+                Entry {
+                    metrics: SessionMetrics {
+                        session: key.to_owned(),
+                        ..Default::default()
+                    },
+                    counters: counters.clone(),
+                    closed: None,
+                },
+            );
+            counters
+        }
+    };
+    drop(sessions);
+    start_sink();
+    counters
+}
+
+/// The counters of the session `key` if it is opened.
+pub fn counters(key: &str) -> Option<Arc<Counters>> {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .get(key)
+        .map(|entry| entry.counters.clone())
+}
+
+pub fn close(key: &str) {
+    if let Some(entry) = SESSIONS.lock().unwrap().get_mut(key) {
+        entry.closed = Some(Instant::now());
+    }
+}
+
+pub fn update<F: FnOnce(&mut SessionMetrics)>(key: &str, f: F) {
+    if let Some(entry) = SESSIONS.lock().unwrap().get_mut(key) {
+        f(&mut entry.metrics);
+    }
+}
+
+/// The snapshot of a session.
+pub fn get(key: &str) -> Option<SessionMetrics> {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .get(key)
+        .map(snapshot)
+}
+
+/// The snapshots of all the sessions.
+pub fn get_all() -> Vec<SessionMetrics> {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .values()
+        .map(snapshot)
+        .collect()
+}
+
+fn snapshot(entry: &Entry) -> SessionMetrics {
+    let mut m = entry.metrics.clone();
+    entry.counters.fill(&mut m);
+    m.time = get_time();
+    m
+}
+
+// the index in MESSAGE_TYPES
+fn message_type(msg: &Message) -> usize {
+    match &msg.union {
+        Some(message::Union::SignedId(_)) => 0,
+        Some(message::Union::PublicKey(_)) => 1,
+        Some(message::Union::TestDelay(_)) => 2,
+        Some(message::Union::VideoFrame(_)) => 3,
+        Some(message::Union::LoginRequest(_)) => 4,
+        Some(message::Union::LoginResponse(_)) => 5,
+        Some(message::Union::Hash(_)) => 6,
+        Some(message::Union::MouseEvent(_)) => 7,
+        Some(message::Union::AudioFrame(_)) => 8,
+        Some(message::Union::CursorData(_)) => 9,
+        Some(message::Union::CursorPosition(_)) => 10,
+        Some(message::Union::CursorId(_)) => 11,
+        Some(message::Union::KeyEvent(_)) => 12,
+        Some(message::Union::Clipboard(_)) => 13,
+        Some(message::Union::FileAction(_)) => 14,
+        Some(message::Union::FileResponse(_)) => 15,
+        Some(message::Union::Misc(_)) => 16,
+        Some(message::Union::Cliprdr(_)) => 17,
+        Some(message::Union::MessageBox(_)) => 18,
+        Some(message::Union::SwitchSidesResponse(_)) => 19,
+        Some(message::Union::VoiceCallRequest(_)) => 20,
+        Some(message::Union::VoiceCallResponse(_)) => 21,
+        Some(message::Union::PeerInfo(_)) => 22,
+        Some(message::Union::TunnelData(_)) => 23,
+        Some(message::Union::Auth2fa(_)) => 24,
+        _ => 25,
+    }
+}
+
+fn start_sink() {
+    static START: Once = Once::new();
+    START.call_once(|| {
+        std::thread::spawn(|| loop {
+            std::thread::sleep(SINK_INTERVAL);
+            let all = {
+                let mut sessions = SESSIONS.lock().unwrap();
+                // the counters still held by a session are kept for its reconnection
+                sessions.retain(|_, entry| {
+                    entry.closed.map_or(true, |t| t.elapsed() < KEEP_CLOSED)
+                        || Arc::strong_count(&entry.counters) > 1
+                });
+                sessions
+                    .values()
+                    .filter(|entry| entry.closed.is_none())
+                    .map(snapshot)
+                    .collect::<Vec<_>>()
+            };
+            let res = match Config::get_option("metrics-sink").as_str() {
+                "prometheus" => write_prometheus(&all),
+                "jsonl" => write_jsonl(&all),
+                _ => Ok(()),
+            };
+            if let Err(err) = res {
+                log::error!("Failed to write metrics: {}", err);
+            }
+        });
+    });
+}
+
+// rewritten each time, for the textfile collector of the node exporter
+fn write_prometheus(all: &Vec<SessionMetrics>) -> std::io::Result<()> {
+    let mut out = String::new();
+    let gauges: [(&str, fn(&SessionMetrics) -> f64); 5] = [
+        ("fps", |m| m.fps as _),
+        ("delay_ms", |m| m.delay as _),
+        ("target_bitrate_kbps", |m| m.target_bitrate as _),
+        ("send_rate_kbps", |m| m.send_rate as _),
+        ("decode_time_ms", |m| m.decode_time),
+    ];
+    let counters: [(&str, fn(&SessionMetrics) -> f64); 3] = [
+        ("decoded_frames_total", |m| m.decoded_frames as _),
+        ("dropped_frames_total", |m| m.dropped_frames as _),
+        ("reconnects_total", |m| m.reconnects as _),
+    ];
+    let metrics = gauges
+        .iter()
+        .map(|(name, value)| (name, "gauge", value))
+        .chain(counters.iter().map(|(name, value)| (name, "counter", value)));
+    for (name, kind, value) in metrics {
+        writeln!(out, "# TYPE rustdesk_session_{} {}", name, kind).ok();
+        for m in all {
+            writeln!(
+                out,
+                "rustdesk_session_{}{{session=\"{}\",codec=\"{}\"}} {}",
+                name,
+                m.session,
+                m.codec,
+                value(m)
+            )
+            .ok();
+        }
+    }
+    let traffic: [(&str, fn(&SessionMetrics) -> &HashMap<&'static str, u64>); 2] =
+        [("in", |m| &m.bytes_in), ("out", |m| &m.bytes_out)];
+    for (name, bytes) in traffic.iter() {
+        writeln!(out, "# TYPE rustdesk_session_bytes_{}_total counter", name).ok();
+        for m in all {
+            for (t, n) in bytes(m) {
+                writeln!(
+                    out,
+                    "rustdesk_session_bytes_{}_total{{session=\"{}\",type=\"{}\"}} {}",
+                    name, m.session, t, n
+                )
+                .ok();
+            }
+        }
+    }
+    let path = Config::log_path().join("metrics.prom");
+    let tmp = path.with_extension("prom.tmp");
+    std::fs::write(&tmp, out)?;
+    std::fs::rename(tmp, path)
+}
+
+// one line per session each time
+fn write_jsonl(all: &Vec<SessionMetrics>) -> std::io::Result<()> {
+    if all.is_empty() {
+        return Ok(());
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(jsonl_path()?)?;
+    for m in all {
+        if let Ok(line) = serde_json::to_string(m) {
+            writeln!(file, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+// metrics_<date>.jsonl, the oldest files are removed when a new day starts
+fn jsonl_path() -> std::io::Result<PathBuf> {
+    let dir = Config::log_path();
+    let name = format!("metrics_{}.jsonl", chrono::Local::now().format("%Y-%m-%d"));
+    let path = dir.join(name);
+    if !path.exists() {
+        let mut old: Vec<PathBuf> = std::fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|name| name.to_str())
+                    .map_or(false, |name| name.starts_with("metrics_") && name.ends_with(".jsonl"))
+            })
+            .collect();
+        old.sort();
+        let n = old.len().saturating_sub(KEEP_JSONL_FILES - 1);
+        for p in old.drain(..n) {
+            std::fs::remove_file(p).ok();
+        }
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_types() {
+        let cases: [(fn(&mut Message), &str); 25] = [
+            (|m| m.set_signed_id(Default::default()), "signed_id"),
+            (|m| m.set_public_key(Default::default()), "public_key"),
+            (|m| m.set_test_delay(Default::default()), "test_delay"),
+            (|m| m.set_video_frame(Default::default()), "video_frame"),
+            (|m| m.set_login_request(Default::default()), "login_request"),
+            (|m| m.set_login_response(Default::default()), "login_response"),
+            (|m| m.set_hash(Default::default()), "hash"),
+            (|m| m.set_mouse_event(Default::default()), "mouse_event"),
+            (|m| m.set_audio_frame(Default::default()), "audio_frame"),
+            (|m| m.set_cursor_data(Default::default()), "cursor_data"),
+            (|m| m.set_cursor_position(Default::default()), "cursor_position"),
+            (|m| m.set_cursor_id(0), "cursor_id"),
+            (|m| m.set_key_event(Default::default()), "key_event"),
+            (|m| m.set_clipboard(Default::default()), "clipboard"),
+            (|m| m.set_file_action(Default::default()), "file_action"),
+            (|m| m.set_file_response(Default::default()), "file_response"),
+            (|m| m.set_misc(Default::default()), "misc"),
+            (|m| m.set_cliprdr(Default::default()), "cliprdr"),
+            (|m| m.set_message_box(Default::default()), "message_box"),
+            (|m| m.set_switch_sides_response(Default::default()), "switch_sides_response"),
+            (|m| m.set_voice_call_request(Default::default()), "voice_call_request"),
+            (|m| m.set_voice_call_response(Default::default()), "voice_call_response"),
+            (|m| m.set_peer_info(Default::default()), "peer_info"),
+            (|m| m.set_tunnel_data(Default::default()), "tunnel_data"),
+            (|m| m.set_auth_2fa(Default::default()), "auth_2fa"),
+        ];
+        let mut seen = std::collections::HashSet::new();
+        for (set, name) in cases {
+            let mut msg = Message::new();
+            set(&mut msg);
+            let i = message_type(&msg);
+            assert_eq!(MESSAGE_TYPES[i], name);
+            assert!(seen.insert(i), "{} shares an index", name);
+        }
+        assert_eq!(MESSAGE_TYPES[message_type(&Message::new())], "unknown");
+        assert_eq!(seen.len() + 1, MESSAGE_TYPES.len());
+    }
+}
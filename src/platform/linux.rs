@@ -4,7 +4,7 @@ use hbb_common::{
     allow_err,
     anyhow::anyhow,
     bail,
-    libc::{c_char, c_int, c_long, c_void},
+    libc::{c_char, c_int, c_long, c_uint, c_ulong, c_void},
     log,
     message_proto::Resolution,
 };
@@ -44,6 +44,46 @@ extern "C" {
 extern "C" {
     fn XOpenDisplay(display_name: *const c_char) -> *mut c_void;
     // fn XCloseDisplay(d: *mut c_void) -> c_int;
+    fn XDefaultRootWindow(d: *mut c_void) -> c_ulong;
+    fn XInternAtom(d: *mut c_void, atom_name: *const c_char, only_if_exists: c_int) -> c_ulong;
+    fn XGetWindowProperty(
+        d: *mut c_void,
+        w: c_ulong,
+        property: c_ulong,
+        long_offset: c_long,
+        long_length: c_long,
+        delete: c_int,
+        req_type: c_ulong,
+        actual_type_return: *mut c_ulong,
+        actual_format_return: *mut c_int,
+        nitems_return: *mut c_ulong,
+        bytes_after_return: *mut c_ulong,
+        prop_return: *mut *mut u8,
+    ) -> c_int;
+    fn XGetGeometry(
+        d: *mut c_void,
+        drawable: c_ulong,
+        root_return: *mut c_ulong,
+        x_return: *mut c_int,
+        y_return: *mut c_int,
+        width_return: *mut c_uint,
+        height_return: *mut c_uint,
+        border_width_return: *mut c_uint,
+        depth_return: *mut c_uint,
+    ) -> c_int;
+    fn XTranslateCoordinates(
+        d: *mut c_void,
+        src_w: c_ulong,
+        dest_w: c_ulong,
+        src_x: c_int,
+        src_y: c_int,
+        dest_x_return: *mut c_int,
+        dest_y_return: *mut c_int,
+        child_return: *mut c_ulong,
+    ) -> c_int;
+    fn XSetErrorHandler(
+        handler: Option<unsafe extern "C" fn(*mut c_void, *mut c_void) -> c_int>,
+    ) -> *mut c_void;
 }
 
 #[link(name = "Xfixes")]
@@ -86,6 +126,116 @@ pub fn get_cursor_pos() -> Option<(i32, i32)> {
 
 pub fn reset_input_cache() {}
 
+// a window may be gone between listing and querying it, which must not kill the process
+unsafe extern "C" fn ignore_x_error(_d: *mut c_void, _e: *mut c_void) -> c_int {
+    0
+}
+
+// The items of a window property, to be freed with XFree.
+unsafe fn get_window_property(d: *mut c_void, w: c_ulong, name: &str) -> Option<(*mut u8, usize)> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let atom = XInternAtom(d, name.as_ptr(), 1);
+    if atom == 0 {
+        return None;
+    }
+    let mut actual_type = 0;
+    let mut actual_format = 0;
+    let mut nitems = 0;
+    let mut bytes_after = 0;
+    let mut prop = std::ptr::null_mut();
+    // AnyPropertyType
+    if XGetWindowProperty(
+        d,
+        w,
+        atom,
+        0,
+        1024,
+        0,
+        0,
+        &mut actual_type,
+        &mut actual_format,
+        &mut nitems,
+        &mut bytes_after,
+        &mut prop,
+    ) != 0
+        || prop.is_null()
+    {
+        return None;
+    }
+    Some((prop, nitems as _))
+}
+
+/// The windows managed by the window manager, as (window, title).
+pub fn get_windows() -> Vec<(u64, String)> {
+    let mut windows = Vec::new();
+    DISPLAY.with(|conn| {
+        if let Ok(d) = conn.try_borrow_mut() {
+            if d.is_null() {
+                return;
+            }
+            unsafe {
+                XSetErrorHandler(Some(ignore_x_error));
+                let root = XDefaultRootWindow(*d);
+                if let Some((prop, n)) = get_window_property(*d, root, "_NET_CLIENT_LIST") {
+                    let ids = std::slice::from_raw_parts(prop as *const c_ulong, n).to_vec();
+                    XFree(prop as _);
+                    for id in ids {
+                        let name = get_window_property(*d, id, "_NET_WM_NAME")
+                            .or_else(|| get_window_property(*d, id, "WM_NAME"));
+                        if let Some((prop, n)) = name {
+                            let title = std::slice::from_raw_parts(prop, n);
+                            let title = String::from_utf8_lossy(title).to_string();
+                            XFree(prop as _);
+                            if !title.is_empty() {
+                                windows.push((id as u64, title));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    });
+    windows
+}
+
+/// The rectangle of a window in screen coordinates, as (x, y, width, height).
+pub fn get_window_rect(id: u64) -> Option<(i32, i32, i32, i32)> {
+    let mut res = None;
+    DISPLAY.with(|conn| {
+        if let Ok(d) = conn.try_borrow_mut() {
+            if d.is_null() {
+                return;
+            }
+            unsafe {
+                XSetErrorHandler(Some(ignore_x_error));
+                let (mut root, mut x, mut y, mut width, mut height, mut border, mut depth) =
+                    (0, 0, 0, 0, 0, 0, 0);
+                if XGetGeometry(
+                    *d,
+                    id as _,
+                    &mut root,
+                    &mut x,
+                    &mut y,
+                    &mut width,
+                    &mut height,
+                    &mut border,
+                    &mut depth,
+                ) == 0
+                {
+                    return;
+                }
+                let mut child = 0;
+                if XTranslateCoordinates(*d, id as _, root, 0, 0, &mut x, &mut y, &mut child) == 0
+                {
+                    return;
+                }
+                res = Some((x, y, width as i32, height as i32));
+            }
+        }
+    });
+    res
+}
+
 pub fn get_cursor() -> ResultType<Option<u64>> {
     let mut res = None;
     DISPLAY.with(|conn| {
@@ -516,7 +666,7 @@ pub fn get_pa_monitor() -> String {
     get_pa_sources()
         .drain(..)
         .map(|x| x.0)
-        .filter(|x| x.contains("monitor"))
+        .filter(|x| x.contains("monitor") && !is_rustdesk_pa_device(x))
         .next()
         .unwrap_or("".to_owned())
 }
@@ -569,6 +719,180 @@ pub fn get_default_pa_source() -> Option<(String, String)> {
     None
 }
 
+/// The prefix of the "audio-input" option to capture the streams of an application.
+pub const PA_APP_PREFIX: &str = "app:";
+const PA_APP_SINK: &str = "rustdesk_app";
+
+#[inline]
+fn is_rustdesk_pa_device(name: &str) -> bool {
+    name.starts_with(crate::audio_service::mic::SINK_PREFIX) || name.starts_with(PA_APP_SINK)
+}
+
+/// Unloads the modules with the device `name` in the arguments,
+/// left by a process not exiting normally.
+pub fn unload_pa_modules(name: &str) {
+    let cmd = format!(
+        "pactl list short modules | grep -E '={}( |\\.monitor|$)' | cut -f1",
+        name
+    );
+    if let Ok(out) = run_cmds(cmd) {
+        for index in out.split_whitespace() {
+            allow_err!(run_cmds(format!("pactl unload-module {}", index)));
+        }
+    }
+}
+
+/// The order of WAVEFORMATEXTENSIBLE for more than 2 channels, see `common::audio_streams`.
+pub fn pa_channel_map(channels: u8) -> Option<pulse::channelmap::Map> {
+    if channels <= 2 {
+        return None;
+    }
+    let mut map = pulse::channelmap::Map::default();
+    map.init_auto(channels, pulse::channelmap::MapDef::WAVEEx)?;
+    Some(map)
+}
+
+/// The channels of the default sink.
+pub fn get_pa_sink_channels() -> Option<u32> {
+    let sink = get_pa_default_sink();
+    if sink.is_empty() {
+        return None;
+    }
+    // index, name, driver, sample spec like "float32le 6ch 48000Hz", state
+    run_cmds("LANG=C pactl list short sinks".to_owned())
+        .ok()?
+        .lines()
+        .find(|line| line.split_whitespace().nth(1) == Some(sink.as_str()))?
+        .split_whitespace()
+        .find_map(|x| x.strip_suffix("ch").and_then(|x| x.parse().ok()))
+}
+
+fn get_pa_default_sink() -> String {
+    run_cmds("LANG=C pactl info | grep \"Default Sink:\" | cut -d: -f2".to_owned())
+        .unwrap_or_default()
+        .trim()
+        .to_owned()
+}
+
+/// (index, application name) of the playing streams.
+pub fn get_pa_sink_inputs() -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut index = None;
+    for line in run_cmds("LANG=C pactl list sink-inputs".to_owned())
+        .unwrap_or_default()
+        .lines()
+    {
+        let line = line.trim();
+        if let Some(i) = line.strip_prefix("Sink Input #") {
+            index = Some(i.to_owned());
+        } else if let Some(name) = line.strip_prefix("application.name = ") {
+            if let Some(i) = index.take() {
+                out.push((i, name.trim_matches('"').to_owned()));
+            }
+        }
+    }
+    out
+}
+
+/// (value of the "audio-input" option, description, is application) of the sources
+/// and the applications playing audio.
+pub fn get_audio_sources() -> Vec<(String, String, bool)> {
+    let mut out: Vec<(String, String, bool)> = get_pa_sources()
+        .drain(..)
+        .filter(|x| !is_rustdesk_pa_device(&x.0))
+        .map(|x| (x.1.clone(), x.1, false))
+        .collect();
+    for (_, app) in get_pa_sink_inputs() {
+        let value = format!("{}{}", PA_APP_PREFIX, app);
+        if out.iter().all(|x| x.0 != value) {
+            out.push((value, app, true));
+        }
+    }
+    out
+}
+
+/// Captures the streams of an application only, by moving them to a null sink
+/// looped back to the default sink, whose monitor is recorded.
+/// Works with both pulseaudio and pipewire-pulse, the streams are moved back when dropped.
+pub struct PaAppCapture {
+    app: String,
+    sink: String,
+    modules: Vec<String>,
+    running: Arc<AtomicBool>,
+}
+
+impl PaAppCapture {
+    pub fn new(app: &str) -> Option<Self> {
+        unload_pa_modules(PA_APP_SINK);
+        let sink = get_pa_default_sink();
+        if sink.is_empty() || sink.starts_with(PA_APP_SINK) {
+            log::error!("No default sink to capture {}", app);
+            return None;
+        }
+        let mut capture = Self {
+            app: app.to_owned(),
+            sink,
+            modules: Vec::new(),
+            running: Arc::new(AtomicBool::new(true)),
+        };
+        for args in [
+            format!(
+                "module-null-sink sink_name={} sink_properties=device.description=RustDesk-App",
+                PA_APP_SINK
+            ),
+            format!(
+                "module-loopback source={}.monitor sink={} latency_msec=20",
+                PA_APP_SINK, capture.sink
+            ),
+        ] {
+            let index = run_cmds(format!("pactl load-module {}", args)).unwrap_or_default();
+            let index = index.trim();
+            if index.is_empty() {
+                log::error!("Failed to load {}", args);
+                return None;
+            }
+            capture.modules.push(index.to_owned());
+        }
+        move_pa_app_streams(app, PA_APP_SINK);
+        // the streams of the application started later, not blocking the recording
+        let app = app.to_owned();
+        let running = capture.running.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(3));
+            if !running.load(Ordering::SeqCst) {
+                break;
+            }
+            move_pa_app_streams(&app, PA_APP_SINK);
+        });
+        log::info!("Capture the audio of {}", capture.app);
+        Some(capture)
+    }
+
+    /// The source to record.
+    pub fn source(&self) -> String {
+        format!("{}.monitor", PA_APP_SINK)
+    }
+
+}
+
+fn move_pa_app_streams(app: &str, sink: &str) {
+    for (index, name) in get_pa_sink_inputs() {
+        if name == app {
+            allow_err!(run_cmds(format!("pactl move-sink-input {} {}", index, sink)));
+        }
+    }
+}
+
+impl Drop for PaAppCapture {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        move_pa_app_streams(&self.app, &self.sink);
+        for index in self.modules.iter().rev() {
+            allow_err!(run_cmds(format!("pactl unload-module {}", index)));
+        }
+    }
+}
+
 pub fn lock_screen() {
     Command::new("xdg-screensaver").arg("lock").spawn().ok();
 }
@@ -569,6 +569,15 @@ pub fn get_double_click_time() -> u32 {
     500 as _
 }
 
+// to-do: window capture is not supported on macos yet
+pub fn get_windows() -> Vec<(u64, String)> {
+    vec![]
+}
+
+pub fn get_window_rect(_id: u64) -> Option<(i32, i32, i32, i32)> {
+    None
+}
+
 pub fn hide_dock() {
     unsafe {
         NSApp().setActivationPolicy_(NSApplicationActivationPolicyAccessory);
@@ -1709,6 +1709,47 @@ pub fn is_foreground_window_elevated() -> ResultType<bool> {
     }
 }
 
+/// The visible top-level windows with a title, as (handle, title).
+pub fn get_windows() -> Vec<(u64, String)> {
+    unsafe extern "system" fn enum_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let windows = &mut *(lparam as *mut Vec<(u64, String)>);
+        if IsWindowVisible(hwnd) == FALSE || IsIconic(hwnd) != FALSE {
+            return TRUE;
+        }
+        let mut buf = [0u16; 256];
+        let len = GetWindowTextW(hwnd, buf.as_mut_ptr(), buf.len() as _);
+        if len > 0 {
+            windows.push((hwnd as u64, String::from_utf16_lossy(&buf[..len as usize])));
+        }
+        TRUE
+    }
+    let mut windows: Vec<(u64, String)> = Vec::new();
+    unsafe {
+        EnumWindows(Some(enum_window), &mut windows as *mut _ as LPARAM);
+    }
+    windows
+}
+
+/// The rectangle of a window in screen coordinates, as (x, y, width, height).
+pub fn get_window_rect(id: u64) -> Option<(i32, i32, i32, i32)> {
+    unsafe {
+        let hwnd = id as HWND;
+        if IsWindow(hwnd) == FALSE || IsIconic(hwnd) != FALSE {
+            return None;
+        }
+        let mut rect: RECT = mem::zeroed();
+        if GetWindowRect(hwnd, &mut rect) == FALSE {
+            return None;
+        }
+        Some((
+            rect.left,
+            rect.top,
+            rect.right - rect.left,
+            rect.bottom - rect.top,
+        ))
+    }
+}
+
 fn get_current_pid() -> u32 {
     unsafe { GetCurrentProcessId() }
 }
@@ -1,20 +1,67 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
 
 use crate::client::*;
 use hbb_common::{
-    allow_err, bail,
-    config::READ_TIMEOUT,
+    allow_err,
+    anyhow::anyhow,
+    bail,
+    bytes::{Bytes, BytesMut},
+    config::{LocalConfig, READ_TIMEOUT},
     futures::{SinkExt, StreamExt},
     log,
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
     tcp, timeout,
-    tokio::{self, net::TcpStream, sync::mpsc},
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream, UdpSocket},
+        sync::{mpsc, Mutex as TokioMutex},
+        time::{Duration, Instant},
+    },
     tokio_util::codec::{BytesCodec, Framed},
+    tunnel::{self, TcpTunnels},
     ResultType, Stream,
 };
 
+/// What the peer connects to for the connections accepted by a local listener.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForwardTarget {
+    /// A fixed host and port.
+    Static(String, i32),
+    /// SOCKS5, the host and port are requested by the local application.
+    Dynamic,
+}
+
+/// Returned by the login of a port forward the peer does not support yet.
+/// The peer does not take the tunnels, the forwards fall back to one session per connection.
+#[derive(Debug)]
+pub struct PeerTooOld;
+
+impl std::fmt::Display for PeerTooOld {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The remote side does not support it, please upgrade it")
+    }
+}
+
+impl std::error::Error for PeerTooOld {}
+
+/// The local address of the listeners, loopback unless the local option
+/// `port-forward-bind-address` widens it, e.g. to 0.0.0.0.
+pub fn bind_address() -> String {
+    let addr = LocalConfig::get_option("port-forward-bind-address");
+    if addr.is_empty() {
+        "127.0.0.1".to_owned()
+    } else {
+        addr
+    }
+}
+
 fn run_rdp(port: u16) {
     std::process::Command::new("cmdkey")
         .arg("/delete:localhost")
@@ -54,7 +101,7 @@ pub async fn listen(
     remote_host: String,
     remote_port: i32,
 ) -> ResultType<()> {
-    let listener = tcp::new_listener(format!("0.0.0.0:{}", port), true).await?;
+    let listener = tcp::new_listener(format!("{}:{}", bind_address(), port), true).await?;
     let addr = listener.local_addr()?;
     log::info!("listening on port {:?}", addr);
     let is_rdp = port == 0;
@@ -70,7 +117,7 @@ pub async fn listen(
                 let id = id.clone();
                 let password = password.clone();
                 let mut forward = Framed::new(forward, BytesCodec::new());
-                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), &mut forward, key, token, is_rdp).await {
+                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), Some(&mut forward), key, token, is_rdp).await {
                     Ok(Some(stream)) => {
                         let interface = interface.clone();
                         tokio::spawn(async move {
@@ -102,12 +149,551 @@ pub async fn listen(
     Ok(())
 }
 
+/// Listen on several local ports of the same peer in one process.
+///
+/// The connections accepted on all the ports are carried by one session as tcp tunnels,
+/// each opened with its own target, so that SOCKS5 clients do not wait for each other.
+/// Peers without tcp tunnels get one session per connection, the logins are serialized
+/// because `lc.port_forward` is switched for each of them.
+pub async fn listen_multiple(
+    id: String,
+    password: String,
+    forwards: Vec<(i32, ForwardTarget)>,
+    bind: String,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+) -> ResultType<()> {
+    let mut listeners = Vec::new();
+    for (port, target) in forwards {
+        let listener = tcp::new_listener(format!("{}:{}", bind, port), true).await?;
+        log::info!(
+            "listening on port {:?} for {:?}",
+            listener.local_addr()?,
+            target
+        );
+        listeners.push((listener, target));
+    }
+    let mut ui_receiver = ui_receiver;
+    lc.write().unwrap().port_forward_tcp_tunnel = true;
+    let res = connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        key,
+        token,
+        false,
+    )
+    .await;
+    lc.write().unwrap().port_forward_tcp_tunnel = false;
+    match res {
+        Ok(Some(stream)) => run_tcp_tunnels(stream, listeners, ui_receiver).await,
+        Ok(None) => Ok(()),
+        Err(err) if err.downcast_ref::<PeerTooOld>().is_some() => {
+            log::warn!("No tcp tunnel, one session per connection");
+            let login_lock = Arc::new(TokioMutex::new(ui_receiver));
+            let mut tasks = Vec::new();
+            for (listener, target) in listeners {
+                tasks.push(tokio::spawn(accept_loop(
+                    listener,
+                    target,
+                    id.clone(),
+                    password.clone(),
+                    interface.clone(),
+                    login_lock.clone(),
+                    key.to_owned(),
+                    token.to_owned(),
+                    lc.clone(),
+                )));
+            }
+            for task in tasks {
+                task.await??;
+            }
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// The SOCKS5 reply is sent once the peer has opened the tunnel.
+async fn run_tcp_tunnels(
+    mut stream: Stream,
+    listeners: Vec<(TcpListener, ForwardTarget)>,
+    mut ui_receiver: mpsc::UnboundedReceiver<Data>,
+) -> ResultType<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<(TcpStream, String, bool)>();
+    let tasks: Vec<_> = listeners
+        .into_iter()
+        .map(|(listener, target)| tokio::spawn(accept_tunnels(listener, target, tx.clone())))
+        .collect();
+    let mut tunnels = TcpTunnels::new();
+    let mut pending: HashMap<i32, (TcpStream, bool)> = Default::default();
+    let mut next_id = 0;
+    let res = loop {
+        tokio::select! {
+            Some((socket, target, is_dynamic)) = rx.recv() => {
+                next_id += 1;
+                log::info!("tunnel #{} to {}", next_id, target);
+                pending.insert(next_id, (socket, is_dynamic));
+                if let Err(err) = stream.send(&tunnel::new_tunnel_connect(next_id, target)).await {
+                    break Err(err);
+                }
+            }
+            res = stream.next() => {
+                let bytes = match res {
+                    Some(Ok(bytes)) => bytes,
+                    _ => break Err(anyhow!("Reset by the peer")),
+                };
+                let td = match Message::parse_from_bytes(&bytes) {
+                    Ok(Message { union: Some(message::Union::TunnelData(td)), .. }) => td,
+                    _ => continue,
+                };
+                let mut close = false;
+                match td.union {
+                    Some(tunnel_data::Union::Open(_)) => {
+                        if let Some((mut socket, is_dynamic)) = pending.remove(&td.id) {
+                            let reply = socks5_reply(SOCKS5_SUCCEEDED);
+                            if is_dynamic && socket.write_all(&reply).await.is_err() {
+                                close = true;
+                            } else {
+                                tunnels.add(td.id, socket);
+                            }
+                        }
+                    }
+                    Some(tunnel_data::Union::Data(data)) => {
+                        close = !tunnels.write(td.id, data).await;
+                    }
+                    Some(tunnel_data::Union::Close(_)) => {
+                        log::info!("tunnel #{} closed", td.id);
+                        if let Some((mut socket, true)) = pending.remove(&td.id) {
+                            let reply = socks5_reply(SOCKS5_HOST_UNREACHABLE);
+                            allow_err!(socket.write_all(&reply).await);
+                        }
+                        tunnels.remove(td.id);
+                    }
+                    _ => {}
+                }
+                if close {
+                    if let Err(err) = stream.send(&tunnel::new_tunnel_close(td.id)).await {
+                        break Err(err);
+                    }
+                }
+            }
+            Some(event) = tunnels.next() => {
+                if let Err(err) = stream.send(&Message::from(event)).await {
+                    break Err(err);
+                }
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) | None => {
+                        break Ok(());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    };
+    for task in tasks {
+        task.abort();
+    }
+    res
+}
+
+// Sends the accepted sockets with their targets, the SOCKS5 handshakes are done in parallel.
+async fn accept_tunnels(
+    listener: TcpListener,
+    target: ForwardTarget,
+    tx: mpsc::UnboundedSender<(TcpStream, String, bool)>,
+) {
+    loop {
+        let (mut socket, addr) = match listener.accept().await {
+            Ok(v) => v,
+            Err(err) => {
+                log::error!("Failed to accept: {}", err);
+                return;
+            }
+        };
+        log::info!("new connection from {:?}", addr);
+        match target {
+            ForwardTarget::Static(ref host, port) => {
+                tx.send((socket, format!("{}:{}", host, port), false)).ok();
+            }
+            ForwardTarget::Dynamic => {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match socks5_handshake(&mut socket).await {
+                        Ok((host, port)) => {
+                            tx.send((socket, format!("{}:{}", host, port), true)).ok();
+                        }
+                        Err(err) => {
+                            log::error!("SOCKS5 handshake with {:?} failed: {}", addr, err);
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    target: ForwardTarget,
+    id: String,
+    password: String,
+    interface: impl Interface,
+    login_lock: Arc<TokioMutex<mpsc::UnboundedReceiver<Data>>>,
+    key: String,
+    token: String,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+) -> ResultType<()> {
+    loop {
+        let (mut socket, addr) = listener.accept().await?;
+        log::info!("new connection from {:?}", addr);
+        let target = target.clone();
+        let id = id.clone();
+        let password = password.clone();
+        let interface = interface.clone();
+        let login_lock = login_lock.clone();
+        let key = key.clone();
+        let token = token.clone();
+        let lc = lc.clone();
+        tokio::spawn(async move {
+            let is_dynamic = target == ForwardTarget::Dynamic;
+            let (host, port) = match target {
+                ForwardTarget::Static(host, port) => (host, port),
+                ForwardTarget::Dynamic => match socks5_handshake(&mut socket).await {
+                    Ok(v) => v,
+                    Err(err) => {
+                        log::error!("SOCKS5 handshake with {:?} failed: {}", addr, err);
+                        return;
+                    }
+                },
+            };
+            let mut forward = Framed::new(socket, BytesCodec::new());
+            let res = {
+                let mut ui_receiver = login_lock.lock().await;
+                lc.write().unwrap().port_forward = (host.clone(), port);
+                connect_and_login(
+                    &id,
+                    &password,
+                    &mut ui_receiver,
+                    interface.clone(),
+                    Some(&mut forward),
+                    &key,
+                    &token,
+                    false,
+                )
+                .await
+            };
+            if is_dynamic {
+                let rep = if let Ok(Some(_)) = res {
+                    SOCKS5_SUCCEEDED
+                } else {
+                    SOCKS5_HOST_UNREACHABLE
+                };
+                allow_err!(forward.send(Bytes::from(socks5_reply(rep))).await);
+            }
+            match res {
+                Ok(Some(stream)) => {
+                    if let Err(err) = run_forward(forward, stream).await {
+                        interface.msgbox("error", "Error", &err.to_string(), "");
+                    }
+                    log::info!("connection from {:?} to {}:{} closed", addr, host, port);
+                }
+                Err(err) => {
+                    interface.msgbox("error", "Error", &err.to_string(), "");
+                }
+                _ => {}
+            }
+        });
+    }
+}
+
+/// UDP port forward, the datagrams received on the local `port` are sent to
+/// `remote_host:remote_port` by the peer, each source address is one flow.
+pub async fn listen_udp(
+    id: String,
+    password: String,
+    port: i32,
+    bind: String,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    remote_host: String,
+    remote_port: i32,
+) -> ResultType<()> {
+    let socket = UdpSocket::bind(format!("{}:{}", bind, port)).await?;
+    log::info!("listening on udp port {:?}", socket.local_addr()?);
+    let mut ui_receiver = ui_receiver;
+    {
+        let mut lc = lc.write().unwrap();
+        lc.port_forward = (remote_host, remote_port);
+        lc.port_forward_udp = true;
+    }
+    let mut stream = match connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        key,
+        token,
+        false,
+    )
+    .await?
+    {
+        Some(stream) => stream,
+        None => return Ok(()),
+    };
+    let mut flows: HashMap<SocketAddr, (i32, Instant)> = Default::default();
+    let mut next_id = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut timer = tokio::time::interval(Duration::from_secs(10));
+    loop {
+        tokio::select! {
+            res = socket.recv_from(&mut buf) => {
+                // e.g. ICMP port unreachable of an earlier reply, not fatal for the other flows
+                let (n, addr) = match res {
+                    Ok(res) => res,
+                    Err(err) => {
+                        log::debug!("udp recv error: {}", err);
+                        continue;
+                    }
+                };
+                let id = match flows.get_mut(&addr) {
+                    Some((id, last_active)) => {
+                        *last_active = Instant::now();
+                        *id
+                    }
+                    None => {
+                        next_id += 1;
+                        log::info!("new udp flow #{} from {:?}", next_id, addr);
+                        flows.insert(addr, (next_id, Instant::now()));
+                        next_id
+                    }
+                };
+                let data = Bytes::copy_from_slice(&buf[..n]);
+                stream.send(&tunnel::new_tunnel_data(id, data)).await?;
+            }
+            res = stream.next() => {
+                let bytes = match res {
+                    Some(Ok(bytes)) => bytes,
+                    _ => bail!("Reset by the peer"),
+                };
+                let td = match Message::parse_from_bytes(&bytes)?.union {
+                    Some(message::Union::TunnelData(td)) => td,
+                    _ => continue,
+                };
+                match td.union {
+                    Some(tunnel_data::Union::Data(data)) => {
+                        if let Some((addr, (_, last_active))) =
+                            flows.iter_mut().find(|(_, (id, _))| *id == td.id)
+                        {
+                            *last_active = Instant::now();
+                            allow_err!(socket.send_to(&data, *addr).await);
+                        }
+                    }
+                    Some(tunnel_data::Union::Close(_)) => {
+                        flows.retain(|_, (id, _)| *id != td.id);
+                    }
+                    _ => {}
+                }
+            }
+            _ = timer.tick() => {
+                let mut idle = Vec::new();
+                flows.retain(|_, (id, last_active)| {
+                    if last_active.elapsed() >= tunnel::UDP_FLOW_TIMEOUT {
+                        idle.push(*id);
+                        false
+                    } else {
+                        true
+                    }
+                });
+                for id in idle {
+                    log::info!("udp flow #{} timed out", id);
+                    stream.send(&tunnel::new_tunnel_close(id)).await?;
+                }
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) | None => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reverse port forward, the peer listens on its loopback `remote_port`,
+/// and each connection accepted there is tunneled to `local_host:local_port`.
+pub async fn listen_reverse(
+    id: String,
+    password: String,
+    remote_port: i32,
+    local_host: String,
+    local_port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+) -> ResultType<()> {
+    let mut ui_receiver = ui_receiver;
+    lc.write().unwrap().reverse_port_forward = remote_port;
+    let mut stream = match connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        key,
+        token,
+        false,
+    )
+    .await?
+    {
+        Some(stream) => stream,
+        None => return Ok(()),
+    };
+    let local_addr = format!("{}:{}", local_host, local_port);
+    log::info!(
+        "remote port {} of {} forwarded to {}",
+        remote_port,
+        id,
+        local_addr
+    );
+    let mut tunnels = TcpTunnels::new();
+    loop {
+        tokio::select! {
+            res = stream.next() => {
+                let bytes = match res {
+                    Some(Ok(bytes)) => bytes,
+                    _ => bail!("Reset by the peer"),
+                };
+                let td = match Message::parse_from_bytes(&bytes)?.union {
+                    Some(message::Union::TunnelData(td)) => td,
+                    _ => continue,
+                };
+                match td.union {
+                    Some(tunnel_data::Union::Open(_)) => {
+                        match timeout(3000, TcpStream::connect(&local_addr)).await {
+                            Ok(Ok(socket)) => {
+                                log::info!("reverse connection #{} to {} opened", td.id, local_addr);
+                                tunnels.add(td.id, socket);
+                            }
+                            _ => {
+                                log::error!("failed to connect to {}", local_addr);
+                                stream.send(&tunnel::new_tunnel_close(td.id)).await?;
+                            }
+                        }
+                    }
+                    Some(tunnel_data::Union::Data(data)) => {
+                        if !tunnels.write(td.id, data).await {
+                            stream.send(&tunnel::new_tunnel_close(td.id)).await?;
+                        }
+                    }
+                    Some(tunnel_data::Union::Close(_)) => {
+                        log::info!("reverse connection #{} closed", td.id);
+                        tunnels.remove(td.id);
+                    }
+                    _ => {}
+                }
+            }
+            Some(event) = tunnels.next() => {
+                stream.send(&Message::from(event)).await?;
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) | None => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_NO_AUTH: u8 = 0;
+const SOCKS5_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const SOCKS5_CMD_CONNECT: u8 = 1;
+const SOCKS5_SUCCEEDED: u8 = 0;
+const SOCKS5_HOST_UNREACHABLE: u8 = 4;
+const SOCKS5_CMD_NOT_SUPPORTED: u8 = 7;
+const SOCKS5_ATYP_NOT_SUPPORTED: u8 = 8;
+
+fn socks5_reply(rep: u8) -> Vec<u8> {
+    // bound address is not meaningful here, always 0.0.0.0:0
+    vec![SOCKS5_VERSION, rep, 0, 1, 0, 0, 0, 0, 0, 0]
+}
+
+/// Server side of the SOCKS5 greeting and CONNECT request (RFC 1928), without authentication.
+/// Returns the requested host and port, the reply is sent after the peer has connected.
+async fn socks5_handshake(socket: &mut TcpStream) -> ResultType<(String, i32)> {
+    let mut head = [0u8; 2];
+    socket.read_exact(&mut head).await?;
+    if head[0] != SOCKS5_VERSION {
+        bail!("Unsupported SOCKS version {}", head[0]);
+    }
+    let mut methods = vec![0u8; head[1] as usize];
+    socket.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS5_NO_AUTH) {
+        socket
+            .write_all(&[SOCKS5_VERSION, SOCKS5_NO_ACCEPTABLE_METHOD])
+            .await?;
+        bail!("No acceptable authentication method");
+    }
+    socket.write_all(&[SOCKS5_VERSION, SOCKS5_NO_AUTH]).await?;
+    let mut req = [0u8; 4];
+    socket.read_exact(&mut req).await?;
+    if req[1] != SOCKS5_CMD_CONNECT {
+        socket.write_all(&socks5_reply(SOCKS5_CMD_NOT_SUPPORTED)).await?;
+        bail!("Unsupported SOCKS command {}", req[1]);
+    }
+    let host = match req[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            socket.read_exact(&mut ip).await?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        3 => {
+            let len = socket.read_u8().await?;
+            let mut domain = vec![0u8; len as usize];
+            socket.read_exact(&mut domain).await?;
+            String::from_utf8_lossy(&domain).to_string()
+        }
+        4 => {
+            let mut ip = [0u8; 16];
+            socket.read_exact(&mut ip).await?;
+            format!("[{}]", std::net::Ipv6Addr::from(ip))
+        }
+        atyp => {
+            socket.write_all(&socks5_reply(SOCKS5_ATYP_NOT_SUPPORTED)).await?;
+            bail!("Unsupported SOCKS address type {}", atyp);
+        }
+    };
+    let port = socket.read_u16().await?;
+    Ok((host, port as _))
+}
+
 async fn connect_and_login(
     id: &str,
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
     key: &str,
     token: &str,
     is_rdp: bool,
@@ -117,7 +703,7 @@ async fn connect_and_login(
         password,
         ui_receiver,
         interface.clone(),
-        forward,
+        forward.as_deref_mut(),
         key,
         token,
         is_rdp,
@@ -144,7 +730,7 @@ async fn connect_and_login_2(
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
     key: &str,
     token: &str,
     is_rdp: bool,
@@ -169,12 +755,16 @@ async fn connect_and_login_2(
                     let msg_in = Message::parse_from_bytes(&bytes)?;
                     match msg_in.union {
                         Some(message::Union::Hash(hash)) => {
+                            check_peer_version(&interface.get_login_config_handler(), &hash)?;
                             interface.handle_hash(password, hash, &mut stream).await;
                         }
                         Some(message::Union::LoginResponse(lr)) => match lr.union {
                             Some(login_response::Union::Error(err)) => {
-                                interface.handle_login_error(&err);
-                                return Ok(None);
+                                // the 2fa code is sent on the same connection
+                                let retry = interface.handle_login_error(&err);
+                                if !retry || !is_2fa_login_error(&err) {
+                                    return Ok(None);
+                                }
                             }
                             Some(login_response::Union::PeerInfo(pi)) => {
                                 interface.handle_peer_info(pi);
@@ -202,10 +792,16 @@ async fn connect_and_login_2(
                     Some(Data::Login((password, remember))) => {
                         interface.handle_login_from_ui(password, remember, &mut stream).await;
                     }
+                    Some(Data::Message(msg)) => {
+                        stream.send(&msg).await?;
+                    }
+                    Some(Data::Close) => {
+                        return Ok(None);
+                    }
                     _ => {}
                 }
             },
-            res = forward.next() => {
+            res = next_forward(&mut forward) => {
                 if let Some(Ok(bytes)) = res {
                     buffer.extend(bytes);
                 } else {
@@ -214,13 +810,38 @@ async fn connect_and_login_2(
             },
         }
     }
+    if forward.is_none() {
+        // reverse forward, the stream carries TunnelData messages
+        return Ok(Some(stream));
+    }
     stream.set_raw();
     if !buffer.is_empty() {
-        allow_err!(stream.send_bytes(buffer.into()).await);
+        allow_err!(stream.send_raw(buffer).await);
     }
     Ok(Some(stream))
 }
 
+// Peers older than the tunnels do not tell the tunnel version before login, and take a tcp or
+// udp tunnel as a classic port forward, or a reverse forward as a remote desktop session.
+fn check_peer_version(lc: &Arc<RwLock<LoginConfigHandler>>, hash: &Hash) -> ResultType<()> {
+    let lc = lc.read().unwrap();
+    let tunneled = lc.port_forward_tcp_tunnel || lc.port_forward_udp || lc.reverse_port_forward > 0;
+    if hash.tunnel_version < tunnel::TUNNEL_VERSION && tunneled {
+        return Err(PeerTooOld.into());
+    }
+    Ok(())
+}
+
+#[inline]
+async fn next_forward(
+    forward: &mut Option<&mut Framed<TcpStream, BytesCodec>>,
+) -> Option<Result<BytesMut, std::io::Error>> {
+    match forward {
+        Some(forward) => forward.next().await,
+        None => std::future::pending().await,
+    }
+}
+
 async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) -> ResultType<()> {
     log::info!("new port forwarding connection started");
     let mut forward = forward;
@@ -229,7 +850,7 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
         tokio::select! {
             res = forward.next() => {
                 if let Some(Ok(bytes)) = res {
-                    allow_err!(stream.send_bytes(bytes.into()).await);
+                    allow_err!(stream.send_raw(bytes.to_vec()).await);
                 } else {
                     break;
                 }
@@ -30,6 +30,7 @@ use service::{GenericService, Service, Subscriber};
 
 use crate::ipc::Data;
 
+mod access_policy;
 pub mod audio_service;
 cfg_if::cfg_if! {
 if #[cfg(not(any(target_os = "android", target_os = "ios")))] {
@@ -93,6 +94,9 @@ pub fn new() -> ServerPtr {
     server.add_service(Box::new(video_service::new()));
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     {
+        for i in 0..video_service::DISPLAY_NAMES.len() {
+            server.add_service(Box::new(video_service::new_display(i)));
+        }
         server.add_service(Box::new(clipboard_service::new()));
         if !video_service::capture_cursor_embedded() {
             server.add_service(Box::new(input_service::new_cursor()));
@@ -142,24 +146,27 @@ pub async fn create_tcp_connection(
         w.id_count
     };
     let (sk, pk) = Config::get_key_pair();
+    // signed by the key login of the peer
+    let mut channel_binding = Vec::new();
     if secure && pk.len() == sign::PUBLICKEYBYTES && sk.len() == sign::SECRETKEYBYTES {
         let mut sk_ = [0u8; sign::SECRETKEYBYTES];
         sk_[..].copy_from_slice(&sk);
         let sk = sign::SecretKey(sk_);
         let mut msg_out = Message::new();
         let (our_pk_b, our_sk_b) = box_::gen_keypair();
+        let signed_id = sign::sign(
+            &IdPk {
+                id: Config::get_id(),
+                pk: Bytes::from(our_pk_b.0.to_vec()),
+                kx_version: crate::common::KX_VERSION,
+                ..Default::default()
+            }
+            .write_to_bytes()
+            .unwrap_or_default(),
+            &sk,
+        );
         msg_out.set_signed_id(SignedId {
-            id: sign::sign(
-                &IdPk {
-                    id: Config::get_id(),
-                    pk: Bytes::from(our_pk_b.0.to_vec()),
-                    ..Default::default()
-                }
-                .write_to_bytes()
-                .unwrap_or_default(),
-                &sk,
-            )
-            .into(),
+            id: signed_id.clone().into(),
             ..Default::default()
         });
         timeout(CONNECT_TIMEOUT, stream.send(&msg_out)).await??;
@@ -168,7 +175,19 @@ pub async fn create_tcp_connection(
                 let bytes = res?;
                 if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                     if let Some(message::Union::PublicKey(pk)) = msg_in.union {
-                        if pk.asymmetric_value.len() == box_::PUBLICKEYBYTES {
+                        if pk.kx_version >= crate::common::KX_VERSION
+                            && pk.asymmetric_value.len() == box_::PUBLICKEYBYTES
+                        {
+                            let mut pk_ = [0u8; box_::PUBLICKEYBYTES];
+                            pk_[..].copy_from_slice(&pk.asymmetric_value);
+                            let transcript =
+                                crate::common::kx_transcript(&signed_id, &pk.asymmetric_value);
+                            let their_pk_b = box_::PublicKey(pk_);
+                            let key = crate::common::kx_key(&their_pk_b, &our_sk_b, &transcript);
+                            stream.set_session_key(key, true);
+                            crate::common::kx_confirm(&mut stream, &transcript, true).await?;
+                            channel_binding = transcript;
+                        } else if pk.asymmetric_value.len() == box_::PUBLICKEYBYTES {
                             let nonce = box_::Nonce([0u8; box_::NONCEBYTES]);
                             let mut pk_ = [0u8; box_::PUBLICKEYBYTES];
                             pk_[..].copy_from_slice(&pk.asymmetric_value);
@@ -184,6 +203,7 @@ pub async fn create_tcp_connection(
                             let mut key = [0u8; secretbox::KEYBYTES];
                             key[..].copy_from_slice(&symmetric_key);
                             stream.set_key(secretbox::Key(key));
+                            channel_binding = signed_id;
                         } else if pk.asymmetric_value.is_empty() {
                             Config::set_key_confirmed(false);
                             log::info!("Force to update pk");
@@ -213,7 +233,7 @@ pub async fn create_tcp_connection(
             .ok();
         log::info!("wake up macos");
     }
-    Connection::start(addr, stream, id, Arc::downgrade(&server)).await;
+    Connection::start(addr, stream, id, Arc::downgrade(&server), channel_binding).await;
     Ok(())
 }
 
@@ -279,7 +299,8 @@ async fn create_relay_connection_(
 impl Server {
     pub fn add_connection(&mut self, conn: ConnInner, noperms: &Vec<&'static str>) {
         for s in self.services.values() {
-            if !noperms.contains(&s.name()) {
+            // the other displays are subscribed on request of the peer
+            if !noperms.contains(&s.name()) && !video_service::DISPLAY_NAMES.contains(&s.name()) {
                 s.on_subscribe(conn.clone());
             }
         }
@@ -0,0 +1,212 @@
+// Per-peer access policies, a json array in the option "access-policies", e.g.
+// [{"peer": "192.168.1.0/24", "keyboard": false, "hours": ["09:00-18:00"]}]
+// The first policy matching the ip of the peer applies, or else the first "*", in either case
+// with what the first policy matching its id or name denies. A permission left unset falls back
+// to the global option. The id and the name are given by the peer, only the ip can not be faked,
+// so a policy matching them can only deny a permission or restrict the hours.
+
+use chrono::{Local, NaiveTime, Timelike};
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{config::Config, log};
+use serde_derive::{Deserialize, Serialize};
+use std::{net::IpAddr, str::FromStr};
+
+pub const OPTION: &str = "access-policies";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Match {
+    Ip,
+    // the id or the name told by the peer
+    Peer,
+    Any,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    // peer id, user name of the peer, ip or cidr, "*" for any
+    pub peer: String,
+    pub keyboard: Option<bool>,
+    pub clipboard: Option<bool>,
+    pub audio: Option<bool>,
+    pub file: Option<bool>,
+    pub restart: Option<bool>,
+    pub recording: Option<bool>,
+    // port forwarding and rdp
+    pub tunnel: Option<bool>,
+    // "HH:MM-HH:MM" of the local time, may cross midnight, any time if empty
+    pub hours: Vec<String>,
+    // of the policy matching the id or the name, in addition to `hours`
+    #[serde(skip)]
+    peer_hours: Vec<String>,
+}
+
+impl Policy {
+    pub fn find(id: &str, name: &str, ip: &str) -> Option<Policy> {
+        let policies = Config::get_option(OPTION);
+        if policies.is_empty() {
+            return None;
+        }
+        match serde_json::from_str::<Vec<Policy>>(&policies) {
+            Ok(policies) => Self::find_in(&policies, id, name, ip),
+            Err(err) => {
+                log::error!("Invalid {}: {}", OPTION, err);
+                None
+            }
+        }
+    }
+
+    fn find_in(policies: &[Policy], id: &str, name: &str, ip: &str) -> Option<Policy> {
+        let find = |m: Match| policies.iter().find(|p| p.matches(id, name, ip) == Some(m));
+        let base = find(Match::Ip).or_else(|| find(Match::Any)).cloned();
+        match find(Match::Peer) {
+            Some(p) => Some(base.unwrap_or_default().deny(p)),
+            None => base,
+        }
+    }
+
+    fn matches(&self, id: &str, name: &str, ip: &str) -> Option<Match> {
+        let peer = self.peer.trim();
+        if peer.is_empty() {
+            return None;
+        }
+        if peer == "*" {
+            return Some(Match::Any);
+        }
+        if let Ok(cidr) = IpCidr::from_str(peer) {
+            return match IpAddr::from_str(ip) {
+                Ok(ip) if cidr.contains(ip) => Some(Match::Ip),
+                _ => None,
+            };
+        }
+        if peer == id || peer == name {
+            return Some(Match::Peer);
+        }
+        None
+    }
+
+    // adds what `other` denies
+    fn deny(mut self, other: &Policy) -> Self {
+        for (p, q) in [
+            (&mut self.keyboard, other.keyboard),
+            (&mut self.clipboard, other.clipboard),
+            (&mut self.audio, other.audio),
+            (&mut self.file, other.file),
+            (&mut self.restart, other.restart),
+            (&mut self.recording, other.recording),
+            (&mut self.tunnel, other.tunnel),
+        ] {
+            if q == Some(false) {
+                *p = Some(false);
+            }
+        }
+        self.peer_hours = other.hours.clone();
+        self
+    }
+
+    /// The permission of the name used by the connection manager,
+    /// `default` if the policy leaves it unset.
+    pub fn permission(&self, name: &str, default: bool) -> bool {
+        match name {
+            "keyboard" => self.keyboard,
+            "clipboard" => self.clipboard,
+            "audio" => self.audio,
+            "file" => self.file,
+            "restart" => self.restart,
+            "recording" => self.recording,
+            "tunnel" => self.tunnel,
+            _ => None,
+        }
+        .unwrap_or(default)
+    }
+
+    pub fn in_hours(&self) -> bool {
+        let now = Local::now().time();
+        self.in_hours_at(now.hour() * 60 + now.minute())
+    }
+
+    fn in_hours_at(&self, minute: u32) -> bool {
+        in_windows(&self.hours, minute) && in_windows(&self.peer_hours, minute)
+    }
+}
+
+fn in_windows(hours: &[String], minute: u32) -> bool {
+    if hours.is_empty() {
+        return true;
+    }
+    hours.iter().any(|w| match parse_window(w) {
+        Some((from, to)) if from <= to => minute >= from && minute < to,
+        Some((from, to)) => minute >= from || minute < to,
+        None => {
+            log::warn!("Invalid hours of access policy: {}", w);
+            false
+        }
+    })
+}
+
+// minutes of the day
+fn parse_window(w: &str) -> Option<(u32, u32)> {
+    let (from, to) = w.split_once('-')?;
+    let minute = |s: &str| {
+        NaiveTime::parse_from_str(s.trim(), "%H:%M")
+            .ok()
+            .map(|t| t.hour() * 60 + t.minute())
+    };
+    Some((minute(from)?, minute(to)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let p = Policy {
+            peer: "10.0.0.0/8".to_owned(),
+            keyboard: Some(false),
+            hours: vec!["22:00-06:00".to_owned(), "12:00-13:00".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(p.matches("123456789", "", "10.1.2.3"), Some(Match::Ip));
+        assert_eq!(p.matches("123456789", "", "192.168.1.2"), None);
+        assert!(!p.permission("keyboard", true));
+        assert!(p.permission("clipboard", true));
+        assert!(p.in_hours_at(23 * 60));
+        assert!(p.in_hours_at(5 * 60 + 59));
+        assert!(!p.in_hours_at(6 * 60));
+        assert!(p.in_hours_at(12 * 60 + 30));
+        assert!(!p.in_hours_at(13 * 60));
+    }
+
+    #[test]
+    fn test_policy_priority() {
+        let policies: Vec<Policy> = serde_json::from_str(
+            r#"[
+                {"peer": "*", "keyboard": false},
+                {"peer": "123456789", "keyboard": true, "clipboard": false,
+                    "hours": ["09:00-18:00"]},
+                {"peer": "10.0.0.0/8", "file": false}
+            ]"#,
+        )
+        .unwrap();
+        let p = Policy::find_in(&policies, "123456789", "", "10.1.2.3").unwrap();
+        assert_eq!(p.file, Some(false));
+        assert_eq!(p.keyboard, None);
+        assert_eq!(p.clipboard, Some(false));
+        assert!(p.in_hours_at(10 * 60));
+        assert!(!p.in_hours_at(20 * 60));
+        let p = Policy::find_in(&policies, "987654321", "", "10.1.2.3").unwrap();
+        assert_eq!(p.file, Some(false));
+        assert_eq!(p.clipboard, None);
+        assert!(p.in_hours_at(20 * 60));
+        let p = Policy::find_in(&policies, "123456789", "", "192.168.1.2").unwrap();
+        assert_eq!(p.keyboard, Some(false));
+        assert_eq!(p.clipboard, Some(false));
+        assert!(!p.in_hours_at(20 * 60));
+        let p = Policy::find_in(&policies[1..2], "123456789", "", "192.168.1.2").unwrap();
+        assert_eq!(p.keyboard, None);
+        let p = Policy::find_in(&policies, "987654321", "", "192.168.1.2").unwrap();
+        assert_eq!(p.keyboard, Some(false));
+        assert_eq!(p.clipboard, None);
+    }
+}
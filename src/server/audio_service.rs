@@ -21,6 +21,10 @@ pub const NAME: &'static str = "audio";
 pub const AUDIO_DATA_SIZE_U8: usize = 960 * 4; // 10ms in 48000 stereo
 static RESTARTING: AtomicBool = AtomicBool::new(false);
 
+lazy_static::lazy_static! {
+    static ref AUDIO_CHANNELS: Mutex<HashMap<i32, u16>> = Default::default();
+}
+
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 pub fn new() -> GenericService {
     let sp = GenericService::new(NAME, true);
@@ -35,6 +39,29 @@ pub fn new() -> GenericService {
     sp
 }
 
+/// The channels of the output device of a connection, for a multistream format.
+pub fn set_audio_channels(conn_id: i32, channels: u32) {
+    AUDIO_CHANNELS
+        .lock()
+        .unwrap()
+        .insert(conn_id, crate::common::supported_audio_channels(channels as _));
+}
+
+pub fn remove_audio_channels(conn_id: i32) {
+    AUDIO_CHANNELS.lock().unwrap().remove(&conn_id);
+}
+
+// The most channels all the subscribers play, stereo for those not telling theirs.
+#[cfg(not(target_os = "android"))]
+fn negotiate_channels(sp: &GenericService) -> u16 {
+    let channels = AUDIO_CHANNELS.lock().unwrap();
+    sp.subscriber_ids()
+        .iter()
+        .map(|id| channels.get(id).cloned().unwrap_or(2))
+        .min()
+        .unwrap_or(2)
+}
+
 pub fn restart() {
     log::info!("restart the audio service, freezing now...");
     if RESTARTING.load(Ordering::SeqCst) {
@@ -55,29 +82,44 @@ mod pa_impl {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
         }
-        let mut encoder = Encoder::new(crate::platform::PA_SAMPLE_RATE, Stereo, LowDelay)?;
         #[cfg(target_os = "linux")]
-        allow_err!(
-            stream
-                .send(&crate::ipc::Data::Config((
-                    "audio-input".to_owned(),
-                    Some(Config::get_option("audio-input"))
-                )))
-                .await
-        );
-        let zero_audio_frame: Vec<f32> = vec![0.; AUDIO_DATA_SIZE_U8 / 4];
+        let channels = negotiate_channels(&sp);
+        // stereo only by the capture of android
+        #[cfg(target_os = "android")]
+        let channels = 2;
+        let mut encoder = AudioEncoder::new(crate::platform::PA_SAMPLE_RATE, channels)?;
+        #[cfg(target_os = "linux")]
+        for (k, v) in [
+            ("audio-input", Config::get_option("audio-input")),
+            ("audio-channels", channels.to_string()),
+        ] {
+            allow_err!(
+                stream
+                    .send(&crate::ipc::Data::Config((k.to_owned(), Some(v))))
+                    .await
+            );
+        }
+        let data_size = AUDIO_DATA_SIZE_U8 / 2 * channels as usize;
+        let zero_audio_frame: Vec<f32> = vec![0.; data_size / 4];
+        // the subscribers of the last run, with other channels
+        sp.send(create_format_msg(crate::platform::PA_SAMPLE_RATE, channels));
         while sp.ok() && !RESTARTING.load(Ordering::SeqCst) {
             sp.snapshot(|sps| {
-                sps.send(create_format_msg(crate::platform::PA_SAMPLE_RATE, 2));
+                sps.send(create_format_msg(crate::platform::PA_SAMPLE_RATE, channels));
                 Ok(())
             })?;
             #[cfg(target_os = "linux")]
+            if negotiate_channels(&sp) != channels {
+                log::info!("Audio channels changed");
+                break;
+            }
+            #[cfg(target_os = "linux")]
             if let Ok(data) = stream.next_raw().await {
                 if data.len() == 0 {
                     send_f32(&zero_audio_frame, &mut encoder, &sp);
                     continue;
                 }
-                if data.len() != AUDIO_DATA_SIZE_U8 {
+                if data.len() != data_size {
                     continue;
                 }
                 let data = unsafe {
@@ -114,19 +156,36 @@ mod cpal_impl {
     #[derive(Default)]
     pub struct State {
         stream: Option<(Box<dyn StreamTrait>, Arc<Message>)>,
+        channels: u16,
+        // to play again for the subscribers
+        channels_changed: bool,
     }
 
     impl super::service::Reset for State {
         fn reset(&mut self) {
             self.stream.take();
+            self.channels_changed = false;
         }
     }
 
     pub fn run(sp: GenericService, state: &mut State) -> ResultType<()> {
+        let channels = negotiate_channels(&sp);
+        if state.channels != channels {
+            state.channels = channels;
+            if state.stream.take().is_some() {
+                state.channels_changed = true;
+            }
+        }
+        if state.channels_changed {
+            let (stream, format) = play(&sp, channels)?;
+            sp.send_shared(format.clone());
+            state.stream = Some((stream, format));
+            state.channels_changed = false;
+        }
         sp.snapshot(|sps| {
             match &state.stream {
                 None => {
-                    state.stream = Some(play(&sp)?);
+                    state.stream = Some(play(&sp, channels)?);
                 }
                 _ => {}
             }
@@ -142,13 +201,22 @@ mod cpal_impl {
         data: &[f32],
         sample_rate0: u32,
         sample_rate: u32,
+        channels0: u16,
         channels: u16,
-        encoder: &mut Encoder,
+        encoder: &mut AudioEncoder,
         sp: &GenericService,
     ) {
+        let downmixed;
+        let data = if channels0 != channels {
+            downmixed = crate::common::downmix_channels(data, channels0, channels);
+            &downmixed
+        } else {
+            data
+        };
         let buffer;
         let data = if sample_rate0 != sample_rate {
-            buffer = crate::common::resample_channels(data, sample_rate0, sample_rate, channels);
+            buffer =
+                crate::common::resample_multichannel(data, sample_rate0, sample_rate, channels);
             &buffer
         } else {
             data
@@ -212,7 +280,10 @@ mod cpal_impl {
         Ok((device, format))
     }
 
-    fn play(sp: &GenericService) -> ResultType<(Box<dyn StreamTrait>, Arc<Message>)> {
+    fn play(
+        sp: &GenericService,
+        channels: u16,
+    ) -> ResultType<(Box<dyn StreamTrait>, Arc<Message>)> {
         let (device, config) = get_device()?;
         let sp = sp.clone();
         let err_fn = move |err| {
@@ -236,12 +307,9 @@ mod cpal_impl {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
         }
-        let mut encoder = Encoder::new(
-            sample_rate,
-            if config.channels() > 1 { Stereo } else { Mono },
-            LowDelay,
-        )?;
-        let channels = config.channels();
+        let channels0 = config.channels();
+        let channels = crate::common::supported_audio_channels(channels0.min(channels));
+        let mut encoder = AudioEncoder::new(sample_rate, channels)?;
         let stream = match config.sample_format() {
             cpal::SampleFormat::F32 => device.build_input_stream(
                 &config.into(),
@@ -250,6 +318,7 @@ mod cpal_impl {
                         data,
                         sample_rate_0,
                         sample_rate,
+                        channels0,
                         channels,
                         &mut encoder,
                         &sp,
@@ -265,6 +334,7 @@ mod cpal_impl {
                         &buffer,
                         sample_rate_0,
                         sample_rate,
+                        channels0,
                         channels,
                         &mut encoder,
                         &sp,
@@ -280,6 +350,7 @@ mod cpal_impl {
                         &buffer,
                         sample_rate_0,
                         sample_rate,
+                        channels0,
                         channels,
                         &mut encoder,
                         &sp,
@@ -297,11 +368,22 @@ mod cpal_impl {
 }
 
 fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
-    let format = AudioFormat {
+    let mut format = AudioFormat {
         sample_rate,
         channels: channels as _,
         ..Default::default()
     };
+    if channels > 2 {
+        let streams = crate::common::audio_streams(channels);
+        // the coupled streams are listed first
+        let decoded: Vec<usize> = streams.iter().flatten().cloned().collect();
+        format.streams = streams.len() as _;
+        format.coupled_streams = streams.iter().filter(|s| s.len() > 1).count() as _;
+        format.mapping = (0..channels as usize)
+            .map(|c| decoded.iter().position(|x| *x == c).unwrap_or(0) as u8)
+            .collect::<Vec<u8>>()
+            .into();
+    }
     let mut misc = Misc::new();
     misc.set_audio_format(format);
     let mut msg = Message::new();
@@ -315,7 +397,7 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
 const MAX_AUDIO_ZERO_COUNT: u16 = 800;
 static mut AUDIO_ZERO_COUNT: u16 = 0;
 
-fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
+fn send_f32(data: &[f32], encoder: &mut AudioEncoder, sp: &GenericService) {
     if data.iter().filter(|x| **x != 0.).next().is_some() {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
@@ -342,19 +424,10 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
         if input_size > BATCH_SIZE && input_size % BATCH_SIZE == 0 {
             let n = input_size / BATCH_SIZE;
             for i in 0..n {
-                match encoder
-                    .encode_vec_float(&data[i * BATCH_SIZE..(i + 1) * BATCH_SIZE], BATCH_SIZE)
+                if let Some(frame) =
+                    encoder.encode(&data[i * BATCH_SIZE..(i + 1) * BATCH_SIZE], BATCH_SIZE)
                 {
-                    Ok(data) => {
-                        let mut msg_out = Message::new();
-                        msg_out.set_audio_frame(AudioFrame {
-                            data: data.into(),
-                            timestamp: get_time(),
-                            ..Default::default()
-                        });
-                        sp.send(msg_out);
-                    }
-                    Err(_) => {}
+                    send_frame(frame, sp);
                 }
             }
         } else {
@@ -364,16 +437,208 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
     }
 
     #[cfg(not(target_os = "android"))]
-    match encoder.encode_vec_float(data, data.len() * 6) {
-        Ok(data) => {
-            let mut msg_out = Message::new();
-            msg_out.set_audio_frame(AudioFrame {
-                data: data.into(),
-                timestamp: get_time(),
-                ..Default::default()
+    if let Some(frame) = encoder.encode(data, data.len() * 6) {
+        send_frame(frame, sp);
+    }
+}
+
+fn send_frame(frame: AudioFrame, sp: &GenericService) {
+    let mut msg_out = Message::new();
+    msg_out.set_audio_frame(frame);
+    super::video_service::record_audio(&msg_out);
+    sp.send(msg_out);
+}
+
+// Encodes the streams of `common::audio_streams`, the first one in `AudioFrame::data`,
+// which is the front pair of more channels, so that the recorder keeps the stereo.
+// Each stream is an independent opus packet rather than a multistream packet,
+// which the older peers and the recorder could not decode.
+struct AudioEncoder {
+    channels: usize,
+    streams: Vec<(Vec<usize>, Encoder)>,
+    buffer: Vec<f32>,
+}
+
+impl AudioEncoder {
+    fn new(sample_rate: u32, channels: u16) -> ResultType<Self> {
+        let mut streams = Vec::new();
+        for s in crate::common::audio_streams(channels) {
+            let encoder =
+                Encoder::new(sample_rate, if s.len() > 1 { Stereo } else { Mono }, LowDelay)?;
+            streams.push((s, encoder));
+        }
+        Ok(Self {
+            channels: crate::common::supported_audio_channels(channels) as _,
+            streams,
+            buffer: Vec::new(),
+        })
+    }
+
+    fn encode(&mut self, data: &[f32], max_size: usize) -> Option<AudioFrame> {
+        let mut frame = AudioFrame {
+            timestamp: get_time(),
+            ..Default::default()
+        };
+        let buffer = &mut self.buffer;
+        for (i, (s, encoder)) in self.streams.iter_mut().enumerate() {
+            let input = if s.len() == self.channels {
+                data
+            } else {
+                buffer.clear();
+                for x in data.chunks_exact(self.channels) {
+                    buffer.extend(s.iter().map(|c| x[*c]));
+                }
+                &buffer[..]
+            };
+            let out = encoder.encode_vec_float(input, max_size).ok()?;
+            if i == 0 {
+                frame.data = out.into();
+            } else {
+                frame.streams.push(out.into());
+            }
+        }
+        Some(frame)
+    }
+}
+
+// The audio forwarded by the peer is played into a null sink, whose monitor is remapped to
+// a source, so that the applications can use it as a normal microphone.
+// Each connection has its own sink and source, named after its id.
+#[cfg(target_os = "linux")]
+pub mod mic {
+    use super::*;
+    use hbb_common::platform::linux::run_cmds;
+    use magnum_opus::Decoder;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+
+    pub const SINK_PREFIX: &'static str = "rustdesk_mic";
+    // the rates of opus
+    const SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+
+    enum MicData {
+        Format(u32, u32),
+        Frame(Vec<u8>),
+    }
+
+    /// The modules are unloaded when dropped.
+    pub struct VirtualMic {
+        tx: Sender<MicData>,
+    }
+
+    /// The format sent by the peer can be played, checked before the source is created.
+    pub fn is_supported_format(sample_rate: u32, channels: u32) -> bool {
+        SAMPLE_RATES.contains(&sample_rate) && (channels == 1 || channels == 2)
+    }
+
+    impl VirtualMic {
+        pub fn new(conn_id: i32) -> Self {
+            let (tx, rx) = channel();
+            std::thread::spawn(move || {
+                let sink = format!("{}_{}", SINK_PREFIX, conn_id);
+                let modules = load_modules(&sink, conn_id);
+                if !modules.is_empty() {
+                    run(rx, &sink);
+                }
+                for index in modules.iter().rev() {
+                    allow_err!(run_cmds(format!("pactl unload-module {}", index)));
+                }
+                log::info!("Virtual microphone closed");
             });
-            sp.send(msg_out);
+            Self { tx }
         }
-        Err(_) => {}
+
+        pub fn set_format(&self, sample_rate: u32, channels: u32) {
+            self.tx.send(MicData::Format(sample_rate, channels)).ok();
+        }
+
+        pub fn push(&self, frame: Vec<u8>) {
+            self.tx.send(MicData::Frame(frame)).ok();
+        }
+    }
+
+    fn load_modules(sink: &str, conn_id: i32) -> Vec<String> {
+        crate::platform::linux::unload_pa_modules(sink);
+        let mut modules = Vec::new();
+        for args in [
+            format!(
+                "module-null-sink sink_name={} \
+                sink_properties=device.description=RustDesk-Sink-{}",
+                sink, conn_id
+            ),
+            format!(
+                "module-remap-source master={}.monitor source_name={}_source \
+                source_properties=device.description=RustDesk-Microphone-{}",
+                sink, sink, conn_id
+            ),
+        ] {
+            let index = run_cmds(format!("pactl load-module {}", args)).unwrap_or_default();
+            let index = index.trim();
+            if index.is_empty() {
+                log::error!("Failed to load {}", args);
+                break;
+            }
+            modules.push(index.to_owned());
+        }
+        log::info!("Virtual microphone modules: {:?}", modules);
+        modules
+    }
+
+    fn run(rx: Receiver<MicData>, sink: &str) {
+        let mut player: Option<(Decoder, psimple::Simple, usize)> = None;
+        let mut buffer = Vec::new();
+        while let Ok(data) = rx.recv() {
+            match data {
+                MicData::Format(sample_rate, channels) => {
+                    player = None;
+                    match open(sample_rate, channels, sink) {
+                        Ok((decoder, simple)) => {
+                            buffer = vec![0.; (sample_rate * channels) as usize];
+                            player = Some((decoder, simple, channels as _));
+                        }
+                        Err(err) => {
+                            log::error!("Failed to open the virtual microphone: {}", err);
+                        }
+                    }
+                }
+                MicData::Frame(frame) => {
+                    if let Some((decoder, simple, channels)) = player.as_mut() {
+                        if let Ok(n) = decoder.decode_float(&frame, &mut buffer, false) {
+                            let n = n * *channels;
+                            let data_u8 = unsafe {
+                                std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, n * 4)
+                            };
+                            allow_err!(simple.write(data_u8));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn open(
+        sample_rate: u32,
+        channels: u32,
+        sink: &str,
+    ) -> ResultType<(Decoder, psimple::Simple)> {
+        if !is_supported_format(sample_rate, channels) {
+            bail!("Unsupported format: {} Hz, {} channels", sample_rate, channels);
+        }
+        let decoder = Decoder::new(sample_rate, if channels > 1 { Stereo } else { Mono })?;
+        let spec = pulse::sample::Spec {
+            format: pulse::sample::Format::F32le,
+            channels: channels as _,
+            rate: sample_rate,
+        };
+        let simple = psimple::Simple::new(
+            None,
+            &crate::get_app_name(),
+            pulse::stream::Direction::Playback,
+            Some(sink),
+            "microphone",
+            &spec,
+            None,
+            None,
+        )?;
+        Ok((decoder, simple))
     }
 }
@@ -1,4 +1,4 @@
-use super::{input_service::*, *};
+use super::{input_service::*, video_qos::NetworkStats, *};
 #[cfg(windows)]
 use crate::clipboard_file::*;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -23,15 +23,20 @@ use hbb_common::{
     fs::can_enable_overwrite_detection,
     futures::{SinkExt, StreamExt},
     get_time, get_version_number,
-    message_proto::{option_message::BoolOption, permission_info::Permission},
+    message_proto::{
+        option_message::BoolOption, permission_info::Permission, test_delay::Congestion,
+    },
     password_security::{self as password, ApproveMode},
-    sleep, timeout,
+    sleep,
+    sodiumoxide::crypto::sign,
+    timeout,
     tokio::{
-        net::TcpStream,
+        net::{lookup_host, TcpListener, TcpStream},
         sync::mpsc,
         time::{self, Duration, Instant, Interval},
     },
     tokio_util::codec::{BytesCodec, Framed},
+    tunnel::{self, TcpTunnels, UdpTunnels},
 };
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use scrap::android::call_main_service_mouse_input;
@@ -89,6 +94,10 @@ pub struct Connection {
     file_timer: Interval,
     file_transfer: Option<(String, bool)>,
     port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
+    reverse_forward_port: i32,
+    reverse_forward_listener: Option<TcpListener>,
+    udp_forward_target: Option<String>,
+    tcp_tunnel: bool,
     port_forward_address: String,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
@@ -99,6 +108,10 @@ pub struct Connection {
     restart: bool,
     recording: bool,
     last_test_delay: i64,
+    // video sent since the last TestDelay response
+    video_bytes: usize,
+    video_backlog: u32,
+    network_since: Instant,
     lock_after_session_end: bool,
     show_remote_cursor: bool,
     // by peer
@@ -114,6 +127,9 @@ pub struct Connection {
     // by peer
     audio_sender: Option<MediaSender>,
     // audio by the remote peer/client
+    audio_format: Option<AudioFormat>,
+    // by peer, play the audio of the peer into a virtual microphone instead
+    forward_microphone: bool,
     tx_input: std_mpsc::Sender<MessageInput>,
     // handle input messages
     video_ack_required: bool,
@@ -129,7 +145,17 @@ pub struct Connection {
     origin_resolution: HashMap<String, Resolution>,
     voice_call_request_timestamp: Option<NonZeroI64>,
     audio_input_device_before_voice_call: Option<String>,
+    // restored when the connection is closed
+    audio_input_device_before_select: Option<String>,
+    // of the secure connection, signed by the key login
+    channel_binding: Vec<u8>,
+    // the names last listed to the peer, the only ones it can select
+    audio_sources: Vec<String>,
     options_in_login: Option<OptionMessage>,
+    // by the peer id, name and ip at login
+    policy: Option<super::access_policy::Policy>,
+    // the password is right, waiting for the code of the second factor
+    require_2fa: bool,
 }
 
 impl ConnInner {
@@ -175,10 +201,12 @@ impl Connection {
         stream: super::Stream,
         id: i32,
         server: super::ServerPtrWeak,
+        channel_binding: Vec<u8>,
     ) {
         let hash = Hash {
             salt: Config::get_salt(),
             challenge: Config::get_auto_password(6),
+            tunnel_version: tunnel::TUNNEL_VERSION,
             ..Default::default()
         };
         ALIVE_CONNS.lock().unwrap().push(id);
@@ -206,6 +234,10 @@ impl Connection {
             file_timer: time::interval(SEC30),
             file_transfer: None,
             port_forward_socket: None,
+            reverse_forward_port: 0,
+            reverse_forward_listener: None,
+            udp_forward_target: None,
+            tcp_tunnel: false,
             port_forward_address: "".to_owned(),
             tx_to_cm,
             authorized: false,
@@ -216,6 +248,9 @@ impl Connection {
             restart: Connection::permission("enable-remote-restart"),
             recording: Connection::permission("enable-record-session"),
             last_test_delay: 0,
+            video_bytes: 0,
+            video_backlog: 0,
+            network_since: Instant::now(),
             lock_after_session_end: false,
             show_remote_cursor: false,
             ip: "".to_owned(),
@@ -236,9 +271,16 @@ impl Connection {
             from_switch: false,
             origin_resolution: Default::default(),
             audio_sender: None,
+            audio_format: None,
+            forward_microphone: false,
             voice_call_request_timestamp: None,
             audio_input_device_before_voice_call: None,
+            audio_input_device_before_select: None,
+            channel_binding,
+            audio_sources: Vec::new(),
             options_in_login: None,
+            policy: None,
+            require_2fa: false,
         };
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         tokio::spawn(async move {
@@ -252,6 +294,8 @@ impl Connection {
         if !conn.on_open(addr).await {
             return;
         }
+        let metrics_key = crate::metrics::server_key(id);
+        let metrics = crate::metrics::open(&metrics_key);
         if !conn.keyboard {
             conn.send_permission(Permission::Keyboard, false).await;
         }
@@ -275,7 +319,7 @@ impl Connection {
         let mut last_recv_time = Instant::now();
 
         conn.stream.set_send_timeout(
-            if conn.file_transfer.is_some() || conn.port_forward_socket.is_some() {
+            if conn.file_transfer.is_some() || conn.is_port_forward() {
                 SEND_TIMEOUT_OTHER
             } else {
                 SEND_TIMEOUT_VIDEO
@@ -294,7 +338,7 @@ impl Connection {
                     match data {
                         ipc::Data::Authorize => {
                             conn.send_logon_response().await;
-                            if conn.port_forward_socket.is_some() {
+                            if conn.is_port_forward() {
                                 break;
                             }
                         }
@@ -312,11 +356,16 @@ impl Connection {
                             });
                             let mut msg_out = Message::new();
                             msg_out.set_misc(misc);
+                            conn.record_event(true, &msg_out);
                             conn.send(msg_out).await;
                             conn.chat_unanswered = false;
                         }
                         ipc::Data::SwitchPermission{name, enabled} => {
                             log::info!("Change permission {} -> {}", name, enabled);
+                            if enabled && conn.policy_denies(&name) {
+                                log::info!("Permission {} is denied by the access policy", name);
+                                continue;
+                            }
                             if &name == "keyboard" {
                                 conn.keyboard = enabled;
                                 conn.send_permission(Permission::Keyboard, enabled).await;
@@ -341,6 +390,9 @@ impl Connection {
                                         super::audio_service::NAME,
                                         conn.inner.clone(), conn.audio_enabled());
                                 }
+                                if !enabled {
+                                    conn.set_forward_microphone(false);
+                                }
                             } else if &name == "file" {
                                 conn.file = enabled;
                                 conn.send_permission(Permission::File, enabled).await;
@@ -406,6 +458,16 @@ impl Connection {
                         ipc::Data::VoiceCallResponse(accepted) => {
                             conn.handle_voice_call(accepted).await;
                         }
+                        ipc::Data::ShareTarget(target) => {
+                            if let Err(err) =
+                                video_service::set_share_target(conn.inner.id(), target)
+                            {
+                                log::warn!("Failed to share: {}", err);
+                            }
+                        }
+                        ipc::Data::AudioSources(Some(sources)) => {
+                            conn.send_audio_sources(sources).await;
+                        }
                         ipc::Data::CloseVoiceCall(_reason) => {
                             log::debug!("Close the voice call from the ipc.");
                             conn.close_voice_call().await;
@@ -427,6 +489,7 @@ impl Connection {
                                 last_recv_time = Instant::now();
                                 *conn.last_recv_time.lock().unwrap() = Instant::now();
                                 if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
+                                    metrics.count_in(&msg_in, bytes.len());
                                     if !conn.on_message(msg_in).await {
                                         break;
                                     }
@@ -456,9 +519,20 @@ impl Connection {
                     }
                 }
                 Some((instant, value)) = rx_video.recv() => {
-                    if !conn.video_ack_required {
-                        video_service::notify_video_frame_fetched(id, Some(instant.into()));
-                    }
+                    let display = match &value.union {
+                        Some(message::Union::VideoFrame(vf)) => vf.display as usize,
+                        _ => 0,
+                    };
+                    video_service::notify_video_frame_sent(
+                        id,
+                        display,
+                        instant.into(),
+                        conn.video_ack_required,
+                    );
+                    let size = value.compute_size() as usize;
+                    conn.video_bytes += size;
+                    metrics.count_out(&value, size);
+                    conn.video_backlog = conn.video_backlog.max(instant.elapsed().as_millis() as _);
                     if let Err(err) = conn.stream.send(&value as &Message).await {
                         conn.on_close(&err.to_string(), false).await;
                         break;
@@ -490,6 +564,7 @@ impl Connection {
                         }
                         _ => {}
                     }
+                    metrics.count_out(msg, msg.compute_size() as _);
                     if let Err(err) = conn.stream.send(msg).await {
                         conn.on_close(&err.to_string(), false).await;
                         break;
@@ -498,6 +573,11 @@ impl Connection {
                 _ = second_timer.tick() => {
                     #[cfg(windows)]
                     conn.portable_check();
+                    if conn.authorized && !conn.in_policy_hours() {
+                        conn.send_close_reason_no_retry("Not allowed at this time").await;
+                        conn.on_close("Out of the allowed hours", true).await;
+                        break;
+                    }
                 }
                 _ = test_delay_timer.tick() => {
                     if last_recv_time.elapsed() >= SEC30 {
@@ -508,13 +588,16 @@ impl Connection {
                     if time > 0 && conn.last_test_delay == 0 {
                         conn.last_test_delay = time;
                         let mut msg_out = Message::new();
-                        let qos = video_service::VIDEO_QOS.lock().unwrap();
-                        msg_out.set_test_delay(TestDelay{
+                        let test_delay = video_service::with_conn_qos(id, |qos| TestDelay{
                             time,
                             last_delay:qos.current_delay,
                             target_bitrate:qos.target_bitrate,
+                            send_rate: qos.network.send_rate,
+                            backlog: qos.network.backlog,
+                            congestion: Congestion::from(qos.abr_state).into(),
                             ..Default::default()
                         });
+                        msg_out.set_test_delay(test_delay);
                         conn.inner.send(msg_out.into());
                     }
                 }
@@ -530,7 +613,13 @@ impl Connection {
         }
         video_service::notify_video_frame_fetched(id, None);
         scrap::codec::Encoder::update_video_encoder(id, scrap::codec::EncoderUpdate::Remove);
-        video_service::VIDEO_QOS.lock().unwrap().reset();
+        video_service::update_qos(|qos| qos.reset());
+        video_service::remove_conn_qos(id);
+        super::audio_service::remove_audio_channels(id);
+        if let Some(device) = conn.audio_input_device_before_select.take() {
+            set_sound_input(device);
+        }
+        crate::metrics::close(&metrics_key);
         if conn.authorized {
             password::update_temporary_password();
         }
@@ -626,18 +715,11 @@ impl Connection {
             let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
             loop {
                 tokio::select! {
-                    Some(data) = rx_from_cm.recv() => {
-                        match data {
-                            ipc::Data::Close => {
-                                bail!("Close requested from selection manager");
-                            }
-                            _ => {}
-                        }
-                    }
+                    Some(data) = rx_from_cm.recv() => Self::check_forward_close(data)?,
                     res = forward.next() => {
                         if let Some(res) = res {
                             last_recv_time = Instant::now();
-                            self.stream.send_bytes(res?.into()).await?;
+                            self.stream.send_raw(res?.to_vec()).await?;
                         } else {
                             bail!("Forward reset by the peer");
                         }
@@ -655,16 +737,156 @@ impl Connection {
                             bail!("Timeout");
                         }
                     }
-                    Ok(conns) = hbbs_rx.recv() => {
-                        if conns.contains(&self.inner.id) {
-                            // todo: check reconnect
-                            bail!("Closed manually by the web console");
+                    // todo: check reconnect
+                    Ok(conns) = hbbs_rx.recv() => self.check_console_close(&conns)?,
+                }
+            }
+        }
+        let listener = self.reverse_forward_listener.take();
+        if listener.is_some() || self.tcp_tunnel {
+            self.tcp_tunnel_loop(listener, rx_from_cm).await?;
+        }
+        if let Some(target) = self.udp_forward_target.take() {
+            self.udp_port_forward_loop(target, rx_from_cm).await?;
+        }
+        Ok(())
+    }
+
+    // The close requests shared by the port forward loops.
+    fn check_forward_close(data: Data) -> ResultType<()> {
+        if let ipc::Data::Close = data {
+            bail!("Close requested from selection manager");
+        }
+        Ok(())
+    }
+
+    fn check_console_close(&self, conns: &[i32]) -> ResultType<()> {
+        if conns.contains(&self.inner.id) {
+            bail!("Closed manually by the web console");
+        }
+        Ok(())
+    }
+
+    async fn udp_port_forward_loop(
+        &mut self,
+        target: String,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        log::info!("Running udp port forwarding loop to {}", target);
+        let mut last_recv_time = Instant::now();
+        let mut flows = UdpTunnels::new(target);
+        let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        loop {
+            tokio::select! {
+                Some(data) = rx_from_cm.recv() => Self::check_forward_close(data)?,
+                Some(event) = flows.next() => {
+                    self.stream.send(&Message::from(event)).await?;
+                }
+                res = self.stream.next() => {
+                    let bytes = if let Some(res) = res {
+                        res?
+                    } else {
+                        bail!("Stream reset by the peer");
+                    };
+                    last_recv_time = Instant::now();
+                    if let Ok(Message {
+                        union: Some(message::Union::TunnelData(td)),
+                        ..
+                    }) = Message::parse_from_bytes(&bytes)
+                    {
+                        match td.union {
+                            Some(tunnel_data::Union::Data(data)) => {
+                                if let Err(err) = flows.send(td.id, &data).await {
+                                    log::debug!("Udp forward #{} failed: {}", td.id, err);
+                                    flows.remove(td.id);
+                                    self.stream.send(&tunnel::new_tunnel_close(td.id)).await?;
+                                }
+                            }
+                            Some(tunnel_data::Union::Close(_)) => {
+                                flows.remove(td.id);
+                            }
+                            _ => {}
                         }
                     }
                 }
+                _ = self.timer.tick() => {
+                    for id in flows.remove_idle() {
+                        self.stream.send(&tunnel::new_tunnel_close(id)).await?;
+                    }
+                    if last_recv_time.elapsed() >= H1 {
+                        bail!("Timeout");
+                    }
+                }
+                Ok(conns) = hbbs_rx.recv() => self.check_console_close(&conns)?,
+            }
+        }
+    }
+
+    // Connections accepted by the reverse forward listener, or requested by the peer
+    // with the target in the open of a tcp tunnel.
+    async fn tcp_tunnel_loop(
+        &mut self,
+        listener: Option<TcpListener>,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        log::info!(
+            "Running tcp tunnel loop, listening on {:?}",
+            listener.as_ref().map(|l| l.local_addr())
+        );
+        let mut last_recv_time = Instant::now();
+        let mut tunnels = TcpTunnels::new();
+        let mut next_id = 0;
+        let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        loop {
+            tokio::select! {
+                Some(data) = rx_from_cm.recv() => Self::check_forward_close(data)?,
+                res = tunnel::accept(&listener) => {
+                    let (sock, addr) = res?;
+                    next_id += 1;
+                    log::debug!("Reverse forward #{} accepted from {}", next_id, addr);
+                    tunnels.add(next_id, sock);
+                    self.stream.send(&tunnel::new_tunnel_open(next_id)).await?;
+                }
+                Some(event) = tunnels.next() => {
+                    self.stream.send(&Message::from(event)).await?;
+                }
+                res = self.stream.next() => {
+                    let bytes = if let Some(res) = res {
+                        res?
+                    } else {
+                        bail!("Stream reset by the peer");
+                    };
+                    last_recv_time = Instant::now();
+                    if let Ok(Message {
+                        union: Some(message::Union::TunnelData(td)),
+                        ..
+                    }) = Message::parse_from_bytes(&bytes)
+                    {
+                        match td.union {
+                            Some(tunnel_data::Union::Open(_)) if listener.is_none() => {
+                                log::debug!("Tunnel #{} to {}", td.id, td.target);
+                                tunnels.connect(td.id, td.target);
+                            }
+                            Some(tunnel_data::Union::Data(data)) => {
+                                if !tunnels.write(td.id, data).await {
+                                    self.stream.send(&tunnel::new_tunnel_close(td.id)).await?;
+                                }
+                            }
+                            Some(tunnel_data::Union::Close(_)) => {
+                                tunnels.remove(td.id);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ = self.timer.tick() => {
+                    if last_recv_time.elapsed() >= H1 {
+                        bail!("Timeout");
+                    }
+                }
+                Ok(conns) = hbbs_rx.recv() => self.check_console_close(&conns)?,
             }
         }
-        Ok(())
     }
 
     async fn send_permission(&mut self, permission: Permission, enabled: bool) {
@@ -676,9 +898,28 @@ impl Connection {
         });
         let mut msg_out = Message::new();
         msg_out.set_misc(misc);
+        self.record_event(true, &msg_out);
         self.send(msg_out).await;
     }
 
+    /// Log the input, clipboard, chat, file and permission messages next to the recording.
+    fn record_event(&self, outgoing: bool, msg: &Message) {
+        let audited = match &msg.union {
+            Some(message::Union::MouseEvent(_))
+            | Some(message::Union::KeyEvent(_))
+            | Some(message::Union::Clipboard(_))
+            | Some(message::Union::FileAction(_)) => true,
+            Some(message::Union::Misc(misc)) => matches!(
+                misc.union,
+                Some(misc::Union::ChatMessage(_)) | Some(misc::Union::PermissionInfo(_))
+            ),
+            _ => false,
+        };
+        if audited {
+            video_service::record_event(self.inner.id, outgoing, msg);
+        }
+    }
+
     async fn on_open(&mut self, addr: SocketAddr) -> bool {
         log::debug!("#{} Connection opened from {}.", self.inner.id, addr);
         let whitelist: Vec<String> = Config::get_option("whitelist")
@@ -813,9 +1054,14 @@ impl Connection {
         if self.authorized {
             return;
         }
+        // the approval may come later
+        if !self.in_policy_hours() {
+            self.send_login_error("Not allowed at this time").await;
+            return;
+        }
         let conn_type = if self.file_transfer.is_some() {
             1
-        } else if self.port_forward_socket.is_some() {
+        } else if self.is_port_forward() {
             2
         } else {
             0
@@ -841,18 +1087,34 @@ impl Connection {
             pi.hostname = DEVICE_NAME.lock().unwrap().clone();
             pi.platform = "Android".into();
         }
-        #[cfg(feature = "hwcodec")]
-        {
-            let (h264, h265) = scrap::codec::Encoder::supported_encoding();
-            pi.encoding = Some(SupportedEncoding {
-                h264,
-                h265,
-                ..Default::default()
-            })
-            .into();
-        }
+        let (h264, h265) = scrap::codec::Encoder::supported_encoding();
+        pi.encoding = Some(SupportedEncoding {
+            h264,
+            h265,
+            av1: scrap::codec::Encoder::supported_av1(),
+            i444: scrap::codec::Encoder::supported_i444(),
+            ..Default::default()
+        })
+        .into();
 
-        if self.port_forward_socket.is_some() {
+        if self.is_port_forward() {
+            if self.reverse_forward_port > 0 && self.reverse_forward_listener.is_none() {
+                // Only loopback, the forwarded service is not exposed to the network.
+                let addr = format!("127.0.0.1:{}", self.reverse_forward_port);
+                match TcpListener::bind(&addr).await {
+                    Ok(listener) => {
+                        self.reverse_forward_listener = Some(listener);
+                    }
+                    Err(err) => {
+                        self.send_login_error(format!(
+                            "Failed to listen on {} of remote: {}",
+                            addr, err
+                        ))
+                        .await;
+                        return;
+                    }
+                }
+            }
             let mut msg_out = Message::new();
             res.set_peer_info(pi);
             msg_out.set_login_response(res);
@@ -860,7 +1122,7 @@ impl Connection {
             return;
         }
         #[cfg(target_os = "linux")]
-        if !self.file_transfer.is_some() && !self.port_forward_socket.is_some() {
+        if !self.file_transfer.is_some() && !self.is_port_forward() {
             let dtype = crate::platform::linux::get_display_server();
             if dtype != "x11" && dtype != "wayland" {
                 res.set_error(format!(
@@ -891,6 +1153,7 @@ impl Connection {
         pi.sas_enabled = sas_enabled;
         pi.features = Some(Features {
             privacy_mode: video_service::is_privacy_mode_supported(),
+            virtual_microphone: cfg!(target_os = "linux"),
             ..Default::default()
         })
         .into();
@@ -963,6 +1226,33 @@ impl Connection {
         }
     }
 
+    // the global option, unless the access policy of the peer sets it
+    fn policy_permission(&self, name: &str, enable_prefix_option: &str) -> bool {
+        let default = Connection::permission(enable_prefix_option);
+        self.policy
+            .as_ref()
+            .map_or(default, |p| p.permission(name, default))
+    }
+
+    fn policy_denies(&self, name: &str) -> bool {
+        self.policy
+            .as_ref()
+            .map_or(false, |p| !p.permission(name, true))
+    }
+
+    fn in_policy_hours(&self) -> bool {
+        self.policy.as_ref().map_or(true, |p| p.in_hours())
+    }
+
+    fn apply_policy(&mut self) {
+        self.keyboard = self.policy_permission("keyboard", "enable-keyboard");
+        self.clipboard = self.policy_permission("clipboard", "enable-clipboard");
+        self.audio = self.policy_permission("audio", "enable-audio");
+        self.file = self.policy_permission("file", "enable-file-transfer");
+        self.restart = self.policy_permission("restart", "enable-remote-restart");
+        self.recording = self.policy_permission("recording", "enable-record-session");
+    }
+
     fn peer_keyboard_enabled(&self) -> bool {
         self.keyboard && !self.disable_keyboard
     }
@@ -1051,19 +1341,21 @@ impl Connection {
         hasher2.finalize()[..] == self.lr.password[..]
     }
 
-    fn validate_password(&mut self) -> bool {
+    fn validate_password(&mut self, add_session: bool) -> bool {
         if password::temporary_enabled() {
             let password = password::temporary_password();
             if self.validate_one_password(password.clone()) {
-                SESSIONS.lock().unwrap().insert(
-                    self.lr.my_id.clone(),
-                    Session {
-                        name: self.lr.my_name.clone(),
-                        session_id: self.lr.session_id,
-                        last_recv_time: self.last_recv_time.clone(),
-                        random_password: password,
-                    },
-                );
+                if add_session {
+                    SESSIONS.lock().unwrap().insert(
+                        self.lr.my_id.clone(),
+                        Session {
+                            name: self.lr.my_name.clone(),
+                            session_id: self.lr.session_id,
+                            last_recv_time: self.last_recv_time.clone(),
+                            random_password: password,
+                        },
+                    );
+                }
                 return true;
             }
         }
@@ -1075,6 +1367,88 @@ impl Connection {
         false
     }
 
+    fn validate_public_key(&self) -> bool {
+        if self.lr.signed_challenge.is_empty()
+            || self.channel_binding.is_empty()
+            || !password::is_authorized_key(&self.lr.public_key)
+        {
+            return false;
+        }
+        let pk = match <[u8; sign::PUBLICKEYBYTES]>::try_from(&self.lr.public_key[..]) {
+            Ok(pk) => sign::PublicKey(pk),
+            Err(_) => return false,
+        };
+        let data = password::key_login_data(
+            &self.channel_binding,
+            &self.hash.salt,
+            &self.hash.challenge,
+        );
+        match sign::verify(&self.lr.signed_challenge, &pk) {
+            Ok(signed) => signed == data,
+            Err(_) => {
+                log::warn!("Invalid signature of the authorized key from {}", self.ip);
+                false
+            }
+        }
+    }
+
+    // the failures of the ip, and false if it has to wait
+    async fn check_failure(&mut self) -> ((i32, i32, i32), bool) {
+        let failure = LOGIN_FAILURES
+            .lock()
+            .unwrap()
+            .get(&self.ip)
+            .map(|x| x.clone())
+            .unwrap_or((0, 0, 0));
+        let time = (get_time() / 60_000) as i32;
+        if failure.2 > 30 {
+            self.send_login_error("Too many wrong password attempts")
+                .await;
+            Self::post_alarm_audit(
+                AlarmAuditType::ManyWrongPassword,
+                true,
+                json!({
+                            "ip":self.ip,
+                }),
+            );
+            (failure, false)
+        } else if time == failure.0 && failure.1 > 6 {
+            self.send_login_error("Please try 1 minute later").await;
+            Self::post_alarm_audit(
+                AlarmAuditType::FrequentAttempt,
+                true,
+                json!({
+                            "ip":self.ip,
+                }),
+            );
+            (failure, false)
+        } else {
+            (failure, true)
+        }
+    }
+
+    fn update_failure(&self, mut failure: (i32, i32, i32), remove: bool) {
+        if remove {
+            if failure.0 != 0 {
+                LOGIN_FAILURES.lock().unwrap().remove(&self.ip);
+            }
+            return;
+        }
+        let time = (get_time() / 60_000) as i32;
+        if failure.0 == time {
+            failure.1 += 1;
+            failure.2 += 1;
+        } else {
+            failure.0 = time;
+            failure.1 = 1;
+            failure.2 += 1;
+        }
+        LOGIN_FAILURES
+            .lock()
+            .unwrap()
+            .insert(self.ip.clone(), failure);
+    }
+
     fn is_recent_session(&mut self) -> bool {
         let session = SESSIONS
             .lock()
@@ -1150,9 +1524,17 @@ impl Connection {
             if self.authorized {
                 return true;
             }
+            self.policy = super::access_policy::Policy::find(&lr.my_id, &lr.my_name, &self.ip);
+            if !self.in_policy_hours() {
+                self.send_login_error("Not allowed at this time").await;
+                sleep(1.).await;
+                return false;
+            }
+            self.apply_policy();
+            self.require_2fa = false;
             match lr.union {
                 Some(login_request::Union::FileTransfer(ft)) => {
-                    if !Connection::permission("enable-file-transfer") {
+                    if !self.file {
                         self.send_login_error("No permission of file transfer")
                             .await;
                         sleep(1.).await;
@@ -1167,8 +1549,8 @@ impl Connection {
                         pf.port = 3389;
                         is_rdp = true;
                     }
-                    if is_rdp && !Connection::permission("enable-rdp")
-                        || !is_rdp && !Connection::permission("enable-tunnel")
+                    if is_rdp && !self.policy_permission("tunnel", "enable-rdp")
+                        || !is_rdp && !self.policy_permission("tunnel", "enable-tunnel")
                     {
                         if is_rdp {
                             self.send_login_error("No permission of RDP").await;
@@ -1182,28 +1564,72 @@ impl Connection {
                         pf.host = "localhost".to_owned();
                     }
                     let mut addr = format!("{}:{}", pf.host, pf.port);
-                    self.port_forward_address = addr.clone();
-                    match timeout(3000, TcpStream::connect(&addr)).await {
-                        Ok(Ok(sock)) => {
-                            self.port_forward_socket = Some(Framed::new(sock, BytesCodec::new()));
+                    if pf.tcp_tunnel && !is_rdp {
+                        self.port_forward_address = "tcp tunnel".to_owned();
+                        self.tcp_tunnel = true;
+                    } else if pf.udp && !is_rdp {
+                        self.port_forward_address = format!("udp {}", addr);
+                        if let Ok(Ok(mut addrs)) = timeout(3000, lookup_host(&addr)).await {
+                            self.udp_forward_target = addrs.next().map(|a| a.to_string());
                         }
-                        _ => {
-                            if is_rdp {
-                                addr = "RDP".to_owned();
-                            }
-                            self.send_login_error(format!(
-                                "Failed to access remote {}, please make sure if it is open",
-                                addr
-                            ))
-                            .await;
+                        if self.udp_forward_target.is_none() {
+                            self.send_login_error(format!("Failed to resolve {}", addr))
+                                .await;
                             return false;
                         }
+                    } else {
+                        self.port_forward_address = addr.clone();
+                        match timeout(3000, TcpStream::connect(&addr)).await {
+                            Ok(Ok(sock)) => {
+                                self.port_forward_socket =
+                                    Some(Framed::new(sock, BytesCodec::new()));
+                            }
+                            _ => {
+                                if is_rdp {
+                                    addr = "RDP".to_owned();
+                                }
+                                self.send_login_error(format!(
+                                    "Failed to access remote {}, please make sure if it is open",
+                                    addr
+                                ))
+                                .await;
+                                return false;
+                            }
+                        }
                     }
                 }
+                Some(login_request::Union::ReversePortForward(rpf)) => {
+                    if !self.policy_permission("tunnel", "enable-tunnel") {
+                        self.send_login_error("No permission of IP tunneling").await;
+                        sleep(1.).await;
+                        return false;
+                    }
+                    if rpf.port <= 0 || rpf.port > u16::MAX as i32 {
+                        self.send_login_error("Invalid port").await;
+                        return false;
+                    }
+                    // bound in send_logon_response, not before the peer is authorized
+                    self.reverse_forward_port = rpf.port;
+                    self.port_forward_address = format!("reverse 127.0.0.1:{}", rpf.port);
+                }
                 _ => {}
             }
             if !hbb_common::is_ipv4_str(&lr.username) && lr.username != Config::get_id() {
                 self.send_login_error("Offline").await;
+            } else if password::approve_mode() != ApproveMode::Click && self.validate_public_key() {
+                log::info!("Logged in with the authorized key of {}", lr.my_id);
+                if crate::auth_2fa::is_enabled() {
+                    // the key stands in for the password, not for the second factor
+                    self.require_2fa = true;
+                    self.send_login_error(crate::client::LOGIN_MSG_2FA_REQUIRED)
+                        .await;
+                } else {
+                    self.try_start_cm(lr.my_id, lr.my_name, true);
+                    self.send_logon_response().await;
+                    if self.is_port_forward() {
+                        return false;
+                    }
+                }
             } else if password::approve_mode() == ApproveMode::Click
                 || password::approve_mode() == ApproveMode::Both && !password::has_valid_password()
             {
@@ -1222,60 +1648,30 @@ impl Connection {
             } else if self.is_recent_session() {
                 self.try_start_cm(lr.my_id, lr.my_name, true);
                 self.send_logon_response().await;
-                if self.port_forward_socket.is_some() {
+                if self.is_port_forward() {
                     return false;
                 }
             } else if lr.password.is_empty() {
                 self.try_start_cm(lr.my_id, lr.my_name, false);
             } else {
-                let mut failure = LOGIN_FAILURES
-                    .lock()
-                    .unwrap()
-                    .get(&self.ip)
-                    .map(|x| x.clone())
-                    .unwrap_or((0, 0, 0));
-                let time = (get_time() / 60_000) as i32;
-                if failure.2 > 30 {
-                    self.send_login_error("Too many wrong password attempts")
-                        .await;
-                    Self::post_alarm_audit(
-                        AlarmAuditType::ManyWrongPassword,
-                        true,
-                        json!({
-                                    "ip":self.ip,
-                        }),
-                    );
-                } else if time == failure.0 && failure.1 > 6 {
-                    self.send_login_error("Please try 1 minute later").await;
-                    Self::post_alarm_audit(
-                        AlarmAuditType::FrequentAttempt,
-                        true,
-                        json!({
-                                    "ip":self.ip,
-                        }),
-                    );
-                } else if !self.validate_password() {
-                    if failure.0 == time {
-                        failure.1 += 1;
-                        failure.2 += 1;
-                    } else {
-                        failure.0 = time;
-                        failure.1 = 1;
-                        failure.2 += 1;
-                    }
-                    LOGIN_FAILURES
-                        .lock()
-                        .unwrap()
-                        .insert(self.ip.clone(), failure);
+                let (failure, ok) = self.check_failure().await;
+                if !ok {
+                    return true;
+                }
+                if !self.validate_password(!crate::auth_2fa::is_enabled()) {
+                    self.update_failure(failure, false);
                     self.send_login_error("Wrong Password").await;
                     self.try_start_cm(lr.my_id, lr.my_name, false);
+                } else if crate::auth_2fa::is_enabled() {
+                    // the failures are kept until the code is right
+                    self.require_2fa = true;
+                    self.send_login_error(crate::client::LOGIN_MSG_2FA_REQUIRED)
+                        .await;
                 } else {
-                    if failure.0 != 0 {
-                        LOGIN_FAILURES.lock().unwrap().remove(&self.ip);
-                    }
+                    self.update_failure(failure, true);
                     self.try_start_cm(lr.my_id, lr.my_name, true);
                     self.send_logon_response().await;
-                    if self.port_forward_socket.is_some() {
+                    if self.is_port_forward() {
                         return false;
                     }
                 }
@@ -1288,10 +1684,27 @@ impl Connection {
             } else {
                 self.last_test_delay = 0;
                 let new_delay = (get_time() - t.time) as u32;
-                video_service::VIDEO_QOS
-                    .lock()
-                    .unwrap()
-                    .update_network_delay(new_delay);
+                let elapsed = self.network_since.elapsed().as_millis().max(1) as usize;
+                let stats = NetworkStats {
+                    send_rate: (self.video_bytes * 8 / elapsed) as _,
+                    backlog: self.video_backlog,
+                    arrival_jitter: t.arrival_jitter,
+                    decode_jitter: t.decode_jitter,
+                };
+                self.video_bytes = 0;
+                self.video_backlog = 0;
+                self.network_since = Instant::now();
+                video_service::update_conn_network(self.inner.id(), new_delay, stats);
+                let (delay, target_bitrate, fps) =
+                    video_service::with_conn_qos(self.inner.id(), |qos| {
+                        (qos.current_delay, qos.target_bitrate, qos.fps)
+                    });
+                crate::metrics::update(&crate::metrics::server_key(self.inner.id()), |m| {
+                    m.delay = delay;
+                    m.target_bitrate = target_bitrate;
+                    m.fps = fps as _;
+                    m.send_rate = stats.send_rate;
+                });
             }
         } else if let Some(message::Union::SwitchSidesResponse(_s)) = msg.union {
             #[cfg(feature = "flutter")]
@@ -1312,7 +1725,31 @@ impl Connection {
                     }
                 }
             }
+        } else if let Some(message::Union::Auth2fa(auth)) = msg.union {
+            if !self.require_2fa || self.authorized {
+                return true;
+            }
+            let (failure, ok) = self.check_failure().await;
+            if !ok {
+                return true;
+            }
+            if !crate::auth_2fa::verify(&auth.code) {
+                self.update_failure(failure, false);
+                self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
+                    .await;
+            } else {
+                self.update_failure(failure, true);
+                self.require_2fa = false;
+                // the session of the one-time password, after the second factor
+                self.validate_password(true);
+                self.try_start_cm(self.lr.my_id.clone(), self.lr.my_name.clone(), true);
+                self.send_logon_response().await;
+                if self.is_port_forward() {
+                    return false;
+                }
+            }
         } else if self.authorized {
+            self.record_event(false, &msg);
             match msg.union {
                 Some(message::Union::MouseEvent(me)) => {
                     #[cfg(any(target_os = "android", target_os = "ios"))]
@@ -1494,6 +1931,8 @@ impl Connection {
                             file_num: block.file_num,
                             data: block.data,
                             compressed: block.compressed,
+                            blk_id: block.blk_id,
+                            unchanged: block.unchanged,
                         });
                     }
                     Some(file_response::Union::Done(d)) => {
@@ -1522,6 +1961,10 @@ impl Connection {
                     Some(misc::Union::SwitchDisplay(s)) => {
                         video_service::switch_display(s.display).await;
                     }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    Some(misc::Union::CaptureDisplays(c)) => {
+                        self.capture_displays(&c.add, &c.sub);
+                    }
                     Some(misc::Union::ChatMessage(c)) => {
                         self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
                         self.chat_unanswered = true;
@@ -1596,19 +2039,26 @@ impl Connection {
                         _ => {}
                     },
                     Some(misc::Union::AudioFormat(format)) => {
-                        if !self.disable_audio {
-                            // Drop the audio sender previously.
-                            drop(std::mem::replace(&mut self.audio_sender, None));
-                            // Start a audio thread to play the audio sent by peer.
-                            let latency_controller = LatencyController::new();
-                            // No video frame will be sent here, so we need to disable latency controller, or audio check may fail.
-                            latency_controller.lock().unwrap().set_audio_only(true);
-                            self.audio_sender = Some(start_audio_thread(Some(latency_controller)));
-                            allow_err!(self
-                                .audio_sender
-                                .as_ref()
-                                .unwrap()
-                                .send(MediaData::AudioFormat(format)));
+                        self.audio_format = Some(format.clone());
+                        if self.forward_microphone {
+                            self.send_to_cm(ipc::Data::MicrophoneFormat((
+                                format.sample_rate,
+                                format.channels,
+                            )));
+                        } else if !self.disable_audio {
+                            self.start_audio_playback(format);
+                        }
+                    }
+                    Some(misc::Union::GetAudioSources(_)) => {
+                        // listed by the connection manager
+                        #[cfg(target_os = "linux")]
+                        if self.audio {
+                            self.send_to_cm(ipc::Data::AudioSources(None));
+                        }
+                    }
+                    Some(misc::Union::SelectAudioSource(name)) => {
+                        if self.audio && self.keyboard {
+                            self.select_audio_source(name);
                         }
                     }
                     #[cfg(feature = "flutter")]
@@ -1648,7 +2098,9 @@ impl Connection {
                     _ => {}
                 },
                 Some(message::Union::AudioFrame(frame)) => {
-                    if !self.disable_audio {
+                    if self.forward_microphone {
+                        self.send_to_cm(ipc::Data::MicrophoneFrame(frame.data.to_vec()));
+                    } else if !self.disable_audio {
                         if let Some(sender) = &self.audio_sender {
                             allow_err!(sender.send(MediaData::AudioFrame(frame)));
                         } else {
@@ -1679,6 +2131,85 @@ impl Connection {
         true
     }
 
+    async fn send_audio_sources(&mut self, sources: Vec<(String, String, bool)>) {
+        self.audio_sources = sources.iter().map(|s| s.0.clone()).collect();
+        let mut misc = Misc::new();
+        misc.set_audio_sources(AudioSources {
+            sources: sources
+                .into_iter()
+                .map(|(name, description, is_application)| AudioSource {
+                    name,
+                    description,
+                    is_application,
+                    ..Default::default()
+                })
+                .collect(),
+            current: Config::get_option("audio-input"),
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        self.send(msg).await;
+    }
+
+    fn select_audio_source(&mut self, name: String) {
+        if !self.audio_sources.contains(&name) {
+            log::warn!("Audio source {} is not listed", name);
+            return;
+        }
+        if self.audio_input_device_before_select.is_none() {
+            self.audio_input_device_before_select = Some(Config::get_option("audio-input"));
+        }
+        set_sound_input(name);
+    }
+
+    fn start_audio_playback(&mut self, format: AudioFormat) {
+        // Drop the audio sender previously.
+        drop(std::mem::replace(&mut self.audio_sender, None));
+        // Start a audio thread to play the audio sent by peer.
+        let latency_controller = LatencyController::new();
+        // No video frame will be sent here, so we need to disable latency controller, or audio check may fail.
+        latency_controller.lock().unwrap().set_audio_only(true);
+        self.audio_sender = Some(start_audio_thread(Some(latency_controller)));
+        allow_err!(self
+            .audio_sender
+            .as_ref()
+            .unwrap()
+            .send(MediaData::AudioFormat(format)));
+    }
+
+    // The virtual microphone is created by the connection manager,
+    // which runs in the session of the user, like the pulseaudio recorder.
+    fn set_forward_microphone(&mut self, on: bool) {
+        if on && !cfg!(target_os = "linux") {
+            log::warn!("Forwarding the microphone is only supported on Linux");
+            return;
+        }
+        let on = on && self.audio;
+        if on == self.forward_microphone {
+            return;
+        }
+        self.forward_microphone = on;
+        let format = self.audio_format.clone();
+        if on {
+            // The peer may be sending its audio for a voice call already.
+            drop(self.audio_sender.take());
+            if let Some(format) = format {
+                self.send_to_cm(ipc::Data::MicrophoneFormat((
+                    format.sample_rate,
+                    format.channels,
+                )));
+            }
+        } else {
+            self.send_to_cm(ipc::Data::CloseMicrophone);
+            if let Some(format) = format {
+                if !self.disable_audio {
+                    self.start_audio_playback(format);
+                }
+            }
+        }
+    }
+
     pub async fn handle_voice_call(&mut self, accepted: bool) {
         if let Some(ts) = self.voice_call_request_timestamp.take() {
             let msg = new_voice_call_response(ts.get(), accepted);
@@ -1713,6 +2244,21 @@ impl Connection {
         self.send_to_cm(Data::CloseVoiceCall("".to_owned()));
     }
 
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn capture_displays(&self, add: &[i32], sub: &[i32]) {
+        log::info!("Capture displays, add: {:?}, sub: {:?}", add, sub);
+        if let Some(s) = self.server.upgrade() {
+            let mut server = s.write().unwrap();
+            for (displays, on) in [(add, true), (sub, false)] {
+                for &i in displays {
+                    if let Some(name) = video_service::DISPLAY_NAMES.get(i as usize) {
+                        server.subscribe(name, self.inner.clone(), on);
+                    }
+                }
+            }
+        }
+    }
+
     async fn update_options(&mut self, o: &OptionMessage) {
         log::info!("Option update: {:?}", o);
         if let Ok(q) = o.image_quality.enum_value() {
@@ -1727,17 +2273,15 @@ impl Connection {
                 image_quality = q.value();
             }
             if image_quality > 0 {
-                video_service::VIDEO_QOS
-                    .lock()
-                    .unwrap()
-                    .update_image_quality(image_quality);
+                video_service::update_conn_qos(self.inner.id(), |qos| {
+                    qos.update_image_quality(image_quality)
+                });
             }
         }
         if o.custom_fps > 0 {
-            video_service::VIDEO_QOS
-                .lock()
-                .unwrap()
-                .update_user_fps(o.custom_fps as _);
+            video_service::update_conn_qos(self.inner.id(), |qos| {
+                qos.update_user_fps(o.custom_fps as _)
+            });
         }
         if let Some(q) = o.video_codec_state.clone().take() {
             scrap::codec::Encoder::update_video_encoder(
@@ -1818,6 +2362,14 @@ impl Connection {
                 }
             }
         }
+        if o.audio_channels > 0 {
+            super::audio_service::set_audio_channels(self.inner.id(), o.audio_channels);
+        }
+        if let Ok(q) = o.forward_microphone.enum_value() {
+            if q != BoolOption::NotSet {
+                self.set_forward_microphone(q == BoolOption::Yes);
+            }
+        }
         if let Ok(q) = o.privacy_mode.enum_value() {
             if self.keyboard {
                 match q {
@@ -1906,7 +2458,20 @@ impl Connection {
         #[cfg(any(target_os = "android", target_os = "ios"))]
         let data = ipc::Data::Close;
         self.tx_to_cm.send(data).ok();
+        video_service::set_share_target(self.inner.id(), None).ok();
         self.port_forward_socket.take();
+        self.reverse_forward_port = 0;
+        self.reverse_forward_listener.take();
+        self.udp_forward_target.take();
+        self.tcp_tunnel = false;
+    }
+
+    #[inline]
+    fn is_port_forward(&self) -> bool {
+        self.port_forward_socket.is_some()
+            || self.reverse_forward_port > 0
+            || self.udp_forward_target.is_some()
+            || self.tcp_tunnel
     }
 
     // The `reason` should be consistent with `check_if_retry` if not empty
@@ -1944,7 +2509,7 @@ impl Connection {
     fn portable_check(&mut self) {
         if self.portable.is_installed
             || self.file_transfer.is_some()
-            || self.port_forward_socket.is_some()
+            || self.is_port_forward()
         {
             return;
         }
@@ -2105,8 +2670,10 @@ async fn start_ipc(
                         if let Data::FS(ipc::FS::WriteBlock{id,
                             file_num,
                             data,
-                            compressed}) = data {
-                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed})).await?;
+                            compressed,
+                            blk_id,
+                            unchanged}) = data {
+                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed, blk_id, unchanged})).await?;
                                 stream.send_raw(data).await?;
                         } else {
                             stream.send(&data).await?;
@@ -364,10 +364,28 @@ fn get_modifier_state(key: Key, en: &mut Enigo) -> bool {
     }
 }
 
+// Positions are relative to the shared region, which may have moved since it was announced.
+// The wheel events carry deltas.
+#[cfg(any(windows, target_os = "linux"))]
+fn translate_mouse(evt: &MouseEvent) -> Option<MouseEvent> {
+    if evt.mask & 0x7 > 2 {
+        return None;
+    }
+    let (x, y) = super::video_service::translate_to_region(evt.x, evt.y)?;
+    let mut evt = evt.clone();
+    evt.x = x;
+    evt.y = y;
+    Some(evt)
+}
+
 pub fn handle_mouse(evt: &MouseEvent, conn: i32) {
     if !active_mouse_(conn) {
         return;
     }
+    #[cfg(any(windows, target_os = "linux"))]
+    let translated = translate_mouse(evt);
+    #[cfg(any(windows, target_os = "linux"))]
+    let evt = translated.as_ref().unwrap_or(evt);
     let evt_type = evt.mask & 0x7;
     if evt_type == 0 {
         let time = get_time();
@@ -378,6 +396,17 @@ pub fn handle_mouse(evt: &MouseEvent, conn: i32) {
             y: evt.y,
         };
     }
+    // the cursor may be out of the region, press where the peer clicked
+    #[cfg(any(windows, target_os = "linux"))]
+    if translated.is_some() && evt_type != 0 {
+        let mut moved = evt.clone();
+        moved.mask = 0;
+        moved.modifiers.clear();
+        #[cfg(windows)]
+        crate::portable_service::client::handle_mouse(&moved);
+        #[cfg(not(windows))]
+        handle_mouse_(&moved);
+    }
     #[cfg(target_os = "macos")]
     if !*IS_SERVER {
         // having GUI, run main GUI thread, otherwise crash
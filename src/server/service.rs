@@ -179,6 +179,19 @@ impl<T: Subscriber + From<ConnInner>> ServiceTmpl<T> {
         conn_ids
     }
 
+    /// Send a frame encoded for the subscriber `id` only, returns whether it is subscribed.
+    pub fn send_video_frame_to(&self, msg: Message, id: i32) -> bool {
+        if let Some(s) = self.0.write().unwrap().subscribes.get_mut(&id) {
+            s.send(Arc::new(msg));
+            return true;
+        }
+        false
+    }
+
+    pub fn subscriber_ids(&self) -> Vec<i32> {
+        self.0.read().unwrap().subscribes.keys().cloned().collect()
+    }
+
     pub fn send_without(&self, msg: Message, sub: i32) {
         let mut lock = self.0.write().unwrap();
         let msg = Arc::new(msg);
@@ -1,8 +1,13 @@
 use super::*;
-use std::time::Duration;
+use hbb_common::message_proto::test_delay::Congestion;
+use std::time::{Duration, Instant};
 pub const FPS: u8 = 30;
 pub const MIN_FPS: u8 = 10;
 pub const MAX_FPS: u8 = 120;
+// kbps
+const MIN_ABR_BITRATE: u32 = 100;
+// no increase for a while after a decrease
+const ABR_HOLD: Duration = Duration::from_secs(3);
 trait Percent {
     fn as_percent(&self) -> u32;
 }
@@ -31,6 +36,40 @@ pub struct VideoQoS {
     updated: bool,
     state: DelayState,
     debounce_count: u32,
+    // the cap of the bitrate by the throughput, 0 if not measured yet
+    abr_bitrate: u32,
+    pub network: NetworkStats,
+    pub abr_state: AbrState,
+    last_decrease: Instant,
+}
+
+/// Network stats of a connection, reported periodically.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NetworkStats {
+    /// Video sent since the last report, in kbps.
+    pub send_rate: u32,
+    /// The longest time a video frame waited to be sent, in ms.
+    pub backlog: u32,
+    /// Reported by the peer, in ms.
+    pub arrival_jitter: u32,
+    pub decode_jitter: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AbrState {
+    Increase,
+    Hold,
+    Decrease,
+}
+
+impl From<AbrState> for Congestion {
+    fn from(state: AbrState) -> Self {
+        match state {
+            AbrState::Increase => Congestion::Increase,
+            AbrState::Hold => Congestion::Hold,
+            AbrState::Decrease => Congestion::Decrease,
+        }
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -70,6 +109,10 @@ impl Default for VideoQoS {
             updated: false,
             state: DelayState::Normal,
             debounce_count: 0,
+            abr_bitrate: 0,
+            network: Default::default(),
+            abr_state: AbrState::Hold,
+            last_decrease: Instant::now(),
         }
     }
 }
@@ -83,6 +126,11 @@ impl VideoQoS {
         self.height = height;
     }
 
+    #[inline]
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
     pub fn spf(&mut self) -> Duration {
         if self.fps < MIN_FPS || self.fps > MAX_FPS {
             self.fps = self.base_fps();
@@ -132,25 +180,108 @@ impl VideoQoS {
         }
     }
 
+    // update_network periodically with the stats of a connection
+    // follow the throughput: decrease below the send rate when the frames queue up
+    // or the peer sees jitter, then increase slowly
+    pub fn update_network(&mut self, stats: NetworkStats) {
+        self.network = stats;
+        if !self.enable_abr || self.width == 0 || self.height == 0 {
+            return;
+        }
+        let ceiling = self.quality_bitrate();
+        let last = if self.abr_bitrate == 0 {
+            ceiling
+        } else {
+            self.abr_bitrate
+        };
+        let spf = 1000 / self.fps.max(1) as u32;
+        let jitter = stats.arrival_jitter.max(stats.decode_jitter);
+        let bitrate;
+        if stats.backlog > 2 * spf.max(50) || jitter > 100 {
+            self.abr_state = AbrState::Decrease;
+            self.last_decrease = Instant::now();
+            let rate = if stats.send_rate > 0 {
+                stats.send_rate.min(last)
+            } else {
+                last
+            };
+            bitrate = rate * 85 / 100;
+        } else if stats.backlog > spf || jitter > 30 || self.last_decrease.elapsed() < ABR_HOLD {
+            self.abr_state = AbrState::Hold;
+            bitrate = last;
+        } else {
+            self.abr_state = AbrState::Increase;
+            bitrate = last + last / 20 + 10;
+        }
+        let bitrate = bitrate.max(MIN_ABR_BITRATE).min(ceiling);
+        log::trace!(
+            "VideoQoS update_network:{:?}, state:{:?}, bitrate:{}",
+            stats,
+            self.abr_state,
+            bitrate
+        );
+        if bitrate != self.abr_bitrate {
+            self.abr_bitrate = bitrate;
+            self.refresh_fps();
+            let _ = self.generate_bitrate().ok();
+            self.updated = true;
+        }
+    }
+
+    /// The bitrate capped by the throughput, its state and stats, `None` if not measured yet.
+    pub fn network_cap(&self) -> Option<(u32, AbrState, NetworkStats)> {
+        if self.abr_bitrate == 0 {
+            return None;
+        }
+        Some((self.abr_bitrate, self.abr_state, self.network))
+    }
+
+    // the shared qos follows the slowest of the connections, which run their own abr
+    pub fn follow_network(&mut self, abr_bitrate: u32, state: AbrState, stats: NetworkStats) {
+        self.network = stats;
+        self.abr_state = state;
+        if !self.enable_abr || self.width == 0 || self.height == 0 {
+            return;
+        }
+        let bitrate = abr_bitrate.min(self.quality_bitrate());
+        if bitrate != self.abr_bitrate {
+            self.abr_bitrate = bitrate;
+            self.refresh_fps();
+            let _ = self.generate_bitrate().ok();
+            self.updated = true;
+        }
+    }
+
+    fn refresh_fps(&mut self) {
+        let base = self.base_fps();
+        let mut fps = match self.state {
+            DelayState::Normal | DelayState::LowDelay => base,
+            DelayState::HighDelay => base / 2,
+            DelayState::Broken => base / 4,
+        };
+        // fewer frames with more bits each if the link is much slower than the quality needs
+        if self.abr_bitrate > 0 && self.abr_bitrate * 4 < self.quality_bitrate() {
+            fps = fps.min(base / 2);
+        }
+        self.fps = fps;
+    }
+
     fn refresh_quality(&mut self) {
         match self.state {
             DelayState::Normal => {
-                self.fps = self.base_fps();
                 self.current_image_quality = self.user_image_quality;
             }
             DelayState::LowDelay => {
-                self.fps = self.base_fps();
                 self.current_image_quality = std::cmp::min(self.user_image_quality, 50);
             }
             DelayState::HighDelay => {
-                self.fps = self.base_fps() / 2;
                 self.current_image_quality = std::cmp::min(self.user_image_quality, 25);
             }
             DelayState::Broken => {
-                self.fps = self.base_fps() / 4;
                 self.current_image_quality = 10;
             }
         }
+        self.refresh_fps();
         let _ = self.generate_bitrate().ok();
         self.updated = true;
     }
@@ -194,6 +325,15 @@ impl VideoQoS {
             self.current_image_quality = ImageQuality::Balanced.as_percent();
         }
 
+        self.target_bitrate = self.quality_bitrate();
+        if self.abr_bitrate > 0 {
+            self.target_bitrate = std::cmp::min(self.target_bitrate, self.abr_bitrate);
+        }
+        Ok(self.target_bitrate)
+    }
+
+    // the bitrate for the image quality, without the cap by the throughput
+    fn quality_bitrate(&self) -> u32 {
         let base_bitrate = ((self.width * self.height) / 800) as u32;
 
         #[cfg(target_os = "android")]
@@ -202,13 +342,11 @@ impl VideoQoS {
             let fix = scrap::Display::fix_quality() as u32;
             log::debug!("Android screen, fix quality:{}", fix);
             let base_bitrate = base_bitrate * fix;
-            self.target_bitrate = base_bitrate * self.current_image_quality / 100;
-            Ok(self.target_bitrate)
+            base_bitrate * self.current_image_quality / 100
         }
         #[cfg(not(target_os = "android"))]
         {
-            self.target_bitrate = base_bitrate * self.current_image_quality / 100;
-            Ok(self.target_bitrate)
+            base_bitrate * self.current_image_quality / 100
         }
     }
 
@@ -224,6 +362,15 @@ impl VideoQoS {
         *self = Default::default();
     }
 
+    // the qos of another display follows the settings of the peer
+    pub fn inherit(&mut self, other: &VideoQoS) {
+        self.user_image_quality = other.user_image_quality;
+        self.current_image_quality = other.current_image_quality;
+        self.user_fps = other.user_fps;
+        self.fps = other.fps;
+        self.current_delay = other.current_delay;
+    }
+
     pub fn check_abr_config(&mut self) -> bool {
         self.enable_abr = "N" != Config::get_option("enable-abr");
         self.enable_abr
@@ -241,3 +388,73 @@ impl VideoQoS {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_qos() -> VideoQoS {
+        let mut qos = VideoQoS::default();
+        qos.set_size(1920, 1080);
+        qos.enable_abr = true;
+        qos
+    }
+
+    fn stats(send_rate: u32, backlog: u32, jitter: u32) -> NetworkStats {
+        NetworkStats {
+            send_rate,
+            backlog,
+            arrival_jitter: jitter,
+            decode_jitter: 0,
+        }
+    }
+
+    #[test]
+    fn test_abr_decrease() {
+        let mut qos = new_qos();
+        qos.update_network(stats(1000, 500, 0));
+        assert_eq!(qos.abr_state, AbrState::Decrease);
+        assert_eq!(qos.target_bitrate, 850);
+        // by the jitter seen by the peer too
+        qos.update_network(stats(1000, 0, 200));
+        assert_eq!(qos.abr_state, AbrState::Decrease);
+        assert_eq!(qos.target_bitrate, 850 * 85 / 100);
+        // never below the minimum
+        for _ in 0..100 {
+            qos.update_network(stats(0, 500, 0));
+        }
+        assert_eq!(qos.target_bitrate, MIN_ABR_BITRATE);
+    }
+
+    #[test]
+    fn test_abr_hold_and_increase() {
+        let mut qos = new_qos();
+        qos.update_network(stats(1000, 500, 0));
+        // hold after a decrease
+        qos.update_network(stats(1000, 0, 0));
+        assert_eq!(qos.abr_state, AbrState::Hold);
+        assert_eq!(qos.target_bitrate, 850);
+        // then increase additively
+        if let Some(t) = Instant::now().checked_sub(ABR_HOLD * 2) {
+            qos.last_decrease = t;
+            qos.update_network(stats(1000, 0, 0));
+            assert_eq!(qos.abr_state, AbrState::Increase);
+            assert_eq!(qos.target_bitrate, 850 + 850 / 20 + 10);
+        }
+    }
+
+    #[test]
+    fn test_abr_ceiling() {
+        let mut qos = new_qos();
+        let ceiling = qos.quality_bitrate();
+        for _ in 0..100 {
+            qos.update_network(stats(ceiling * 2, 0, 0));
+        }
+        assert_eq!(qos.target_bitrate, ceiling);
+        // the shared qos follows a slower connection, but not above its own ceiling
+        qos.follow_network(500, AbrState::Decrease, stats(600, 500, 0));
+        assert_eq!(qos.target_bitrate, 500);
+        qos.follow_network(ceiling * 2, AbrState::Increase, stats(0, 0, 0));
+        assert_eq!(qos.target_bitrate, ceiling);
+    }
+}
@@ -18,7 +18,11 @@
 // to-do:
 // https://slhck.info/video/2017/03/01/rate-control.html
 
-use super::{video_qos::VideoQoS, *};
+use super::{
+    video_qos::{AbrState, NetworkStats, VideoQoS},
+    *,
+};
+use crate::ipc::ShareTarget;
 #[cfg(windows)]
 use hbb_common::get_version_number;
 use hbb_common::tokio::sync::{
@@ -27,8 +31,10 @@ use hbb_common::tokio::sync::{
 };
 #[cfg(not(windows))]
 use scrap::Capturer;
+#[cfg(feature = "av1")]
+use scrap::aomcodec::AomEncoderConfig;
 use scrap::{
-    codec::{Encoder, EncoderCfg, HwEncoderConfig},
+    codec::{Encoder, EncoderCfg, EncoderChoice, HwEncoderConfig},
     record::{Recorder, RecorderContext},
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
     Display, TraitCapturer,
@@ -51,6 +57,17 @@ pub const SCRAP_X11_REQUIRED: &str = "x11 expected";
 pub const SCRAP_X11_REF_URL: &str = "https://rustdesk.com/docs/en/manual/linux/#x11-required";
 
 pub const NAME: &'static str = "video";
+/// Services of the displays streamed besides the current one, indexed by display.
+pub const DISPLAY_NAMES: [&'static str; 8] = [
+    "video_display0",
+    "video_display1",
+    "video_display2",
+    "video_display3",
+    "video_display4",
+    "video_display5",
+    "video_display6",
+    "video_display7",
+];
 
 lazy_static::lazy_static! {
     pub static ref CURRENT_DISPLAY: Arc<Mutex<usize>> = Arc::new(Mutex::new(usize::MAX));
@@ -63,9 +80,40 @@ lazy_static::lazy_static! {
     static ref PRIVACY_MODE_CONN_ID: Mutex<i32> = Mutex::new(0);
     static ref IS_CAPTURER_MAGNIFIER_SUPPORTED: bool = is_capturer_mag_supported();
     pub static ref VIDEO_QOS: Arc<Mutex<VideoQoS>> = Default::default();
+    static ref DISPLAY_QOS: Mutex<HashMap<usize, Arc<Mutex<VideoQoS>>>> = Default::default();
+    static ref CONN_QOS: Mutex<HashMap<i32, Arc<Mutex<VideoQoS>>>> = Default::default();
     pub static ref IS_UAC_RUNNING: Arc<Mutex<bool>> = Default::default();
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
     pub static ref LAST_SYNC_DISPLAYS: Arc<RwLock<Vec<DisplayInfo>>> = Default::default();
+    static ref CURRENT_RECORDER: Mutex<Weak<Mutex<Option<Recorder>>>> = Default::default();
+    // the connections sharing a window or a rect, all of them share the same one
+    static ref SHARE_TARGETS: Mutex<Vec<(i32, ShareTarget)>> = Default::default();
+    // the peers were told the shared window is gone
+    static ref SHARE_TARGET_GONE: Mutex<bool> = Default::default();
+    // the frames of the displays streamed by run_display
+    static ref DISPLAY_FETCHED_NOTIFIERS: Mutex<HashMap<usize, std::sync::mpsc::Sender<i32>>> =
+        Default::default();
+}
+
+#[cfg(any(windows, target_os = "linux"))]
+lazy_static::lazy_static! {
+    static ref SHARED_REGION: Mutex<Option<SharedRegion>> = Default::default();
+}
+
+// Where the shared region was announced to the peers, and where it is now, in screen coordinates.
+#[cfg(any(windows, target_os = "linux"))]
+#[derive(Debug, Clone, Copy)]
+struct SharedRegion {
+    announced: (i32, i32),
+    current: (i32, i32, i32, i32),
+}
+
+// The region captured instead of the whole display.
+#[cfg(any(windows, target_os = "linux"))]
+pub(super) struct CapturedRegion {
+    target: ShareTarget,
+    region: Arc<Mutex<scrap::CaptureRegion>>,
+    display: ((i32, i32), usize, usize),
 }
 
 fn is_capturer_mag_supported() -> bool {
@@ -83,6 +131,57 @@ pub fn notify_video_frame_fetched(conn_id: i32, frame_tm: Option<Instant>) {
     FRAME_FETCHED_NOTIFIER.0.send((conn_id, frame_tm)).unwrap()
 }
 
+// The frames of the other displays are fetched once sent, the peer only acks the main display.
+pub fn notify_video_frame_sent(
+    conn_id: i32,
+    display: usize,
+    frame_tm: Instant,
+    ack_required: bool,
+) {
+    if let Some(tx) = DISPLAY_FETCHED_NOTIFIERS.lock().unwrap().get(&display) {
+        tx.send(conn_id).ok();
+    } else if !ack_required {
+        notify_video_frame_fetched(conn_id, Some(frame_tm));
+    }
+}
+
+struct DisplayFetchedNotifier(usize);
+
+impl DisplayFetchedNotifier {
+    fn new(display: usize) -> (Self, std::sync::mpsc::Receiver<i32>) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        DISPLAY_FETCHED_NOTIFIERS.lock().unwrap().insert(display, tx);
+        (Self(display), rx)
+    }
+}
+
+impl Drop for DisplayFetchedNotifier {
+    fn drop(&mut self) {
+        DISPLAY_FETCHED_NOTIFIERS.lock().unwrap().remove(&self.0);
+    }
+}
+
+// Wait until all the peers sent the frame have fetched it, or timeout.
+fn wait_display_fetched(
+    rx: &std::sync::mpsc::Receiver<i32>,
+    mut send_conn_ids: HashSet<i32>,
+    timeout: Duration,
+) {
+    let begin = Instant::now();
+    while !send_conn_ids.is_empty() {
+        let left = match timeout.checked_sub(begin.elapsed()) {
+            Some(left) => left,
+            None => break,
+        };
+        match rx.recv_timeout(left) {
+            Ok(id) => {
+                send_conn_ids.remove(&id);
+            }
+            Err(_) => break,
+        }
+    }
+}
+
 pub fn set_privacy_mode_conn_id(conn_id: i32) {
     *PRIVACY_MODE_CONN_ID.lock().unwrap() = conn_id
 }
@@ -91,6 +190,176 @@ pub fn get_privacy_mode_conn_id() -> i32 {
     *PRIVACY_MODE_CONN_ID.lock().unwrap()
 }
 
+fn get_share_target() -> Option<ShareTarget> {
+    SHARE_TARGETS.lock().unwrap().first().map(|t| t.1.clone())
+}
+
+/// Share a window or a rect of the screen with the connection, `None` to share the display.
+/// The capturer is shared by the connections, so a target different from the one another
+/// connection shares is refused.
+pub fn set_share_target(conn_id: i32, target: Option<ShareTarget>) -> ResultType<()> {
+    #[cfg(not(any(windows, target_os = "linux")))]
+    if target.is_some() {
+        bail!("Sharing a window or a region is not supported on this platform");
+    }
+    #[cfg(target_os = "linux")]
+    if target.is_some() && !scrap::is_x11() {
+        bail!("Sharing a window or a region is not supported on Wayland");
+    }
+    let mut targets = SHARE_TARGETS.lock().unwrap();
+    if let Some(target) = &target {
+        if let Some((id, other)) = targets.iter().find(|t| t.0 != conn_id && t.1 != *target) {
+            bail!("Connection {} shares {:?}, refuse to share {:?}", id, other, target);
+        }
+    }
+    let old = targets.first().map(|t| t.1.clone());
+    targets.retain(|t| t.0 != conn_id);
+    if let Some(target) = target {
+        targets.push((conn_id, target));
+    }
+    let new = targets.first().map(|t| t.1.clone());
+    drop(targets);
+    if old != new {
+        log::info!("share target: {:?}", new);
+        *SHARE_TARGET_GONE.lock().unwrap() = false;
+        *SWITCH.lock().unwrap() = true;
+    }
+    Ok(())
+}
+
+/// Map a position sent by the peer to the screen when the shared region has moved.
+#[cfg(any(windows, target_os = "linux"))]
+pub fn translate_to_region(x: i32, y: i32) -> Option<(i32, i32)> {
+    let r = (*SHARED_REGION.lock().unwrap())?;
+    Some(r.translate(x, y))
+}
+
+#[cfg(any(windows, target_os = "linux"))]
+impl SharedRegion {
+    // Move a position by the offset of the region since it was announced, clamped to the region.
+    fn translate(&self, x: i32, y: i32) -> (i32, i32) {
+        let (left, top, width, height) = self.current;
+        let x = (x - self.announced.0 + left).max(left).min(left + width - 1);
+        let y = (y - self.announced.1 + top).max(top).min(top + height - 1);
+        (x, y)
+    }
+}
+
+#[cfg(any(windows, target_os = "linux"))]
+fn share_rect(target: &ShareTarget) -> Option<(i32, i32, i32, i32)> {
+    match target {
+        ShareTarget::Window(id) => crate::platform::get_window_rect(*id),
+        ShareTarget::Rect {
+            x,
+            y,
+            width,
+            height,
+        } => Some((*x, *y, *width, *height)),
+    }
+}
+
+// Clip a rect of the screen to the display, with even sizes for the yuv conversion.
+#[cfg(any(windows, target_os = "linux"))]
+fn clip_region(
+    rect: (i32, i32, i32, i32),
+    origin: (i32, i32),
+    width: usize,
+    height: usize,
+) -> Option<scrap::CaptureRegion> {
+    let left = rect.0.max(origin.0);
+    let top = rect.1.max(origin.1);
+    let right = (rect.0 + rect.2).min(origin.0 + width as i32);
+    let bottom = (rect.1 + rect.3).min(origin.1 + height as i32);
+    let (w, h) = ((right - left) & !1, (bottom - top) & !1);
+    if w <= 0 || h <= 0 {
+        return None;
+    }
+    Some(scrap::CaptureRegion {
+        x: (left - origin.0) as _,
+        y: (top - origin.1) as _,
+        width: w as _,
+        height: h as _,
+    })
+}
+
+// Switch to the display with the center of the shared rect.
+#[cfg(any(windows, target_os = "linux"))]
+fn select_share_display(target: &ShareTarget) {
+    let (x, y, w, h) = match share_rect(target) {
+        Some(r) => r,
+        None => return,
+    };
+    let (cx, cy) = (x + w / 2, y + h / 2);
+    if let Ok(displays) = try_get_displays() {
+        if let Some(idx) = displays.iter().position(|d| {
+            let (dx, dy) = d.origin();
+            cx >= dx && cx < dx + d.width() as i32 && cy >= dy && cy < dy + d.height() as i32
+        }) {
+            *CURRENT_DISPLAY.lock().unwrap() = idx;
+        }
+    }
+}
+
+// Pause the stream while the shared window is gone, the peers sharing it are told once.
+#[cfg(any(windows, target_os = "linux"))]
+fn check_shared_window(sp: &GenericService) -> ResultType<()> {
+    let target = match get_share_target() {
+        Some(target @ ShareTarget::Window(_)) => target,
+        _ => return Ok(()),
+    };
+    if share_rect(&target).is_some() {
+        *SHARE_TARGET_GONE.lock().unwrap() = false;
+        return Ok(());
+    }
+    let notify = !std::mem::replace(&mut *SHARE_TARGET_GONE.lock().unwrap(), true);
+    if notify {
+        log::info!("{:?} is gone, pause sharing it", target);
+        let conn_ids: Vec<i32> = SHARE_TARGETS.lock().unwrap().iter().map(|t| t.0).collect();
+        for conn_id in conn_ids {
+            let mut msg_out = Message::new();
+            msg_out.set_message_box(MessageBox {
+                msgtype: "nook-nocancel-hasclose".to_owned(),
+                title: "Share".to_owned(),
+                text: "The shared window is closed.".to_owned(),
+                link: "".to_owned(),
+                ..Default::default()
+            });
+            sp.send_to(msg_out, conn_id);
+        }
+    }
+    bail!("{:?} is gone", target);
+}
+
+// Follow the shared window, restart capturing if its size changes.
+#[cfg(any(windows, target_os = "linux"))]
+fn follow_shared_window(c: &CapturerInfo) -> ResultType<()> {
+    let captured = match &c.region {
+        Some(captured) => captured,
+        None => return Ok(()),
+    };
+    if let ShareTarget::Window(_) = captured.target {
+        let (origin, width, height) = captured.display;
+        let r = share_rect(&captured.target).and_then(|r| clip_region(r, origin, width, height));
+        let mut region = captured.region.lock().unwrap();
+        match r {
+            Some(r) if r.width == region.width && r.height == region.height => {
+                if r != *region {
+                    *region = r;
+                    if let Some(shared) = SHARED_REGION.lock().unwrap().as_mut() {
+                        shared.current.0 = origin.0 + r.x as i32;
+                        shared.current.1 = origin.1 + r.y as i32;
+                    }
+                }
+            }
+            _ => {
+                *SWITCH.lock().unwrap() = true;
+                bail!("SWITCH");
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn is_privacy_mode_supported() -> bool {
     #[cfg(windows)]
     return *IS_CAPTURER_MAGNIFIER_SUPPORTED
@@ -149,12 +418,323 @@ impl VideoFrameController {
     }
 }
 
+enum VideoEncoder {
+    Shared(Encoder),
+    Separate(ConnEncoders),
+}
+
+impl VideoEncoder {
+    // capture for the fastest peer if each has its own encoder
+    fn spf(&self, shared: Duration) -> Duration {
+        match self {
+            VideoEncoder::Shared(_) => shared,
+            VideoEncoder::Separate(encoders) => encoders.spf(shared),
+        }
+    }
+
+    fn handle_frame(
+        &mut self,
+        sp: &GenericService,
+        frame: &[u8],
+        ms: i64,
+        display: usize,
+        recorder: Arc<Mutex<Option<Recorder>>>,
+        record_choice: &EncoderChoice,
+    ) -> ResultType<HashSet<i32>> {
+        match self {
+            VideoEncoder::Shared(encoder) => {
+                handle_one_frame(sp, frame, ms, display, encoder, recorder)
+            }
+            VideoEncoder::Separate(encoders) => {
+                encoders.handle_frame(sp, frame, ms, display, recorder, record_choice)
+            }
+        }
+    }
+}
+
+struct ConnEncoder {
+    encoder: Encoder,
+    choice: EncoderChoice,
+    qos: Arc<Mutex<VideoQoS>>,
+    spf: Duration,
+    last_encode: Option<Instant>,
+}
+
+// An encoder with its own qos and codec for each connection,
+// so that a peer on a slow network does not lower the quality for the others.
+// The capturer outputs bgra, converted to yuv once for the encoders using it.
+struct ConnEncoders {
+    width: usize,
+    height: usize,
+    encoders: HashMap<i32, ConnEncoder>,
+    // the choices failed to be created, not retried until the peer changes its codec
+    failed: HashMap<i32, EncoderChoice>,
+    // the connection whose frames are recorded, kept until it leaves or changes its codec
+    record_id: Option<i32>,
+    i420: Vec<u8>,
+}
+
+impl ConnEncoders {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            encoders: Default::default(),
+            failed: Default::default(),
+            record_id: None,
+            i420: Vec::new(),
+        }
+    }
+
+    fn spf(&self, default: Duration) -> Duration {
+        self.encoders
+            .values()
+            .map(|e| e.spf)
+            .min()
+            .unwrap_or(default)
+    }
+
+    fn create(
+        width: usize,
+        height: usize,
+        id: i32,
+        choice: EncoderChoice,
+    ) -> ResultType<ConnEncoder> {
+        let qos = conn_qos(id);
+        let mut video_qos = qos.lock().unwrap();
+        video_qos.set_size(width as _, height as _);
+        video_qos.check_abr_config();
+        let bitrate = video_qos.generate_bitrate()?;
+        let spf = video_qos.spf();
+        video_qos.check_if_updated();
+        drop(video_qos);
+        log::info!("new encoder of connection {}: {:?}, bitrate={}", id, choice, bitrate);
+        let encoder = match Encoder::new(get_encoder_config(width, height, bitrate, &choice)) {
+            Ok(encoder) => encoder,
+            Err(err) if choice.hw_name.is_some() || choice.av1 => {
+                log::error!("Failed to create encoder: {}, fall back to vp9", err);
+                let vp9 = EncoderChoice::default();
+                match Encoder::new(get_encoder_config(width, height, bitrate, &vp9)) {
+                    Ok(encoder) => encoder,
+                    Err(err) => bail!("Failed to create encoder: {}", err),
+                }
+            }
+            Err(err) => bail!("Failed to create encoder: {}", err),
+        };
+        Ok(ConnEncoder {
+            encoder,
+            choice,
+            qos,
+            spf,
+            last_encode: None,
+        })
+    }
+
+    fn handle_frame(
+        &mut self,
+        sp: &GenericService,
+        frame: &[u8],
+        ms: i64,
+        display: usize,
+        recorder: Arc<Mutex<Option<Recorder>>>,
+        record_choice: &EncoderChoice,
+    ) -> ResultType<HashSet<i32>> {
+        // the new subscribers get a new encoder, which begins with a key frame
+        sp.snapshot(|_| Ok(()))?;
+        let ids = sp.subscriber_ids();
+        self.encoders.retain(|id, _| ids.contains(id));
+        self.failed.retain(|id, _| ids.contains(id));
+        if self.record_id.map_or(false, |id| !self.encoders.contains_key(&id)) {
+            self.record_id = None;
+        }
+
+        let now = Instant::now();
+        let mut converted = false;
+        let mut send_conn_ids: HashSet<i32> = Default::default();
+        for id in ids {
+            let choice = Encoder::peer_choice(id);
+            if self.encoders.get(&id).map_or(true, |e| e.choice != choice) {
+                if self.failed.get(&id) == Some(&choice) {
+                    continue;
+                }
+                if self.record_id == Some(id) {
+                    self.record_id = None;
+                }
+                match Self::create(self.width, self.height, id, choice.clone()) {
+                    Ok(e) => {
+                        self.failed.remove(&id);
+                        self.encoders.insert(id, e);
+                    }
+                    Err(err) => {
+                        // the other peers keep their video
+                        log::error!("Failed to create encoder of connection {}: {}", id, err);
+                        self.encoders.remove(&id);
+                        self.failed.insert(id, choice);
+                        continue;
+                    }
+                }
+            }
+            let e = match self.encoders.get_mut(&id) {
+                Some(e) => e,
+                None => continue,
+            };
+            // the recording is pinned to one encoder whose codec is the recorder's
+            if self.record_id.is_none()
+                && e.choice.hw_name == record_choice.hw_name
+                && e.choice.av1 == record_choice.av1
+            {
+                self.record_id = Some(id);
+                // a switched recording resumes with a key frame
+                e.encoder.request_keyframe();
+            }
+            let mut video_qos = e.qos.lock().unwrap();
+            if video_qos.check_if_updated() {
+                log::debug!(
+                    "qos of connection {} is updated, target_bitrate:{}, fps:{}",
+                    id,
+                    video_qos.target_bitrate,
+                    video_qos.fps
+                );
+                allow_err!(e.encoder.set_bitrate(video_qos.target_bitrate));
+                e.spf = video_qos.spf();
+            }
+            drop(video_qos);
+            // the frames are captured for the fastest peer, skip some for the slower ones
+            if e.last_encode.map_or(false, |t| now - t < e.spf * 3 / 4) {
+                continue;
+            }
+            e.last_encode = Some(now);
+            let input = if e.encoder.use_yuv() {
+                if !converted {
+                    scrap::bgra_to_i420(self.width, self.height, frame, &mut self.i420);
+                    converted = true;
+                }
+                &self.i420[..]
+            } else {
+                frame
+            };
+            if let Ok(mut msg) = e.encoder.encode_to_message(input, ms) {
+                if let Some(message::Union::VideoFrame(vf)) = &mut msg.union {
+                    vf.display = display as _;
+                }
+                #[cfg(not(target_os = "ios"))]
+                if Some(id) == self.record_id {
+                    let split = recorder.lock().unwrap().as_mut().map_or(false, |r| {
+                        r.write_message(&msg);
+                        r.split_pending()
+                    });
+                    if split {
+                        // the recorder starts the next segment with it
+                        e.encoder.request_keyframe();
+                    }
+                }
+                if sp.send_video_frame_to(msg, id) {
+                    send_conn_ids.insert(id);
+                }
+            }
+        }
+        Ok(send_conn_ids)
+    }
+}
+
+// Encode the video of each connection with its own encoder and qos.
+fn separate_encoders() -> bool {
+    Config::get_option("allow-separate-encoders") == "Y"
+}
+
 pub fn new() -> GenericService {
     let sp = GenericService::new(NAME, true);
     sp.run(run);
     sp
 }
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn new_display(idx: usize) -> GenericService {
+    let sp = GenericService::new(DISPLAY_NAMES[idx], true);
+    sp.run(move |sp| run_display(sp, idx));
+    sp
+}
+
+/// Apply a change of the peer settings to the qos of all the displays.
+pub fn update_qos<F: Fn(&mut VideoQoS)>(f: F) {
+    f(&mut VIDEO_QOS.lock().unwrap());
+    for qos in DISPLAY_QOS.lock().unwrap().values() {
+        f(&mut qos.lock().unwrap());
+    }
+}
+
+/// Apply a change of the settings or the network of a peer to its own qos and the shared ones.
+pub fn update_conn_qos<F: Fn(&mut VideoQoS)>(id: i32, f: F) {
+    f(&mut conn_qos(id).lock().unwrap());
+    update_qos(&f);
+}
+
+/// Run the abr of a connection, the shared qos follow the slowest connection.
+pub fn update_conn_network(id: i32, delay: u32, stats: NetworkStats) {
+    let (width, height) = VIDEO_QOS.lock().unwrap().size();
+    let qos = conn_qos(id);
+    let mut qos = qos.lock().unwrap();
+    if !separate_encoders() {
+        // no encoder of its own
+        qos.set_size(width, height);
+        qos.check_abr_config();
+    }
+    qos.update_network_delay(delay);
+    qos.update_network(stats);
+    drop(qos);
+    let conns: Vec<_> = CONN_QOS.lock().unwrap().values().cloned().collect();
+    let mut delay = 0;
+    let mut slowest: Option<(u32, AbrState, NetworkStats)> = None;
+    for qos in conns {
+        let qos = qos.lock().unwrap();
+        delay = delay.max(qos.current_delay);
+        if let Some(cap) = qos.network_cap() {
+            if slowest.map_or(true, |s| cap.0 < s.0) {
+                slowest = Some(cap);
+            }
+        }
+    }
+    update_qos(|qos| {
+        qos.update_network_delay(delay);
+        if let Some((bitrate, state, stats)) = slowest {
+            qos.follow_network(bitrate, state, stats);
+        }
+    });
+}
+
+pub fn remove_conn_qos(id: i32) {
+    CONN_QOS.lock().unwrap().remove(&id);
+}
+
+/// Read the qos of the video sent to the connection `id`.
+pub fn with_conn_qos<R, F: FnOnce(&VideoQoS) -> R>(id: i32, f: F) -> R {
+    if separate_encoders() {
+        let qos = CONN_QOS.lock().unwrap().get(&id).cloned();
+        if let Some(qos) = qos {
+            return f(&qos.lock().unwrap());
+        }
+    }
+    f(&VIDEO_QOS.lock().unwrap())
+}
+
+fn conn_qos(id: i32) -> Arc<Mutex<VideoQoS>> {
+    CONN_QOS.lock().unwrap().entry(id).or_default().clone()
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn display_qos(idx: usize) -> Arc<Mutex<VideoQoS>> {
+    DISPLAY_QOS
+        .lock()
+        .unwrap()
+        .entry(idx)
+        .or_insert_with(|| {
+            let mut qos = VideoQoS::default();
+            qos.inherit(&VIDEO_QOS.lock().unwrap());
+            Arc::new(Mutex::new(qos))
+        })
+        .clone()
+}
+
 fn check_display_changed(
     last_n: usize,
     last_current: usize,
@@ -329,6 +909,19 @@ pub(super) struct CapturerInfo {
     pub privacy_mode_id: i32,
     pub _capturer_privacy_mode_id: i32,
     pub capturer: Box<dyn TraitCapturer>,
+    #[cfg(any(windows, target_os = "linux"))]
+    pub region: Option<CapturedRegion>,
+}
+
+impl CapturerInfo {
+    // The frames are smaller than the display when sharing a region.
+    fn display_size(&self) -> (usize, usize) {
+        #[cfg(any(windows, target_os = "linux"))]
+        if let Some(captured) = &self.region {
+            return (captured.display.1, captured.display.2);
+        }
+        (self.width, self.height)
+    }
 }
 
 impl Deref for CapturerInfo {
@@ -353,8 +946,33 @@ fn get_capturer(use_yuv: bool, portable_service_running: bool) -> ResultType<Cap
         }
     }
 
+    let share_target = get_share_target();
+    #[cfg(any(windows, target_os = "linux"))]
+    if let Some(target) = &share_target {
+        select_share_display(target);
+    }
     let (ndisplay, current, display) = get_current_display()?;
+    #[cfg(any(windows, target_os = "linux"))]
+    let (mut origin, mut width, mut height) = (display.origin(), display.width(), display.height());
+    #[cfg(not(any(windows, target_os = "linux")))]
     let (origin, width, height) = (display.origin(), display.width(), display.height());
+    #[cfg(any(windows, target_os = "linux"))]
+    let region = match share_target {
+        Some(target) => match share_rect(&target) {
+            Some(rect) => match clip_region(rect, origin, width, height) {
+                Some(r) => Some((target, r)),
+                // wait for it to be visible again
+                None => bail!("{:?} is not visible on display {}", target, current),
+            },
+            // never fall back to the whole display, see check_shared_window
+            None => bail!("{:?} is gone", target),
+        },
+        None => None,
+    };
+    #[cfg(not(any(windows, target_os = "linux")))]
+    if share_target.is_some() {
+        bail!("Sharing a region is not supported");
+    }
     log::debug!(
         "#displays={}, current={}, origin: {:?}, width={}, height={}, cpus={}/{}, name:{}",
         ndisplay,
@@ -397,6 +1015,37 @@ fn get_capturer(use_yuv: bool, portable_service_running: bool) -> ResultType<Cap
         current,
         portable_service_running,
     )?;
+    #[cfg(any(windows, target_os = "linux"))]
+    let (capturer, region) = match region {
+        Some((target, r)) => {
+            log::info!("Share {:?}, region: {:?}", target, r);
+            let display = (origin, width, height);
+            let shared = Arc::new(Mutex::new(r));
+            let capturer: Box<dyn TraitCapturer> = Box::new(scrap::RegionCapturer::new(
+                capturer,
+                height,
+                shared.clone(),
+                use_yuv,
+            ));
+            origin = (origin.0 + r.x as i32, origin.1 + r.y as i32);
+            width = r.width;
+            height = r.height;
+            *SHARED_REGION.lock().unwrap() = Some(SharedRegion {
+                announced: origin,
+                current: (origin.0, origin.1, width as _, height as _),
+            });
+            let region = CapturedRegion {
+                target,
+                region: shared,
+                display,
+            };
+            (capturer, Some(region))
+        }
+        None => {
+            *SHARED_REGION.lock().unwrap() = None;
+            (capturer, None)
+        }
+    };
     Ok(CapturerInfo {
         origin,
         width,
@@ -406,6 +1055,8 @@ fn get_capturer(use_yuv: bool, portable_service_running: bool) -> ResultType<Cap
         privacy_mode_id,
         _capturer_privacy_mode_id: capturer_privacy_mode_id,
         capturer,
+        #[cfg(any(windows, target_os = "linux"))]
+        region,
     })
 }
 
@@ -458,6 +1109,8 @@ fn run(sp: GenericService) -> ResultType<()> {
     #[cfg(not(windows))]
     let last_portable_service_running = false;
 
+    #[cfg(any(windows, target_os = "linux"))]
+    check_shared_window(&sp)?;
     let mut c = get_capturer(true, last_portable_service_running)?;
 
     let mut video_qos = VIDEO_QOS.lock().unwrap();
@@ -468,29 +1121,20 @@ fn run(sp: GenericService) -> ResultType<()> {
     drop(video_qos);
     log::info!("init bitrate={}, abr enabled:{}", bitrate, abr);
 
-    let encoder_cfg = match Encoder::current_hw_encoder_name() {
-        Some(codec_name) => EncoderCfg::HW(HwEncoderConfig {
-            codec_name,
-            width: c.width,
-            height: c.height,
-            bitrate: bitrate as _,
-        }),
-        None => EncoderCfg::VPX(VpxEncoderConfig {
-            width: c.width as _,
-            height: c.height as _,
-            timebase: [1, 1000], // Output timestamp precision
-            bitrate,
-            codec: VpxVideoCodecId::VP9,
-            num_threads: (num_cpus::get() / 2) as _,
-        }),
+    let separate = separate_encoders();
+    let choice = Encoder::shared_choice();
+    let mut encoder = if separate {
+        VideoEncoder::Separate(ConnEncoders::new(c.width, c.height))
+    } else {
+        match Encoder::new(get_encoder_config(c.width, c.height, bitrate, &choice)) {
+            Ok(x) => VideoEncoder::Shared(x),
+            Err(err) => bail!("Failed to create encoder: {}", err),
+        }
     };
-
-    let mut encoder;
-    match Encoder::new(encoder_cfg) {
-        Ok(x) => encoder = x,
-        Err(err) => bail!("Failed to create encoder: {}", err),
-    }
-    c.set_use_yuv(encoder.use_yuv());
+    c.set_use_yuv(match &encoder {
+        VideoEncoder::Shared(encoder) => encoder.use_yuv(),
+        VideoEncoder::Separate(_) => false,
+    });
 
     if *SWITCH.lock().unwrap() {
         log::debug!("Broadcasting display switch");
@@ -526,8 +1170,8 @@ fn run(sp: GenericService) -> ResultType<()> {
     let mut try_gdi = 1;
     #[cfg(windows)]
     log::info!("gdi: {}", c.is_gdi());
-    let codec_name = Encoder::current_hw_encoder_name();
-    let recorder = get_recorder(c.width, c.height, &codec_name);
+    let recorder = get_recorder(c.width, c.height, &choice.hw_name, choice.av1);
+    *CURRENT_RECORDER.lock().unwrap() = Arc::downgrade(&recorder);
     #[cfg(windows)]
     start_uac_elevation_check();
 
@@ -545,10 +1189,13 @@ fn run(sp: GenericService) -> ResultType<()> {
                 video_qos.target_bitrate,
                 video_qos.fps
             );
-            allow_err!(encoder.set_bitrate(video_qos.target_bitrate));
+            if let VideoEncoder::Shared(encoder) = &mut encoder {
+                allow_err!(encoder.set_bitrate(video_qos.target_bitrate));
+            }
             spf = video_qos.spf();
         }
         drop(video_qos);
+        let spf = encoder.spf(spf);
 
         if *SWITCH.lock().unwrap() {
             bail!("SWITCH");
@@ -557,7 +1204,8 @@ fn run(sp: GenericService) -> ResultType<()> {
             *SWITCH.lock().unwrap() = true;
             bail!("SWITCH");
         }
-        if codec_name != Encoder::current_hw_encoder_name() {
+        // the separate encoders follow the codec of their own peer
+        if separate != separate_encoders() || !separate && choice != Encoder::shared_choice() {
             bail!("SWITCH");
         }
         #[cfg(windows)]
@@ -565,6 +1213,8 @@ fn run(sp: GenericService) -> ResultType<()> {
             bail!("SWITCH");
         }
         check_privacy_mode_changed(&sp, c.privacy_mode_id)?;
+        #[cfg(any(windows, target_os = "linux"))]
+        follow_shared_window(&c)?;
         #[cfg(windows)]
         {
             if crate::platform::windows::desktop_changed()
@@ -612,8 +1262,14 @@ fn run(sp: GenericService) -> ResultType<()> {
                     }
                     scrap::Frame::RAW(data) => {
                         if data.len() != 0 {
-                            let send_conn_ids =
-                                handle_one_frame(&sp, data, ms, &mut encoder, recorder.clone())?;
+                            let send_conn_ids = encoder.handle_frame(
+                                &sp,
+                                data,
+                                ms,
+                                c.current,
+                                recorder.clone(),
+                                &choice,
+                            )?;
                             frame_controller.set_send(now, send_conn_ids);
                         }
                     }
@@ -629,8 +1285,14 @@ fn run(sp: GenericService) -> ResultType<()> {
             Ok(frame) => {
                 let time = now - start;
                 let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
-                let send_conn_ids =
-                    handle_one_frame(&sp, &frame, ms, &mut encoder, recorder.clone())?;
+                let send_conn_ids = encoder.handle_frame(
+                    &sp,
+                    &frame,
+                    ms,
+                    c.current,
+                    recorder.clone(),
+                    &choice,
+                )?;
                 frame_controller.set_send(now, send_conn_ids);
                 #[cfg(windows)]
                 {
@@ -664,7 +1326,8 @@ fn run(sp: GenericService) -> ResultType<()> {
                 }
             }
             Err(err) => {
-                if check_display_changed(c.ndisplay, c.current, c.width, c.height) {
+                let (width, height) = c.display_size();
+                if check_display_changed(c.ndisplay, c.current, width, height) {
                     log::info!("Displays changed");
                     *SWITCH.lock().unwrap() = true;
                     bail!("SWITCH");
@@ -717,10 +1380,142 @@ fn run(sp: GenericService) -> ResultType<()> {
     Ok(())
 }
 
+// The current display is sent by the main video service,
+// and nothing else in privacy mode or when sharing a region.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn can_capture_display(idx: usize) -> bool {
+    #[cfg(target_os = "linux")]
+    if !scrap::is_x11() {
+        return false;
+    }
+    idx != *CURRENT_DISPLAY.lock().unwrap()
+        && get_privacy_mode_conn_id() == 0
+        && SHARE_TARGETS.lock().unwrap().is_empty()
+}
+
+// Stream the display `idx` with its own capturer, encoder and qos.
+// Like the main video service, a frame waits for the peers to fetch the previous one.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn run_display(sp: GenericService, idx: usize) -> ResultType<()> {
+    if !can_capture_display(idx) {
+        std::thread::sleep(Duration::from_millis(300));
+        return Ok(());
+    }
+    let mut displays = try_get_displays()?;
+    let ndisplay = displays.len();
+    if idx >= ndisplay {
+        std::thread::sleep(Duration::from_millis(300));
+        return Ok(());
+    }
+    let display = displays.remove(idx);
+    let (width, height) = (display.width(), display.height());
+    log::debug!(
+        "capture display {}, origin: {:?}, width={}, height={}, name:{}",
+        idx,
+        display.origin(),
+        width,
+        height,
+        display.name(),
+    );
+    let mut c = create_capturer(0, display, true, idx, false)?;
+
+    let qos = display_qos(idx);
+    let mut video_qos = qos.lock().unwrap();
+    video_qos.set_size(width as _, height as _);
+    let mut spf = video_qos.spf();
+    let bitrate = video_qos.generate_bitrate()?;
+    drop(video_qos);
+
+    let choice = Encoder::shared_choice();
+    let mut encoder;
+    match Encoder::new(get_encoder_config(width, height, bitrate, &choice)) {
+        Ok(x) => encoder = x,
+        Err(err) => bail!("Failed to create encoder: {}", err),
+    }
+    c.set_use_yuv(encoder.use_yuv());
+
+    let (_notifier, fetched) = DisplayFetchedNotifier::new(idx);
+    let start = time::Instant::now();
+    let mut last_check_displays = time::Instant::now();
+    while sp.ok() {
+        let mut video_qos = qos.lock().unwrap();
+        if video_qos.check_if_updated() {
+            allow_err!(encoder.set_bitrate(video_qos.target_bitrate));
+            spf = video_qos.spf();
+        }
+        drop(video_qos);
+
+        if !can_capture_display(idx) || choice != Encoder::shared_choice() {
+            bail!("SWITCH");
+        }
+        let now = time::Instant::now();
+        if last_check_displays.elapsed().as_millis() > 1000 {
+            last_check_displays = now;
+            if ndisplay != get_display_num() {
+                bail!("SWITCH");
+            }
+        }
+
+        match c.frame(spf) {
+            Ok(frame) => {
+                let time = now - start;
+                let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
+                // drop the late acks of the frames timed out
+                fetched.try_iter().count();
+                let send_conn_ids =
+                    handle_one_frame(&sp, &frame, ms, idx, &mut encoder, Default::default())?;
+                wait_display_fetched(&fetched, send_conn_ids, Duration::from_millis(3_000));
+            }
+            Err(ref e) if e.kind() == WouldBlock => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let elapsed = now.elapsed();
+        if elapsed < spf {
+            std::thread::sleep(spf - elapsed);
+        }
+    }
+    Ok(())
+}
+
+fn get_encoder_config(
+    width: usize,
+    height: usize,
+    bitrate: u32,
+    choice: &EncoderChoice,
+) -> EncoderCfg {
+    match choice.hw_name.clone() {
+        Some(codec_name) => EncoderCfg::HW(HwEncoderConfig {
+            codec_name,
+            width,
+            height,
+            bitrate: bitrate as _,
+        }),
+        #[cfg(feature = "av1")]
+        None if choice.av1 => EncoderCfg::AOM(AomEncoderConfig {
+            width: width as _,
+            height: height as _,
+            timebase: [1, 1000], // Output timestamp precision
+            bitrate,
+            num_threads: (num_cpus::get() / 2) as _,
+        }),
+        None => EncoderCfg::VPX(VpxEncoderConfig {
+            width: width as _,
+            height: height as _,
+            timebase: [1, 1000], // Output timestamp precision
+            bitrate,
+            codec: VpxVideoCodecId::VP9,
+            num_threads: (num_cpus::get() / 2) as _,
+            i444: choice.i444,
+        }),
+    }
+}
+
 fn get_recorder(
     width: usize,
     height: usize,
     codec_name: &Option<String>,
+    use_av1: bool,
 ) -> Arc<Mutex<Option<Recorder>>> {
     #[cfg(not(target_os = "ios"))]
     let recorder = if !Config::get_option("allow-auto-record-incoming").is_empty() {
@@ -742,6 +1537,7 @@ fn get_recorder(
                     H265
                 }
             }
+            None if use_av1 => AV1,
             None => VP9,
         };
         Recorder::new(RecorderContext {
@@ -764,6 +1560,26 @@ fn get_recorder(
     recorder
 }
 
+/// Log a message of the connection `conn_id` next to the current recording, if any.
+pub fn record_event(conn_id: i32, outgoing: bool, msg: &Message) {
+    let recorder = CURRENT_RECORDER.lock().unwrap().upgrade();
+    if let Some(recorder) = recorder {
+        if let Some(r) = recorder.lock().unwrap().as_mut() {
+            allow_err!(r.write_event(conn_id, outgoing, msg));
+        }
+    }
+}
+
+/// Mux an audio frame into the current recording, if any.
+pub fn record_audio(msg: &Message) {
+    let recorder = CURRENT_RECORDER.lock().unwrap().upgrade();
+    if let Some(recorder) = recorder {
+        if let Some(r) = recorder.lock().unwrap().as_mut() {
+            r.write_message(msg);
+        }
+    }
+}
+
 fn check_privacy_mode_changed(sp: &GenericService, privacy_mode_id: i32) -> ResultType<()> {
     let privacy_mode_id_2 = *PRIVACY_MODE_CONN_ID.lock().unwrap();
     if privacy_mode_id != privacy_mode_id_2 {
@@ -797,6 +1613,7 @@ fn handle_one_frame(
     sp: &GenericService,
     frame: &[u8],
     ms: i64,
+    display: usize,
     encoder: &mut Encoder,
     recorder: Arc<Mutex<Option<Recorder>>>,
 ) -> ResultType<HashSet<i32>> {
@@ -809,13 +1626,21 @@ fn handle_one_frame(
     })?;
 
     let mut send_conn_ids: HashSet<i32> = Default::default();
-    if let Ok(msg) = encoder.encode_to_message(frame, ms) {
+    if let Ok(mut msg) = encoder.encode_to_message(frame, ms) {
+        if let Some(message::Union::VideoFrame(vf)) = &mut msg.union {
+            vf.display = display as _;
+        }
         #[cfg(not(target_os = "ios"))]
-        recorder
-            .lock()
-            .unwrap()
-            .as_mut()
-            .map(|r| r.write_message(&msg));
+        {
+            let split = recorder.lock().unwrap().as_mut().map_or(false, |r| {
+                r.write_message(&msg);
+                r.split_pending()
+            });
+            if split {
+                // the recorder starts the next segment with it
+                encoder.request_keyframe();
+            }
+        }
         send_conn_ids = sp.send_video_frame(msg);
     }
     Ok(send_conn_ids)
@@ -1032,3 +1857,25 @@ fn start_uac_elevation_check() {
         }
     });
 }
+
+#[cfg(all(test, any(windows, target_os = "linux")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_to_region() {
+        // announced at (100, 50) with 200 x 100, then moved to (300, 80)
+        let mut r = SharedRegion {
+            announced: (100, 50),
+            current: (100, 50, 200, 100),
+        };
+        assert_eq!(r.translate(150, 60), (150, 60));
+        r.current = (300, 80, 200, 100);
+        assert_eq!(r.translate(100, 50), (300, 80));
+        assert_eq!(r.translate(299, 149), (499, 179));
+        // clamped to the region
+        assert_eq!(r.translate(0, 0), (300, 80));
+        assert_eq!(r.translate(1000, 1000), (499, 179));
+        assert_eq!(r.translate(-50, 100), (300, 130));
+    }
+}
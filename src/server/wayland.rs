@@ -278,6 +278,7 @@ pub(super) fn get_capturer() -> ResultType<super::video_service::CapturerInfo> {
                 privacy_mode_id: 0,
                 _capturer_privacy_mode_id: 0,
                 capturer: Box::new(cap_display_info.capturer.clone()),
+                region: None,
             })
         }
     } else {
@@ -119,6 +119,22 @@ impl SciterConnectionManager {
         crate::ui_cm_interface::close(id);
     }
 
+    fn get_windows(&self) -> String {
+        crate::ui_cm_interface::get_windows()
+    }
+
+    fn share_window(&self, id: i32, window: f64) {
+        crate::ui_cm_interface::share_window(id, window as _);
+    }
+
+    fn share_rect(&self, id: i32, x: i32, y: i32, width: i32, height: i32) {
+        crate::ui_cm_interface::share_rect(id, x, y, width, height);
+    }
+
+    fn share_display(&self, id: i32) {
+        crate::ui_cm_interface::share_display(id);
+    }
+
     fn remove_disconnected_connection(&self, id: i32) {
         crate::ui_cm_interface::remove(id);
     }
@@ -167,6 +183,10 @@ impl sciter::EventHandler for SciterConnectionManager {
         fn quit();
         fn authorize(i32);
         fn switch_permission(i32, String, bool);
+        fn get_windows();
+        fn share_window(i32, f64);
+        fn share_rect(i32, i32, i32, i32, i32);
+        fn share_display(i32);
         fn send_msg(i32, String);
         fn can_elevate();
         fn elevate_portable(i32);
@@ -28,6 +28,8 @@ type Video = AssetPtr<video_destination>;
 
 lazy_static::lazy_static! {
     static ref VIDEO: Arc<Mutex<Option<Video>>> = Default::default();
+    // the videos of the displays tiled below the current one, and their sizes
+    static ref DISPLAY_VIDEOS: Arc<Mutex<HashMap<usize, (Video, usize)>>> = Default::default();
 }
 
 /// SciterHandler
@@ -128,7 +130,11 @@ impl InvokeUiSession for SciterHandler {
                 status.target_bitrate.map_or(Value::null(), |it| it.into()),
                 status
                     .codec_format
-                    .map_or(Value::null(), |it| it.to_string().into())
+                    .map_or(Value::null(), |it| it.to_string().into()),
+                status.send_rate.map_or(Value::null(), |it| it.into()),
+                status.backlog.map_or(Value::null(), |it| it.into()),
+                status.jitter.map_or(Value::null(), |it| it.into()),
+                status.congestion.map_or(Value::null(), |it| it.into())
             ),
         );
     }
@@ -223,6 +229,14 @@ impl InvokeUiSession for SciterHandler {
             .map(|v| v.render_frame(data).ok());
     }
 
+    fn on_display_rgba(&self, display: usize, data: &mut Vec<u8>) {
+        if let Some((v, size)) = DISPLAY_VIDEOS.lock().unwrap().get_mut(&display) {
+            if data.len() == *size {
+                v.render_frame(data).ok();
+            }
+        }
+    }
+
     fn set_peer_info(&self, pi: &PeerInfo) {
         let mut pi_sciter = Value::map();
         pi_sciter.set_item("username", pi.username.clone());
@@ -295,6 +309,8 @@ impl InvokeUiSession for SciterHandler {
         self.call("onVoiceCallIncoming", &make_args!());
     }
 
+    fn set_audio_sources(&self, _sources: &AudioSources) {}
+
     /// RGBA is directly rendered by [on_rgba]. No need to store the rgba for the sciter ui.
     fn get_rgba(&self) -> *const u8 {
         std::ptr::null()
@@ -355,7 +371,18 @@ impl sciter::EventHandler for SciterSession {
                     if ptr.is_null() {
                         return true;
                     }
-                    let site = AssetPtr::adopt(ptr as *mut video_destination);
+                    let mut site = AssetPtr::adopt(ptr as *mut video_destination);
+                    let attr = |name| source.get_attribute(name).and_then(|v| v.parse().ok());
+                    let (display, w, h) = (attr("display"), attr("w"), attr("h"));
+                    if let (Some(display), Some(w), Some(h)) = (display, w, h) {
+                        let ok = site.start_streaming((w as _, h as _), COLOR_SPACE::Rgb32, None);
+                        log::debug!("[video] display {} started: {:?}", display, ok);
+                        DISPLAY_VIDEOS
+                            .lock()
+                            .unwrap()
+                            .insert(display, (site, w * h * 4));
+                        return true;
+                    }
                     log::debug!("[video] start video");
                     *VIDEO.lock().unwrap() = Some(site);
                     self.reconnect(false);
@@ -399,6 +426,7 @@ impl sciter::EventHandler for SciterSession {
         fn is_port_forward();
         fn is_rdp();
         fn login(String, bool);
+        fn send2fa(String);
         fn new_rdp();
         fn send_mouse(i32, i32, i32, bool, bool, bool, bool);
         fn enter();
@@ -445,6 +473,7 @@ impl sciter::EventHandler for SciterSession {
         fn record_screen(bool, i32, i32);
         fn get_toggle_option(String);
         fn is_privacy_mode_supported();
+        fn is_virtual_microphone_supported();
         fn toggle_option(String);
         fn get_remember();
         fn peer_platform();
@@ -453,6 +482,10 @@ impl sciter::EventHandler for SciterSession {
         fn save_keyboard_mode(String);
         fn has_hwcodec();
         fn supported_hwcodec();
+        fn capture_displays(Value, Value);
+        fn supported_av1();
+        fn supported_i444();
+        fn get_metrics();
         fn change_prefer_codec();
         fn restart_remote_device();
         fn request_voice_call();
@@ -524,6 +557,29 @@ impl SciterSession {
         v
     }
 
+    fn capture_displays(&self, add: Value, sub: Value) {
+        let to_vec = |v: Value| v.values().filter_map(|d| d.to_int()).collect::<Vec<i32>>();
+        let (add, sub) = (to_vec(add), to_vec(sub));
+        let mut videos = DISPLAY_VIDEOS.lock().unwrap();
+        for d in sub.iter() {
+            videos.remove(&(*d as usize));
+        }
+        drop(videos);
+        self.0.capture_displays(add, sub);
+    }
+
+    fn supported_av1(&self) -> bool {
+        self.0.supported_av1()
+    }
+
+    fn supported_i444(&self) -> bool {
+        self.0.supported_i444()
+    }
+
+    fn get_metrics(&self) -> String {
+        self.0.get_metrics()
+    }
+
     fn save_size(&mut self, x: i32, y: i32, w: i32, h: i32) {
         let size = (x, y, w, h);
         let mut config = self.load_config();
@@ -66,6 +66,8 @@ struct IpcTaskRunner<T: InvokeUiCM> {
     conn_id: i32,
     #[cfg(windows)]
     file_transfer_enabled: bool,
+    #[cfg(target_os = "linux")]
+    microphone: Option<crate::audio_service::mic::VirtualMic>,
 }
 
 lazy_static::lazy_static! {
@@ -260,6 +262,45 @@ pub fn switch_permission(id: i32, name: String, enabled: bool) {
     };
 }
 
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn get_windows() -> String {
+    let windows: Vec<_> = crate::platform::get_windows()
+        .into_iter()
+        .map(|(id, title)| serde_json::json!({ "id": id, "title": title }))
+        .collect();
+    serde_json::to_string(&windows).unwrap_or("".into())
+}
+
+#[inline]
+fn share_target(id: i32, target: Option<ipc::ShareTarget>) {
+    if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+        allow_err!(client.tx.send(Data::ShareTarget(target)));
+    };
+}
+
+#[inline]
+pub fn share_window(id: i32, window: u64) {
+    share_target(id, Some(ipc::ShareTarget::Window(window)));
+}
+
+#[inline]
+pub fn share_rect(id: i32, x: i32, y: i32, width: i32, height: i32) {
+    share_target(
+        id,
+        Some(ipc::ShareTarget::Rect {
+            x,
+            y,
+            width,
+            height,
+        }),
+    );
+}
+
+#[inline]
+pub fn share_display(id: i32) {
+    share_target(id, None);
+}
+
 #[cfg(any(target_os = "android", target_os = "ios", feature = "flutter"))]
 #[inline]
 pub fn get_clients_state() -> String {
@@ -385,9 +426,9 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                     self.cm.new_message(self.conn_id, text);
                                 }
                                 Data::FS(mut fs) => {
-                                    if let ipc::FS::WriteBlock { id, file_num, data: _, compressed } = fs {
+                                    if let ipc::FS::WriteBlock { id, file_num, data: _, compressed, blk_id, unchanged } = fs {
                                         if let Ok(bytes) = self.stream.next_raw().await {
-                                            fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed};
+                                            fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed, blk_id, unchanged};
                                             handle_fs(fs, &mut write_jobs, &self.tx).await;
                                         }
                                     } else {
@@ -428,6 +469,46 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                 Data::CloseVoiceCall(reason) => {
                                     self.cm.voice_call_closed(self.conn_id, reason.as_str());
                                 }
+                                Data::MicrophoneFormat((_sample_rate, _channels)) => {
+                                    #[cfg(target_os = "linux")]
+                                    if !crate::audio_service::mic::is_supported_format(
+                                        _sample_rate,
+                                        _channels,
+                                    ) {
+                                        log::warn!(
+                                            "Unsupported microphone format: {} Hz, {} channels",
+                                            _sample_rate,
+                                            _channels
+                                        );
+                                        self.microphone = None;
+                                    } else {
+                                        let conn_id = self.conn_id;
+                                        self.microphone
+                                            .get_or_insert_with(|| {
+                                                crate::audio_service::mic::VirtualMic::new(conn_id)
+                                            })
+                                            .set_format(_sample_rate, _channels);
+                                    }
+                                }
+                                Data::MicrophoneFrame(_frame) => {
+                                    #[cfg(target_os = "linux")]
+                                    if let Some(mic) = self.microphone.as_ref() {
+                                        mic.push(_frame);
+                                    }
+                                }
+                                Data::AudioSources(None) => {
+                                    // listed in the session of the user, like the recording
+                                    #[cfg(target_os = "linux")]
+                                    allow_err!(self.tx.send(Data::AudioSources(Some(
+                                        crate::platform::linux::get_audio_sources()
+                                    ))));
+                                }
+                                Data::CloseMicrophone => {
+                                    #[cfg(target_os = "linux")]
+                                    {
+                                        self.microphone = None;
+                                    }
+                                }
                                 _ => {
 
                                 }
@@ -468,6 +549,8 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
             conn_id: 0,
             #[cfg(windows)]
             file_transfer_enabled: false,
+            #[cfg(target_os = "linux")]
+            microphone: None,
         };
 
         while task_runner.running {
@@ -656,6 +739,8 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
             file_num,
             data,
             compressed,
+            blk_id,
+            unchanged,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
                 if let Err(err) = job
@@ -664,6 +749,8 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
                         file_num,
                         data,
                         compressed,
+                        blk_id,
+                        unchanged,
                         ..Default::default()
                     })
                     .await
@@ -706,6 +793,9 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
                                 DigestCheckResult::NeedConfirm(mut digest) => {
                                     // upload to server, but server has the same file, request
                                     digest.is_upload = is_upload;
+                                    // let the client send only the changed blocks if overwritten
+                                    digest.block_hashes =
+                                        fs::get_block_hashes(&path).await.unwrap_or_default();
                                     let mut msg_out = Message::new();
                                     let mut fr = FileResponse::new();
                                     fr.set_digest(digest);
@@ -713,6 +803,9 @@ async fn handle_fs(fs: ipc::FS, write_jobs: &mut Vec<fs::TransferJob>, tx: &Unbo
                                     send_raw(msg_out, &tx);
                                 }
                                 DigestCheckResult::NoSuchFile => {
+                                    let req =
+                                        fs::new_resume_confirm(id, file_num, &path, file_size)
+                                            .await;
                                     let msg_out = new_send_confirm(req);
                                     send_raw(msg_out, &tx);
                                 }
@@ -451,6 +451,27 @@ pub fn set_permanent_password(password: String) {
     allow_err!(ipc::set_permanent_password(password));
 }
 
+/// The otpauth uri of a new secret of 2FA, enabled by `verify2fa`.
+pub fn generate2fa() -> String {
+    match crate::auth_2fa::generate() {
+        Ok(uri) => uri,
+        Err(err) => {
+            log::error!("Failed to generate 2FA: {}", err);
+            "".to_owned()
+        }
+    }
+}
+
+pub fn verify2fa(code: String) -> bool {
+    match crate::auth_2fa::confirm(&code) {
+        Some(v) => {
+            set_option(crate::auth_2fa::OPTION.to_owned(), v);
+            true
+        }
+        None => false,
+    }
+}
+
 #[inline]
 pub fn get_peer(id: String) -> PeerConfig {
     PeerConfig::load(&id)
@@ -92,6 +92,17 @@ impl<T: InvokeUiSession> Session<T> {
         self.lc.read().unwrap().conn_type.eq(&ConnType::RDP)
     }
 
+    pub fn metrics_key(&self) -> String {
+        let conn_type = format!("{:?}", self.lc.read().unwrap().conn_type);
+        crate::metrics::client_key(&self.id, &conn_type.to_lowercase())
+    }
+
+    pub fn get_metrics(&self) -> String {
+        crate::metrics::get(&self.metrics_key())
+            .map(|m| serde_json::to_string(&m).unwrap_or_default())
+            .unwrap_or_default()
+    }
+
     pub fn set_connection_info(&mut self, direct: bool, received: bool) {
         let mut lc = self.lc.write().unwrap();
         lc.direct = Some(direct);
@@ -150,6 +161,14 @@ impl<T: InvokeUiSession> Session<T> {
         if let Some(msg) = msg {
             self.send(Data::Message(msg));
         }
+        if name == "i444" {
+            // sent with the decoder state
+            self.change_prefer_codec();
+        }
+        if name == "forward-microphone" {
+            let on = self.get_toggle_option(name);
+            self.send(Data::ForwardMicrophone(on));
+        }
     }
 
     pub fn get_toggle_option(&self, name: String) -> bool {
@@ -160,6 +179,10 @@ impl<T: InvokeUiSession> Session<T> {
         self.lc.read().unwrap().is_privacy_mode_supported()
     }
 
+    pub fn is_virtual_microphone_supported(&self) -> bool {
+        self.lc.read().unwrap().is_virtual_microphone_supported()
+    }
+
     pub fn is_text_clipboard_required(&self) -> bool {
         *self.server_clipboard_enabled.read().unwrap()
             && *self.server_keyboard_enabled.read().unwrap()
@@ -237,6 +260,15 @@ impl<T: InvokeUiSession> Session<T> {
         (false, false)
     }
 
+    pub fn supported_av1(&self) -> bool {
+        scrap::codec::Decoder::video_codec_state(&self.id).score_av1 > 0
+            && self.lc.read().unwrap().supported_av1
+    }
+
+    pub fn supported_i444(&self) -> bool {
+        self.lc.read().unwrap().supported_i444
+    }
+
     pub fn change_prefer_codec(&self) {
         let msg = self.lc.write().unwrap().change_prefer_codec();
         self.send(Data::Message(msg));
@@ -481,6 +513,11 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    /// Stream the displays `add` besides the current one, and stop streaming `sub`.
+    pub fn capture_displays(&self, add: Vec<i32>, sub: Vec<i32>) {
+        self.send(Data::CaptureDisplays(add, sub));
+    }
+
     pub fn enter(&self) {
         #[cfg(target_os = "windows")]
         {
@@ -716,6 +753,10 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Login((password, remember)));
     }
 
+    pub fn send2fa(&self, code: String) {
+        self.send(Data::Message(crate::client::auth_2fa_msg(code)));
+    }
+
     pub fn new_rdp(&self) {
         self.send(Data::NewRDP);
     }
@@ -804,6 +845,23 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg));
     }
 
+    /// The sources are returned by [`InvokeUiSession::set_audio_sources`].
+    pub fn get_audio_sources(&self) {
+        let mut misc = Misc::new();
+        misc.set_get_audio_sources(true);
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        self.send(Data::Message(msg));
+    }
+
+    pub fn select_audio_source(&self, name: String) {
+        let mut misc = Misc::new();
+        misc.set_select_audio_source(name);
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        self.send(Data::Message(msg));
+    }
+
     pub fn request_voice_call(&self) {
         self.send(Data::NewVoiceCall);
     }
@@ -910,6 +968,7 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn job_progress(&self, id: i32, file_num: i32, speed: f64, finished_size: f64);
     fn adapt_size(&self);
     fn on_rgba(&self, data: &mut Vec<u8>);
+    fn on_display_rgba(&self, display: usize, data: &mut Vec<u8>);
     fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str, retry: bool);
     #[cfg(any(target_os = "android", target_os = "ios"))]
     fn clipboard(&self, content: String);
@@ -920,6 +979,7 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn on_voice_call_closed(&self, reason: &str);
     fn on_voice_call_waiting(&self);
     fn on_voice_call_incoming(&self);
+    fn set_audio_sources(&self, sources: &AudioSources);
     fn get_rgba(&self) -> *const u8;
     fn next_rgba(&self);
 }
@@ -1036,9 +1096,21 @@ impl<T: InvokeUiSession> Interface for Session<T> {
 
     async fn handle_test_delay(&mut self, t: TestDelay, peer: &mut Stream) {
         if !t.from_client {
+            crate::metrics::update(&self.metrics_key(), |m| {
+                m.delay = t.last_delay;
+                m.target_bitrate = t.target_bitrate;
+                m.send_rate = t.send_rate;
+            });
             self.update_quality_status(QualityStatus {
                 delay: Some(t.last_delay as _),
                 target_bitrate: Some(t.target_bitrate as _),
+                send_rate: Some(t.send_rate as _),
+                backlog: Some(t.backlog as _),
+                jitter: Some(t.arrival_jitter.max(t.decode_jitter) as _),
+                congestion: Some(
+                    crate::client::helper::congestion_name(t.congestion.enum_value_or_default())
+                        .to_owned(),
+                ),
                 ..Default::default()
             });
             handle_test_delay(t, peer).await;
@@ -1177,11 +1249,21 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>) {
     }
     let frame_count = Arc::new(AtomicUsize::new(0));
     let frame_count_cl = frame_count.clone();
+    let decode_jitter = Arc::new(AtomicUsize::new(0));
+    let metrics_key = handler.metrics_key();
     let ui_handler = handler.ui_handler.clone();
-    let (video_sender, audio_sender) = start_video_audio_threads(move |data: &mut Vec<u8>| {
-        frame_count_cl.fetch_add(1, Ordering::Relaxed);
-        ui_handler.on_rgba(data);
-    });
+    let (video_sender, audio_sender) = start_video_audio_threads(
+        move |display: Option<usize>, data: &mut Vec<u8>| {
+            if let Some(display) = display {
+                ui_handler.on_display_rgba(display, data);
+                return;
+            }
+            frame_count_cl.fetch_add(1, Ordering::Relaxed);
+            ui_handler.on_rgba(data);
+        },
+        decode_jitter.clone(),
+        metrics_key,
+    );
 
     let mut remote = Remote::new(
         handler,
@@ -1190,6 +1272,7 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>) {
         receiver,
         sender,
         frame_count,
+        decode_jitter,
     );
     remote.io_loop(&key, &token).await;
     remote.sync_jobs_status_to_local().await;